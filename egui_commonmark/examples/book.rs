@@ -54,6 +54,10 @@ impl App {
                         .default_width(Some(200))
                         .max_image_width(Some(512))
                         .enable_scroll_to_heading(true)
+                        .enable_heading_slugs(true)
+                        .enable_superscript_and_subscript(true)
+                        .code_block_header(true)
+                        .render_csv(true)
                         .show(
                             ui,
                             &mut self.cache,
@@ -18,6 +18,7 @@ impl eframe::App for App {
             egui::ScrollArea::vertical().show(ui, |ui| {
                 CommonMarkViewer::new()
                     .max_image_width(Some(512))
+                    .enable_superscript_and_subscript(true)
                     .show(ui, &mut self.cache, text);
             });
         });
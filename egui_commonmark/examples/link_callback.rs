@@ -0,0 +1,95 @@
+//! Add `light` or `dark` to the end of the command to specify theme. Default
+//! is system theme. `cargo r --example link_callback -- dark`
+
+use std::cell::Cell;
+
+use eframe::egui;
+use egui_commonmark::*;
+
+const PAGES: [(&str, &str); 2] = [
+    (
+        "intro.md",
+        r#"# Introduction
+Continue to the [next page](guide.md) or open the [settings](app://settings).
+
+Links that the application does not handle are opened as usual: [egui](https://www.egui.rs)
+"#,
+    ),
+    (
+        "guide.md",
+        r#"# Guide
+Go back to the [introduction](intro.md).
+"#,
+    ),
+];
+
+struct App {
+    cache: CommonMarkCache,
+    curr_page: usize,
+    show_settings: bool,
+}
+
+impl eframe::App for App {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        // The callback is only borrowed by the viewer so it can't mutate the app
+        // directly
+        let next_page = Cell::new(None);
+        let show_settings = Cell::new(false);
+
+        let on_link_click = |link: LinkContext| {
+            if let Some(page) = PAGES.iter().position(|(name, _)| *name == link.destination) {
+                next_page.set(Some(page));
+                LinkAction::Ignore
+            } else if link.destination == "app://settings" {
+                show_settings.set(true);
+                LinkAction::Ignore
+            } else {
+                LinkAction::Default
+            }
+        };
+
+        egui::CentralPanel::default().show(ui, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                CommonMarkViewer::new()
+                    .on_link_click(Some(&on_link_click))
+                    .show(ui, &mut self.cache, PAGES[self.curr_page].1);
+            });
+        });
+
+        if let Some(page) = next_page.get() {
+            self.curr_page = page;
+        }
+        self.show_settings |= show_settings.get();
+
+        egui::Window::new("Settings")
+            .open(&mut self.show_settings)
+            .show(ui.ctx(), |ui| {
+                ui.label("Opened from a link");
+            });
+    }
+}
+
+fn main() -> eframe::Result {
+    let mut args = std::env::args();
+    args.next();
+
+    eframe::run_native(
+        "Markdown viewer link callback",
+        eframe::NativeOptions::default(),
+        Box::new(move |cc| {
+            if let Some(theme) = args.next() {
+                if theme == "light" {
+                    cc.egui_ctx.set_theme(egui::Theme::Light);
+                } else if theme == "dark" {
+                    cc.egui_ctx.set_theme(egui::Theme::Dark);
+                }
+            }
+
+            Ok(Box::new(App {
+                cache: CommonMarkCache::default(),
+                curr_page: 0,
+                show_settings: false,
+            }))
+        }),
+    )
+}
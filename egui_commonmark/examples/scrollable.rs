@@ -0,0 +1,82 @@
+//! Make sure to run this example from the repo directory and not the example
+//! directory. To see all the features in full effect, run this example with
+//! `cargo r --features better_syntax_highlighting,svg,fetch`
+//! Add `light` or `dark` to the end of the command to specify theme. Default
+//! is system theme. `cargo r --features better_syntax_highlighting,svg,fetch -- dark`
+
+use eframe::egui;
+use egui_commonmark::*;
+
+struct App {
+    cache: CommonMarkCache,
+    text: String,
+}
+
+impl eframe::App for App {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        egui::Panel::left("outline_panel").show(ui, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                CommonMarkViewer::new()
+                    .enable_scroll_to_heading(true)
+                    .enable_heading_slugs(true)
+                    .show_outline(ui, &mut self.cache, &self.text);
+            });
+        });
+
+        egui::CentralPanel::default().show(ui, |ui| {
+            CommonMarkViewer::new()
+                .max_image_width(Some(512))
+                .enable_scroll_to_heading(true)
+                .enable_heading_slugs(true)
+                .render_csv(true)
+                .show_scrollable("viewer", ui, &mut self.cache, &self.text);
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    let mut args = std::env::args();
+    args.next();
+
+    // Repeat the example documents to get something long enough to make only
+    // rendering the visible parts worthwhile
+    let text = [
+        include_str!("markdown/hello_world.md"),
+        include_str!("markdown/headers.md"),
+        include_str!("markdown/lists.md"),
+        include_str!("markdown/tables.md"),
+        include_str!("markdown/code-blocks.md"),
+        include_str!("markdown/blockquotes.md"),
+    ]
+    .join("\n\n")
+    .repeat(20);
+
+    let text = format!(
+        "{}\n\n{text}",
+        include_str!("markdown/scroll_to_heading.md")
+    );
+
+    eframe::run_native(
+        "Scrollable markdown viewer",
+        eframe::NativeOptions::default(),
+        Box::new(move |cc| {
+            if let Some(theme) = args.next() {
+                if theme == "light" {
+                    cc.egui_ctx.set_theme(egui::Theme::Light);
+                } else if theme == "dark" {
+                    cc.egui_ctx.set_theme(egui::Theme::Dark);
+                }
+            }
+
+            cc.egui_ctx.global_style_mut(|style| {
+                // Show the url of a hyperlink on hover
+                style.url_in_tooltip = true;
+            });
+
+            Ok(Box::new(App {
+                cache: CommonMarkCache::default(),
+                text,
+            }))
+        }),
+    )
+}
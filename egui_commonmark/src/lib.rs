@@ -76,14 +76,25 @@ use egui::{self, Id};
 
 mod parsers;
 
+pub use egui_commonmark_backend::LinkClickFn;
+pub use egui_commonmark_backend::RenderCodeBlockFn;
 pub use egui_commonmark_backend::RenderHtmlFn;
 pub use egui_commonmark_backend::RenderMathFn;
 pub use egui_commonmark_backend::alerts::{Alert, AlertBundle};
+pub use egui_commonmark_backend::document;
+pub use egui_commonmark_backend::highlighting;
 pub use egui_commonmark_backend::misc::CommonMarkCache;
+pub use egui_commonmark_backend::misc::{LinkAction, LinkContext};
+pub use egui_commonmark_backend::theme::{CommonMarkTheme, HeadingTheme};
 
+#[cfg(feature = "better_syntax_highlighting")]
+pub use egui_commonmark_backend::misc::CommonMarkCacheBuilder;
 #[cfg(feature = "better_syntax_highlighting")]
 pub use egui_commonmark_backend::syntect;
 
+#[cfg(feature = "tree_sitter")]
+pub use egui_commonmark_backend::tree_sitter_highlight;
+
 #[cfg(feature = "macros")]
 pub use egui_commonmark_macros::*;
 
@@ -125,6 +136,36 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Code blocks taller than this are shown in a scroll area with a button to expand
+    /// them. By default code blocks are never limited in height
+    pub fn code_block_max_height(mut self, height: Option<f32>) -> Self {
+        self.options.code_block_max_height = height;
+        self
+    }
+
+    /// Whether long lines in code blocks are wrapped. When disabled the code block can be
+    /// scrolled horizontally instead, which keeps ascii diagrams and aligned output intact.
+    /// Every code block with long lines has a button to toggle this. By default this is enabled.
+    pub fn code_block_wrap(mut self, wrap: bool) -> Self {
+        self.options.code_block_wrap = wrap;
+        self
+    }
+
+    /// Show a header above code blocks with the name of the language. With the
+    /// `better_syntax_highlighting` feature the name is looked up in the syntax set, so
+    /// ```` ```sh ```` is shown as `Bourne Again Shell (bash)`. By default this is disabled.
+    pub fn code_block_header(mut self, enable: bool) -> Self {
+        self.options.code_block_header = enable;
+        self
+    }
+
+    /// Highlight code blocks with a custom [`highlighting::Highlighter`]. Languages it
+    /// returns `None` for are highlighted as usual.
+    pub fn highlighter(mut self, highlighter: Option<&'f dyn highlighting::Highlighter>) -> Self {
+        self.options.highlighter = highlighter;
+        self
+    }
+
     /// Show alt text when hovering over images. By default this is enabled.
     pub fn show_alt_text_on_hover(mut self, show: bool) -> Self {
         self.options.show_alt_text_on_hover = show;
@@ -144,6 +185,24 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Resolve relative image and link destinations against this uri as described
+    /// in RFC 3986, typically the location the markdown was loaded from. Destinations
+    /// that are only a fragment, `#heading`, still scroll to the heading if enabled.
+    ///
+    /// The base must be a uri, Windows paths such as `C:\docs\intro.md` are ignored.
+    /// Use `file:///C:/docs/intro.md` instead.
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// // `../img/a.png` is shown from `file:///docs/img/a.png`
+    /// CommonMarkViewer::new().base_uri("file:///docs/guide/intro.md");
+    /// ```
+    pub fn base_uri<S: Into<String>>(mut self, base: S) -> Self {
+        self.options.base_uri = Some(base.into());
+        self
+    }
+
     /// By default any image without a uri scheme such as `foo://` is assumed to
     /// be of the type `file://`. This assumption can sometimes be wrong or be done
     /// incorrectly, so if you want to always be explicit with the scheme then set
@@ -225,6 +284,82 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Render a safe subset of html, such as `<kbd>`, `<sub>`, `<sup>`, `<br>`,
+    /// `<img width="..">`, `<a href="..">`, `<details>` and `<p align="center">`, instead
+    /// of showing the tags as text. Html blocks are given to the
+    /// [`render_html_fn`](Self::render_html_fn) instead if it is set. The macros always
+    /// show html as text. By default this is disabled.
+    pub fn render_html(mut self, enable: bool) -> Self {
+        self.options.render_html = enable;
+        self
+    }
+
+    /// Hide html elements that are not rendered by [`render_html`](Self::render_html)
+    /// instead of showing their tags as text. Their content is still shown. By default
+    /// this is disabled.
+    pub fn strip_unknown_html(mut self, strip: bool) -> Self {
+        self.options.strip_unknown_html = strip;
+        self
+    }
+
+    /// Render fenced code blocks of the given language with `func` instead of showing
+    /// the code. The language is the first word of the info string, so
+    /// ```` ```csv ```` and ```` ```csv header ```` both use the renderer registered for
+    /// `csv`. Can be called multiple times to register several languages.
+    ///
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// let render_plot = |ui: &mut egui::Ui, code: &str| {
+    ///     let points = code.lines().filter_map(|line| line.trim().parse::<f32>().ok());
+    ///     ui.label(format!("A plot of {} points", points.count()));
+    /// };
+    /// CommonMarkViewer::new().render_code_block_fn("plot", &render_plot);
+    /// ```
+    pub fn render_code_block_fn<S: Into<String>>(
+        mut self,
+        lang: S,
+        func: &'f RenderCodeBlockFn,
+    ) -> Self {
+        self.options.code_block_fns.insert(lang.into(), func);
+        self
+    }
+
+    /// Show ```` ```csv ```` and ```` ```tsv ```` code blocks as tables. By default this is
+    /// disabled. Whether the first row is a header is guessed, add `header` or `noheader`
+    /// after the language to decide it explicitly. A renderer registered with
+    /// [`Self::render_code_block_fn`] takes precedence.
+    pub fn render_csv(mut self, enable: bool) -> Self {
+        self.options.render_csv = enable;
+        self
+    }
+
+    /// Decide what happens when a link is clicked, e.g. to navigate within the
+    /// application instead of opening the url. Links registered with
+    /// [`CommonMarkCache::add_link_hook`] are not passed to the callback.
+    ///
+    /// ```
+    /// # use egui_commonmark::*;
+    /// let on_click = |link: LinkContext| {
+    ///     if link.destination.starts_with("app://") {
+    ///         // Navigate within the application
+    ///         LinkAction::Ignore
+    ///     } else {
+    ///         LinkAction::Default
+    ///     }
+    /// };
+    ///
+    /// # egui::__run_test_ui(|ui| {
+    /// let mut cache = CommonMarkCache::default();
+    /// CommonMarkViewer::new()
+    ///     .on_link_click(Some(&on_click))
+    ///     .show(ui, &mut cache, "[settings](app://settings)");
+    /// # });
+    /// ```
+    pub fn on_link_click(mut self, func: Option<&'f LinkClickFn<'f>>) -> Self {
+        self.options.on_link_click = func;
+        self
+    }
+
     /// Enable scrolling to headings by their ID.
     /// To give a heading an ID, use the syntax `# Heading {#myheadingid}`. Then links to `#myheadingid` e.g. `[click me!](#myheadingid)` will scroll to that heading.
     pub fn enable_scroll_to_heading(mut self, enable: bool) -> Self {
@@ -232,7 +367,49 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Enable `^superscript^` and `~subscript~`. Note that single tildes will no longer
+    /// produce strikethrough when this is enabled, use `~~strikethrough~~` instead.
+    pub fn enable_superscript_and_subscript(mut self, enable: bool) -> Self {
+        self.options.enable_superscript_and_subscript = enable;
+        self
+    }
+
+    /// Give headings without an explicit id a GitHub style slug as id, e.g.
+    /// `## Installation Guide` becomes `installation-guide`. Links to the slugs, such as
+    /// `[install](#installation-guide)`, will scroll to the heading like they do on GitHub.
+    /// Duplicate slugs are suffixed with `-1`, `-2` and so on.
+    pub fn enable_heading_slugs(mut self, enable: bool) -> Self {
+        self.options.enable_heading_slugs = enable;
+        self
+    }
+
+    /// Show all footnote definitions at the end of the document below a rule, like GitHub
+    /// does, instead of where they are written. Footnotes are numbered by the order in
+    /// which they are first referenced rather than by their label. By default this is
+    /// disabled.
+    pub fn gather_footnotes(mut self, enable: bool) -> Self {
+        self.options.gather_footnotes = enable;
+        self
+    }
+
+    /// Parse front matter at the start of the markdown, either YAML between `---` lines or
+    /// TOML between `+++` lines. The front matter is not shown, get it with
+    /// [`front_matter`](Self::front_matter). By default this is disabled.
+    pub fn enable_front_matter(mut self, enable: bool) -> Self {
+        self.options.enable_front_matter = enable;
+        self
+    }
+
+    /// Set the visual styling of the markdown
+    pub fn theme(mut self, theme: CommonMarkTheme) -> Self {
+        self.options.theme = theme;
+        self
+    }
+
     /// Shows rendered markdown
+    ///
+    /// The parsed markdown is kept in the [`CommonMarkCache`] for as long as the same
+    /// text is shown every frame, so static text is only parsed once.
     pub fn show(
         self,
         ui: &mut egui::Ui,
@@ -240,13 +417,44 @@ impl<'f> CommonMarkViewer<'f> {
         text: &str,
     ) -> egui::InnerResponse<()> {
         egui_commonmark_backend::prepare_show(cache, ui.ctx());
+        let document = parsers::pulldown::parse_cached(
+            cache,
+            Some(ui.next_auto_id()),
+            text,
+            &self.options,
+            self.options.enable_scroll_to_heading,
+        );
+        let (response, _) = parsers::pulldown::CommonMarkViewerInternal::new().show(
+            ui,
+            cache,
+            &self.options,
+            &document,
+        );
+
+        response
+    }
+
+    /// Parse markdown into a [`Document`](document::Document) with the extensions
+    /// required by the options of this viewer. The document can be inspected or modified
+    /// before being shown with [`show_document`](Self::show_document).
+    pub fn parse(&self, text: &str) -> document::Document {
+        parsers::pulldown::parse(text, &self.options, self.options.enable_scroll_to_heading)
+    }
+
+    /// Shows an already parsed document. See [`parse`](Self::parse)
+    pub fn show_document(
+        self,
+        ui: &mut egui::Ui,
+        cache: &mut CommonMarkCache,
+        document: &document::Document,
+    ) -> egui::InnerResponse<()> {
+        egui_commonmark_backend::prepare_show(cache, ui.ctx());
 
         let (response, _) = parsers::pulldown::CommonMarkViewerInternal::new().show(
             ui,
             cache,
             &self.options,
-            text,
-            None,
+            document,
         );
 
         response
@@ -264,13 +472,19 @@ impl<'f> CommonMarkViewer<'f> {
         self.options.mutable = true;
         egui_commonmark_backend::prepare_show(cache, ui.ctx());
 
+        let document = parsers::pulldown::parse_cached(
+            cache,
+            Some(ui.next_auto_id()),
+            text,
+            &self.options,
+            self.options.enable_scroll_to_heading,
+        );
         let (mut inner_response, checkmark_events) =
             parsers::pulldown::CommonMarkViewerInternal::new().show(
                 ui,
                 cache,
                 &self.options,
-                text,
-                None,
+                &document,
             );
 
         // Update source text for checkmarks that were clicked
@@ -289,18 +503,15 @@ impl<'f> CommonMarkViewer<'f> {
 
     /// Shows markdown inside a [`ScrollArea`].
     /// This function is much more performant than just calling [`show`] inside a [`ScrollArea`],
-    /// because it only renders elements that are visible.
-    ///
-    /// # Caveat
+    /// because it only renders the top level blocks that are visible.
     ///
-    /// This assumes that the markdown is static. If it does change, you have to clear the cache
-    /// by using [`clear_scrollable_with_id`](CommonMarkCache::clear_scrollable_with_id) or
-    /// [`clear_scrollable`](CommonMarkCache::clear_scrollable). If the content changes every frame,
-    /// it's faster to call [`show`] directly.
+    /// The height of each block is measured when it is rendered. Blocks that have not been
+    /// rendered yet, or were last rendered at a different width, use an estimated height
+    /// until they scroll into view. If the text or options that change the layout change,
+    /// the measurements are discarded automatically.
     ///
     /// [`ScrollArea`]: egui::ScrollArea
     /// [`show`]: crate::CommonMarkViewer::show
-    #[doc(hidden)] // Buggy in scenarios more complex than the example application
     #[cfg(feature = "pulldown_cmark")]
     pub fn show_scrollable(
         self,
@@ -318,6 +529,85 @@ impl<'f> CommonMarkViewer<'f> {
             text,
         );
     }
+
+    /// The headings of the markdown as a tree. Headings without an explicit id,
+    /// `# Heading {#id}`, are given a generated one when
+    /// [`enable_scroll_to_heading`](Self::enable_scroll_to_heading) is set. See
+    /// [`enable_heading_slugs`](Self::enable_heading_slugs) for how to get readable ids.
+    pub fn outline(&self, cache: &mut CommonMarkCache, text: &str) -> Vec<document::OutlineEntry> {
+        parsers::pulldown::parse_cached(
+            cache,
+            None,
+            text,
+            &self.options,
+            self.options.enable_scroll_to_heading,
+        )
+        .outline()
+    }
+
+    /// The front matter of the markdown without showing it. Requires
+    /// [`enable_front_matter`](Self::enable_front_matter).
+    ///
+    /// ```
+    /// # use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
+    /// let text = "---\ntitle: Hello\n---\n# Hello";
+    /// let mut cache = CommonMarkCache::default();
+    /// let front_matter = CommonMarkViewer::new()
+    ///     .enable_front_matter(true)
+    ///     .front_matter(&mut cache, text);
+    ///
+    /// assert_eq!(front_matter.unwrap().content, "title: Hello\n");
+    /// ```
+    pub fn front_matter(
+        &self,
+        cache: &mut CommonMarkCache,
+        text: &str,
+    ) -> Option<document::FrontMatter> {
+        parsers::pulldown::parse_cached(
+            cache,
+            None,
+            text,
+            &self.options,
+            self.options.enable_scroll_to_heading,
+        )
+        .front_matter()
+    }
+
+    /// Shows the headings of the markdown as a tree. Clicking a heading scrolls the
+    /// viewer showing the same text with the same cache to it.
+    ///
+    /// Scrolling to headings is enabled for the outline. The viewer showing the text
+    /// must have [`enable_scroll_to_heading`](Self::enable_scroll_to_heading) set as
+    /// well, so that both use the same parsed document, and be placed in a
+    /// [`ScrollArea`](egui::ScrollArea) or be shown with
+    /// [`show_scrollable`](Self::show_scrollable).
+    pub fn show_outline(
+        self,
+        ui: &mut egui::Ui,
+        cache: &mut CommonMarkCache,
+        text: &str,
+    ) -> egui::InnerResponse<()> {
+        let outline = self.enable_scroll_to_heading(true).outline(cache, text);
+        ui.vertical(|ui| outline_ui(ui, cache, &outline))
+    }
+}
+
+fn outline_ui(ui: &mut egui::Ui, cache: &mut CommonMarkCache, entries: &[document::OutlineEntry]) {
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(id) = &entry.id {
+            if ui.link(&entry.text).clicked() {
+                *cache.scroll_to_id_target_mut() = Some(id.clone());
+            }
+        } else {
+            ui.label(&entry.text);
+        }
+
+        if !entry.children.is_empty() {
+            // Headings may have no id, so the indent is salted with the position of the
+            // heading. The id of the nested ui already includes that of its parents.
+            ui.indent(index, |ui| outline_ui(ui, cache, &entry.children));
+        }
+    }
 }
 
 pub(crate) struct ListLevel {
@@ -364,18 +654,18 @@ impl List {
             ui.label(" ".repeat((len - 1) * options.indentation_spaces));
 
             if let Some(number) = &mut item.current_number {
-                number_point(ui, &number.to_string());
+                number_point(ui, &options.theme, &number.to_string());
                 *number += 1;
             } else if len > 1 {
-                bullet_point_hollow(ui);
+                bullet_point_hollow(ui, &options.theme);
             } else {
-                bullet_point(ui);
+                bullet_point(ui, &options.theme);
             }
         } else {
             unreachable!();
         }
 
-        ui.add_space(4.0);
+        ui.add_space(options.theme.list_marker_spacing);
     }
 
     pub fn end_level(&mut self, ui: &mut egui::Ui, insert_newline: bool) {
@@ -1,89 +1,49 @@
-use std::iter::Peekable;
+use std::collections::HashMap;
 use std::ops::Range;
+use std::sync::Arc;
 
 use crate::{CommonMarkCache, CommonMarkOptions};
 
-use egui::{self, Id, Pos2, TextStyle, Ui};
+use egui::{self, Id, TextStyle, Ui};
 
 use crate::List;
+use egui_commonmark_backend::document::{
+    Block, BlockKind, DefinitionListItem, Document, Inline, InlineKind,
+};
 use egui_commonmark_backend::elements::*;
+use egui_commonmark_backend::html;
 use egui_commonmark_backend::misc::*;
 use egui_commonmark_backend::pulldown::*;
-use pulldown_cmark::{CowStr, HeadingLevel};
-
-/// Newline logic is constructed by the following:
-/// All elements try to insert a newline before them (if they are allowed)
-/// and end their own line.
-struct Newline {
-    /// Whether a newline should not be inserted before a widget. This is only for
-    /// the first widget.
-    should_not_start_newline_forced: bool,
-    /// Whether an element should insert a newline before it
-    should_start_newline: bool,
-    /// Whether an element should end it's own line using a newline
-    /// This will have to be set to false in cases such as when blocks are within
-    /// a list.
-    should_end_newline: bool,
-    /// only false when the widget is the last one.
-    should_end_newline_forced: bool,
-}
-
-impl Default for Newline {
-    fn default() -> Self {
-        Self {
-            should_not_start_newline_forced: true,
-            should_start_newline: true,
-            should_end_newline: true,
-            should_end_newline_forced: true,
-        }
-    }
-}
-
-impl Newline {
-    pub fn can_insert_end(&self) -> bool {
-        self.should_end_newline && self.should_end_newline_forced
-    }
-
-    pub fn can_insert_start(&self) -> bool {
-        self.should_start_newline && !self.should_not_start_newline_forced
-    }
-
-    pub fn try_insert_start(&self, ui: &mut Ui) {
-        if self.can_insert_start() {
-            newline(ui);
-        }
-    }
-
-    pub fn try_insert_end(&self, ui: &mut Ui) {
-        if self.can_insert_end() {
-            newline(ui);
-        }
-    }
-}
-
-#[derive(Default)]
-struct DefinitionList {
-    is_first_item: bool,
-    is_def_list_def: bool,
-}
+use pulldown_cmark::HeadingLevel;
 
-pub struct CommonMarkViewerInternal {
+pub struct CommonMarkViewerInternal<'d> {
     curr_table: usize,
     text_style: Style,
     list: List,
     link: Option<Link>,
     image: Option<Image>,
     line: Newline,
-    code_block: Option<CodeBlock>,
-
-    /// Only populated if the html_fn option has been set
-    html_block: String,
-    is_list_item: bool,
-    def_list: DefinitionList,
-    is_table: bool,
-    is_blockquote: bool,
+
+    /// The text of the `<kbd>` element that is being written
+    kbd: Option<String>,
+    /// How many `<a>` elements are open within the link that an html `<a href>` started.
+    /// Zero if the current link, if any, comes from the markdown.
+    html_link_depth: usize,
+    /// The style flags that open inline html elements set, with the value each flag had
+    /// before its element started
+    html_styles: Vec<(HtmlStyle, bool)>,
+    /// A `<details>` element opened by an html block, the markdown up until its end tag
+    /// is shown within it
+    details: Option<html::Element>,
+    /// How many sections with a summary have been shown, to give each section an id
+    details_count: HashMap<String, usize>,
+    /// The id that the ids of details sections are derived from instead of the ui, so
+    /// that they are the same no matter which part of the document is shown
+    details_id: Option<Id>,
     checkbox_events: Vec<CheckboxClickEvent>,
     deferred_scroll_to_heading: Option<String>,
+    /// The document being shown, used to look up footnote definitions for tooltips
+    document: Option<&'d Document>,
 }
 
 pub(crate) struct CheckboxClickEvent {
@@ -91,7 +51,7 @@ pub(crate) struct CheckboxClickEvent {
     pub(crate) span: Range<usize>,
 }
 
-impl CommonMarkViewerInternal {
+impl CommonMarkViewerInternal<'_> {
     pub fn new() -> Self {
         Self {
             curr_table: 0,
@@ -100,21 +60,92 @@ impl CommonMarkViewerInternal {
             link: None,
             image: None,
             line: Newline::default(),
-            is_list_item: false,
-            def_list: Default::default(),
-            code_block: None,
-            html_block: String::new(),
-            is_table: false,
-            is_blockquote: false,
+            kbd: None,
+            html_link_depth: 0,
+            html_styles: Vec::new(),
+            details: None,
+            details_count: HashMap::new(),
+            details_id: None,
             checkbox_events: Vec::new(),
             deferred_scroll_to_heading: None,
+            document: None,
+        }
+    }
+}
+
+/// An inline html element that sets a flag of the text style
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum HtmlStyle {
+    Strong,
+    Emphasis,
+    Strikethrough,
+    Code,
+    Subscript,
+    Superscript,
+}
+
+impl HtmlStyle {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "b" | "strong" => Self::Strong,
+            "i" | "em" => Self::Emphasis,
+            "s" | "del" | "strike" => Self::Strikethrough,
+            "code" => Self::Code,
+            "sub" => Self::Subscript,
+            "sup" => Self::Superscript,
+            _ => return None,
+        })
+    }
+
+    fn flag(self, style: &mut Style) -> &mut bool {
+        match self {
+            Self::Strong => &mut style.strong,
+            Self::Emphasis => &mut style.emphasis,
+            Self::Strikethrough => &mut style.strikethrough,
+            Self::Code => &mut style.code,
+            Self::Subscript => &mut style.subscript,
+            Self::Superscript => &mut style.superscript,
+        }
+    }
+}
+
+/// Whether the html has nothing to show
+fn is_blank_html<'a>(mut nodes: impl Iterator<Item = &'a html::Node>) -> bool {
+    nodes.all(|node| matches!(node, html::Node::Text(text) if text.trim().is_empty()))
+}
+
+/// Collapses whitespace like a browser does. Whitespace at the start or end of a line is
+/// removed.
+fn collapse_whitespace(text: &str, is_line_start: bool, is_line_end: bool) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for word in text.split_whitespace() {
+        if !collapsed.is_empty() {
+            collapsed.push(' ');
+        }
+        collapsed.push_str(word);
+    }
+
+    if collapsed.is_empty() {
+        if !text.is_empty() && !is_line_start && !is_line_end {
+            collapsed.push(' ');
         }
+        return collapsed;
+    }
+
+    if !is_line_start && text.starts_with(char::is_whitespace) {
+        collapsed.insert(0, ' ');
+    }
+    if !is_line_end && text.ends_with(char::is_whitespace) {
+        collapsed.push(' ');
     }
+    collapsed
 }
 
 fn parser_options_extras(
     is_math_enabled: bool,
     is_scroll_to_heading_enabled: bool,
+    is_superscript_and_subscript_enabled: bool,
+    is_front_matter_enabled: bool,
 ) -> pulldown_cmark::Options {
     let mut result = parser_options();
     if is_math_enabled {
@@ -123,20 +154,291 @@ fn parser_options_extras(
     if is_scroll_to_heading_enabled {
         result |= pulldown_cmark::Options::ENABLE_HEADING_ATTRIBUTES;
     }
+    if is_superscript_and_subscript_enabled {
+        result |=
+            pulldown_cmark::Options::ENABLE_SUPERSCRIPT | pulldown_cmark::Options::ENABLE_SUBSCRIPT;
+    }
+    if is_front_matter_enabled {
+        result |= pulldown_cmark::Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+            | pulldown_cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    }
     result
 }
 
-impl CommonMarkViewerInternal {
+/// Parse the text with all the extensions the given options require. Headings without
+/// an id are only given a generated one when `heading_ids` is set, as only scrolling to
+/// headings and the outline need them.
+pub(crate) fn parse(text: &str, options: &CommonMarkOptions, heading_ids: bool) -> Document {
+    let mut document = Document::parse(text, parser_options_for(options));
+    if options.enable_heading_slugs {
+        document.assign_heading_slugs();
+    }
+    if heading_ids {
+        document.assign_heading_ids();
+    }
+    if options.gather_footnotes {
+        document.gather_footnotes();
+    }
+    document
+}
+
+/// Parse the text or reuse the document from a previous frame if the text is unchanged.
+/// The previous document of the viewer with the given id is replaced if its text changed.
+pub(crate) fn parse_cached(
+    cache: &mut CommonMarkCache,
+    viewer: Option<Id>,
+    text: &str,
+    options: &CommonMarkOptions,
+    heading_ids: bool,
+) -> Arc<Document> {
+    let key = document_key(text, options, heading_ids);
+    cached_document(cache, key, viewer, || parse(text, options, heading_ids))
+}
+
+/// Identifies the text together with the options that change how it is parsed
+fn document_key(text: &str, options: &CommonMarkOptions, heading_ids: bool) -> u64 {
+    egui::util::hash((
+        text,
+        parser_options_for(options).bits(),
+        options.enable_heading_slugs,
+        heading_ids,
+        options.gather_footnotes,
+    ))
+}
+
+/// Identifies the document together with the options that change the height of its
+/// blocks, so that the measured heights are discarded when any of them change
+fn layout_key(text: &str, options: &CommonMarkOptions) -> u64 {
+    let mut code_block_fns: Vec<_> = options.code_block_fns.keys().collect();
+    code_block_fns.sort_unstable();
+    egui::util::hash((
+        document_key(text, options, options.enable_scroll_to_heading),
+        &options.theme,
+        options.indentation_spaces,
+        options.max_image_width,
+        options.default_width,
+        (
+            options.render_html,
+            options.strip_unknown_html,
+            options.html_fn.is_some(),
+            options.math_fn.is_some(),
+        ),
+        (
+            options.render_csv,
+            code_block_fns,
+            options.code_block_max_height.map(egui::emath::OrderedFloat),
+            options.code_block_wrap,
+            options.code_block_header,
+        ),
+    ))
+}
+
+/// The blocks that are shown. Front matter is only returned to the caller.
+fn visible_blocks(document: &Document) -> &[Block] {
+    match document.blocks.split_first() {
+        Some((
+            Block {
+                kind: BlockKind::MetadataBlock { .. },
+                ..
+            },
+            rest,
+        )) => rest,
+        _ => &document.blocks,
+    }
+}
+
+/// Whether the block is or contains a heading, footnote definition or footnote reference
+/// with the given id
+fn contains_target(block: &Block, id: &str) -> bool {
+    match &block.kind {
+        BlockKind::Heading {
+            id: Some(heading_id),
+            ..
+        } if heading_id == id => true,
+        BlockKind::FootnoteDefinition { label, .. } if footnote_id(label) == id => true,
+        BlockKind::Paragraph(content)
+        | BlockKind::Plain(content)
+        | BlockKind::Heading { content, .. } => inlines_contain_target(content, id),
+        BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+            blocks.iter().any(|b| contains_target(b, id))
+        }
+        BlockKind::List { items, .. } => items
+            .iter()
+            .any(|item| item.blocks.iter().any(|b| contains_target(b, id))),
+        BlockKind::Table { header, rows, .. } => std::iter::once(header)
+            .chain(rows)
+            .flat_map(|row| &row.cells)
+            .any(|cell| inlines_contain_target(&cell.content, id)),
+        BlockKind::DefinitionList(items) => items.iter().any(|item| match item {
+            DefinitionListItem::Title { content, .. } => inlines_contain_target(content, id),
+            DefinitionListItem::Definition { blocks, .. } => {
+                blocks.iter().any(|b| contains_target(b, id))
+            }
+        }),
+        BlockKind::CodeBlock { .. }
+        | BlockKind::HtmlBlock(_)
+        | BlockKind::MetadataBlock { .. }
+        | BlockKind::Rule => false,
+    }
+}
+
+fn inlines_contain_target(inlines: &[Inline], id: &str) -> bool {
+    inlines.iter().any(|inline| match &inline.kind {
+        InlineKind::FootnoteReference(label) => footnote_reference_id(label) == id,
+        InlineKind::Emphasis(content)
+        | InlineKind::Strong(content)
+        | InlineKind::Strikethrough(content)
+        | InlineKind::Superscript(content)
+        | InlineKind::Subscript(content)
+        | InlineKind::Link { content, .. }
+        | InlineKind::Image { content, .. } => inlines_contain_target(content, id),
+        _ => false,
+    })
+}
+
+/// The html of an html block
+fn html_block_text(block: &Block) -> Option<String> {
+    match &block.kind {
+        BlockKind::HtmlBlock(lines) => Some(html_text(lines)),
+        _ => None,
+    }
+}
+
+fn html_text(lines: &[Inline]) -> String {
+    lines
+        .iter()
+        .filter_map(|line| match &line.kind {
+            InlineKind::Html(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The number of blocks within a `<details>` section that was opened before them and
+/// the html that follows the end tag in the html block that closes the section
+fn details_end(blocks: &[Block]) -> (usize, String) {
+    let mut depth = 1;
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some(html) = html_block_text(block)
+            && let Some(rest) = html::close_details(&html, &mut depth)
+        {
+            return (i, rest);
+        }
+    }
+    (blocks.len(), String::new())
+}
+
+/// The top level blocks that are shown together. A `<details>` section is shown as a
+/// single collapsing header, so the blocks within it are kept with the blocks that
+/// open and close it.
+fn block_units(blocks: &[Block], options: &CommonMarkOptions) -> Vec<BlockUnit> {
+    let opens_details = |html: &str| html::split_open_details(&mut html::parse(html)).is_some();
+    let renders_details = options.render_html && options.html_fn.is_none();
+
+    let mut units = Vec::new();
+    let mut details_count = Arc::new(HashMap::new());
+    let mut start = 0;
+    while start < blocks.len() {
+        let mut end = start + 1;
+        let mut depth = usize::from(
+            renders_details
+                && html_block_text(&blocks[start]).is_some_and(|html| opens_details(&html)),
+        );
+        while depth > 0 && end < blocks.len() {
+            if let Some(html) = html_block_text(&blocks[end])
+                && let Some(rest) = html::close_details(&html, &mut depth)
+                && opens_details(&rest)
+            {
+                depth = 1;
+            }
+            end += 1;
+        }
+
+        units.push(BlockUnit {
+            blocks: start..end,
+            details_count: details_count.clone(),
+        });
+
+        if renders_details {
+            for html in blocks[start..end].iter().filter_map(html_block_text) {
+                count_details(&html::parse(&html), Arc::make_mut(&mut details_count));
+            }
+        }
+        start = end;
+    }
+    units
+}
+
+fn count_details(nodes: &[html::Node], details_count: &mut HashMap<String, usize>) {
+    for node in nodes {
+        if let html::Node::Element(element) = node {
+            if element.name == "details" {
+                let summary = element.summary().unwrap_or_else(|| "Details".to_owned());
+                *details_count.entry(summary).or_default() += 1;
+            }
+            count_details(&element.children, details_count);
+        }
+    }
+}
+
+/// Shows the content in a ui with the given id if there is one
+fn details_scope<R>(ui: &mut Ui, id: Option<Id>, add_contents: impl FnOnce(&mut Ui) -> R) -> R {
+    match id {
+        Some(id) => {
+            ui.scope_builder(egui::UiBuilder::new().id(id), add_contents)
+                .inner
+        }
+        None => add_contents(ui),
+    }
+}
+
+/// Shows the content of a footnote definition when hovering a reference to it
+fn footnote_tooltip(
+    ui: &mut Ui,
+    blocks: &[Block],
+    cache: &mut CommonMarkCache,
+    options: &CommonMarkOptions,
+) {
+    let max_width = ui.spacing().tooltip_width;
+    let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+    ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        ui.set_row_height(ui.text_style_height(&TextStyle::Body));
+
+        let mut viewer = CommonMarkViewerInternal::new();
+        viewer.line.should_not_start_newline_forced = true;
+        viewer.render(ui, blocks, cache, options, max_width);
+    });
+}
+
+/// Rough height of a block that has not been rendered yet based on its source lines
+fn estimate_block_height(text: &str, block: &Block, row_height: f32) -> f32 {
+    let lines = text
+        .get(block.span.clone())
+        .map_or(1, |source| source.lines().count().max(1));
+    // Account for the empty line that separates blocks
+    (lines + 1) as f32 * row_height
+}
+
+fn parser_options_for(options: &CommonMarkOptions) -> pulldown_cmark::Options {
+    parser_options_extras(
+        options.math_fn.is_some(),
+        options.enable_scroll_to_heading,
+        options.enable_superscript_and_subscript,
+        options.enable_front_matter,
+    )
+}
+
+impl<'d> CommonMarkViewerInternal<'d> {
     /// Be aware that this acquires egui::Context internally.
-    /// If split Id is provided then split points will be populated
     pub(crate) fn show(
         &mut self,
         ui: &mut egui::Ui,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
-        text: &str,
-        split_points_id: Option<Id>,
+        document: &'d Document,
     ) -> (egui::InnerResponse<()>, Vec<CheckboxClickEvent>) {
+        self.document = Some(document);
         let max_width = options.max_width(ui);
         let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
 
@@ -145,60 +447,17 @@ impl CommonMarkViewerInternal {
             let height = ui.text_style_height(&TextStyle::Body);
             ui.set_row_height(height);
 
-            let mut events = pulldown_cmark::Parser::new_ext(
-                text,
-                parser_options_extras(options.math_fn.is_some(), options.enable_scroll_to_heading),
-            )
-            .into_offset_iter()
-            .enumerate()
-            .peekable();
-
-            while let Some((index, (e, src_span))) = events.next() {
-                let start_position = ui.next_widget_position();
-                let is_element_end = matches!(e, pulldown_cmark::Event::End(_));
-                let should_add_split_point = self.list.is_inside_a_list() && is_element_end;
-
-                if events.peek().is_none() {
-                    self.line.should_end_newline_forced = false;
-                }
-
-                self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
-
-                if let Some(source_id) = split_points_id
-                    && should_add_split_point
-                {
-                    let scroll_cache = scroll_cache(cache, &source_id);
-                    let end_position = ui.next_widget_position();
-
-                    let split_point_exists = scroll_cache
-                        .split_points
-                        .iter()
-                        .any(|(i, _, _)| *i == index);
-
-                    if !split_point_exists {
-                        scroll_cache
-                            .split_points
-                            .push((index, start_position, end_position));
-                    }
-                }
-
-                if index == 0 {
-                    self.line.should_not_start_newline_forced = false;
-                }
-            }
+            self.render(ui, visible_blocks(document), cache, options, max_width);
 
             // deferral to make it consistent no matter whether the target is before or after the link
             *cache.scroll_to_id_target_mut() = self.deferred_scroll_to_heading.take();
-
-            if let Some(source_id) = split_points_id {
-                scroll_cache(cache, &source_id).page_size =
-                    Some(ui.next_widget_position().to_vec2());
-            }
         });
 
         (re, std::mem::take(&mut self.checkbox_events))
     }
 
+    /// Only renders the top level blocks that are within the viewport. The heights of
+    /// the blocks are measured as they are rendered and are otherwise estimated.
     pub(crate) fn show_scrollable(
         &mut self,
         source_id: Id,
@@ -207,29 +466,34 @@ impl CommonMarkViewerInternal {
         options: &CommonMarkOptions,
         text: &str,
     ) {
-        let available_size = ui.available_size();
         let scroll_id = source_id.with("_scroll_area");
-
-        let Some(page_size) = scroll_cache(cache, &source_id).page_size else {
-            egui::ScrollArea::vertical()
-                .id_salt(scroll_id)
-                .auto_shrink([false, true])
-                .show(ui, |ui| {
-                    self.show(ui, cache, options, text, Some(source_id));
-                });
-            // Prevent repopulating points twice at startup
-            scroll_cache(cache, &source_id).available_size = available_size;
-            return;
-        };
-
-        let events = pulldown_cmark::Parser::new_ext(
+        let document = parse_cached(
+            cache,
+            Some(source_id),
             text,
-            parser_options_extras(options.math_fn.is_some(), options.enable_scroll_to_heading),
-        )
-        .into_offset_iter()
-        .collect::<Vec<_>>();
-
-        let num_rows = events.len();
+            options,
+            options.enable_scroll_to_heading,
+        );
+        let blocks = visible_blocks(&document);
+        // The units only change with the document and options, so they are not
+        // gathered again every frame
+        let source_hash = layout_key(text, options);
+        let scrollable = scroll_cache(cache, &source_id);
+        if scrollable.source_hash != source_hash {
+            let units = block_units(blocks, options);
+            scrollable.source_hash = source_hash;
+            scrollable.block_heights = vec![None; units.len()];
+            scrollable.units = units.into();
+        }
+        let units = scrollable.units.clone();
+
+        let target_block = cache.scroll_to_id_target().and_then(|target| {
+            units.iter().position(|unit| {
+                blocks[unit.blocks.clone()]
+                    .iter()
+                    .any(|b| contains_target(b, target))
+            })
+        });
 
         egui::ScrollArea::vertical()
             .id_salt(scroll_id)
@@ -238,383 +502,208 @@ impl CommonMarkViewerInternal {
             // with different widths.
             .auto_shrink([false, true])
             .show_viewport(ui, |ui, viewport| {
-                ui.set_height(page_size.y);
-                let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
-
                 let max_width = options.max_width(ui);
-                ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
-                    ui.spacing_mut().item_spacing.x = 0.0;
-                    let scroll_cache = scroll_cache(cache, &source_id);
-
-                    // finding the first element that's not in the viewport anymore
-                    let (first_event_index, _, first_end_position) = scroll_cache
-                        .split_points
-                        .iter()
-                        .filter(|(_, _, end_position)| end_position.y < viewport.min.y)
-                        .nth_back(1)
-                        .copied()
-                        .unwrap_or((0, Pos2::ZERO, Pos2::ZERO));
-
-                    // finding the last element that's just outside the viewport
-                    let last_event_index = scroll_cache
-                        .split_points
-                        .iter()
-                        .filter(|(_, start_position, _)| start_position.y > viewport.max.y)
-                        .nth(1)
-                        .map(|(index, _, _)| *index)
-                        .unwrap_or(num_rows);
-
-                    ui.allocate_space(first_end_position.to_vec2());
-
-                    // only rendering the elements that are inside the viewport
-                    let mut events = events
-                        .into_iter()
-                        .enumerate()
-                        .skip(first_event_index)
-                        .take(last_event_index - first_event_index)
-                        .peekable();
-
-                    while let Some((i, (e, src_span))) = events.next() {
-                        if events.peek().is_none() {
-                            self.line.should_end_newline_forced = false;
-                        }
-
-                        self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
-
-                        if i == 0 {
-                            self.line.should_not_start_newline_forced = false;
-                        }
-                    }
-                });
-            });
-
-        // Forcing full re-render to repopulate split points for the new size
-        let scroll_cache = scroll_cache(cache, &source_id);
-        if available_size != scroll_cache.available_size {
-            scroll_cache.available_size = available_size;
-            scroll_cache.page_size = None;
-            scroll_cache.split_points.clear();
-        }
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    fn process_event<'e>(
-        &mut self,
-        ui: &mut Ui,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        event: pulldown_cmark::Event,
-        src_span: Range<usize>,
-        cache: &mut CommonMarkCache,
-        options: &CommonMarkOptions,
-        max_width: f32,
-    ) {
-        self.event(ui, event, src_span, cache, options, max_width);
-
-        self.def_list_def_wrapping(events, max_width, cache, options, ui);
-        self.item_list_wrapping(events, max_width, cache, options, ui);
-        self.table(events, cache, options, ui, max_width);
-        self.blockquote(events, max_width, cache, options, ui);
-    }
+                let row_height = ui.text_style_height(&TextStyle::Body);
+                let content_top = ui.cursor().top();
+
+                // The blocks are stacked directly on top of each other as their content
+                // already contains the spacing between them
+                let item_spacing_y = ui.spacing().item_spacing.y;
+                ui.spacing_mut().item_spacing.y = 0.0;
+
+                let scrollable = scroll_cache(cache, &source_id);
+
+                // Blocks measured at a different width are estimated again until they
+                // are rendered, as their old height can be far off when text rewraps
+                let heights = units
+                    .iter()
+                    .zip(&scrollable.block_heights)
+                    .map(|(unit, measured)| {
+                        measured
+                            .filter(|(_, width)| *width == max_width)
+                            .map(|(height, _)| height)
+                            .unwrap_or_else(|| {
+                                blocks[unit.blocks.clone()]
+                                    .iter()
+                                    .map(|block| estimate_block_height(text, block, row_height))
+                                    .sum()
+                            })
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut tops = Vec::with_capacity(heights.len());
+                let mut total_height = 0.0;
+                for height in &heights {
+                    tops.push(total_height);
+                    total_height += height;
+                }
 
-    fn def_list_def_wrapping<'e>(
-        &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        max_width: f32,
-        cache: &mut CommonMarkCache,
-        options: &CommonMarkOptions,
-        ui: &mut Ui,
-    ) {
-        if self.def_list.is_def_list_def {
-            self.def_list.is_def_list_def = false;
+                let first = (0..units.len())
+                    .find(|&i| tops[i] + heights[i] >= viewport.min.y)
+                    .unwrap_or(units.len());
+                let last = (first..units.len())
+                    .take_while(|&i| tops[i] <= viewport.max.y)
+                    .last()
+                    .map_or(first, |i| i + 1);
 
-            let item_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::DefinitionListDefinition)
-            });
+                if let Some(target) = target_block
+                    && !(first..last).contains(&target)
+                {
+                    // Jump to the estimated position. The heading itself will scroll to
+                    // its exact position once it has been rendered.
+                    let rect = egui::Rect::from_min_size(
+                        egui::pos2(ui.cursor().left(), content_top + tops[target]),
+                        egui::vec2(max_width, heights[target]),
+                    );
+                    ui.scroll_to_rect(rect, Some(egui::Align::TOP));
+                }
 
-            let mut events_iter = item_events.into_iter().enumerate().peekable();
+                if first > 0 {
+                    ui.add_space(tops[first]);
+                }
 
-            self.line.try_insert_start(ui);
+                let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+                for (i, unit) in units.iter().enumerate().take(last).skip(first) {
+                    let block_top = ui.cursor().top();
+                    let mut viewer = CommonMarkViewerInternal::new();
+                    viewer.document = Some(&document);
+                    viewer.details_count = (*unit.details_count).clone();
+                    viewer.details_id = Some(source_id.with("_details"));
+                    // Html blocks printed as text end with the line break of their last
+                    // line, which has already started the row the next block begins on
+                    let follows_html_text = i > 0
+                        && options.html_fn.is_none()
+                        && !options.render_html
+                        && matches!(blocks[unit.blocks.start - 1].kind, BlockKind::HtmlBlock(_));
+                    viewer.line.should_not_start_newline_forced = i == 0 || follows_html_text;
+
+                    // Stable ids ensure that widget state survives blocks being skipped
+                    ui.push_id(i, |ui| {
+                        ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+                            ui.spacing_mut().item_spacing = egui::vec2(0.0, item_spacing_y);
+                            ui.set_row_height(row_height);
+                            // The block does not end its own line as the next block will
+                            // always start below it
+                            viewer.render(
+                                ui,
+                                &blocks[unit.blocks.clone()],
+                                cache,
+                                options,
+                                max_width,
+                            );
+                        });
+                    });
 
-            // Proccess a single event separately so that we do not insert spaces where we do not
-            // want them
-            self.line.should_start_newline = false;
-            if let Some((_, (e, src_span))) = events_iter.next() {
-                self.process_event(ui, &mut events_iter, e, src_span, cache, options, max_width);
-            }
+                    if let Some(target) = viewer.deferred_scroll_to_heading.take() {
+                        self.deferred_scroll_to_heading = Some(target);
+                    }
 
-            ui.label(" ".repeat(options.indentation_spaces));
-            self.line.should_start_newline = true;
-            self.line.should_end_newline = false;
-            // Required to ensure that the content is aligned with the identation
-            ui.horizontal_wrapped(|ui| {
-                while let Some((_, (e, src_span))) = events_iter.next() {
-                    self.process_event(
-                        ui,
-                        &mut events_iter,
-                        e,
-                        src_span,
-                        cache,
-                        options,
-                        max_width,
-                    );
+                    scroll_cache(cache, &source_id).block_heights[i] =
+                        Some((ui.cursor().top() - block_top, max_width));
                 }
-            });
-            self.line.should_end_newline = true;
-
-            // Only end the definition items line if it is not the last element in the list
-            if !matches!(
-                events.peek(),
-                Some((
-                    _,
-                    (
-                        pulldown_cmark::Event::End(pulldown_cmark::TagEnd::DefinitionList),
-                        _
-                    )
-                ))
-            ) {
-                self.line.try_insert_end(ui);
-            }
-        }
-    }
 
-    fn item_list_wrapping<'e>(
-        &mut self,
-        events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
-        max_width: f32,
-        cache: &mut CommonMarkCache,
-        options: &CommonMarkOptions,
-        ui: &mut Ui,
-    ) {
-        if self.is_list_item {
-            self.is_list_item = false;
-
-            let item_events = delayed_events_list_item(events);
-            let mut events_iter = item_events.into_iter().enumerate().peekable();
-
-            // Required to ensure that the content of the list item is aligned with
-            // the * or - when wrapping
-            ui.horizontal_wrapped(|ui| {
-                while let Some((_, (e, src_span))) = events_iter.next() {
-                    self.process_event(
-                        ui,
-                        &mut events_iter,
-                        e,
-                        src_span,
-                        cache,
-                        options,
-                        max_width,
-                    );
+                let rendered_bottom = tops.get(last).copied().unwrap_or(total_height);
+                let remaining = total_height - rendered_bottom;
+                if remaining > 0.0 {
+                    ui.add_space(remaining);
                 }
             });
-        }
-    }
-
-    fn blockquote<'e>(
-        &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        max_width: f32,
-        cache: &mut CommonMarkCache,
-        options: &CommonMarkOptions,
-        ui: &mut Ui,
-    ) {
-        if self.is_blockquote {
-            let mut collected_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::BlockQuote(_))
-            });
-            self.line.try_insert_start(ui);
-
-            // Currently the blockquotes are made in such a way that they need a newline at the end
-            // and the start so when this is the first element in the markdown the newline must be
-            // manually enabled
-            self.line.should_not_start_newline_forced = false;
-            if let Some(alert) = parse_alerts(&options.alerts, &mut collected_events) {
-                egui_commonmark_backend::alert_ui(alert, ui, |ui| {
-                    for (event, src_span) in collected_events {
-                        self.event(ui, event, src_span, cache, options, max_width);
-                    }
-                })
-            } else {
-                blockquote(ui, ui.visuals().weak_text_color(), |ui| {
-                    self.text_style.quote = true;
-                    for (event, src_span) in collected_events {
-                        self.event(ui, event, src_span, cache, options, max_width);
-                    }
-                    self.text_style.quote = false;
-                });
-            }
 
-            if events.peek().is_none() {
-                self.line.should_end_newline_forced = false;
-            }
-
-            self.line.try_insert_end(ui);
-            self.is_blockquote = false;
+        // Keep the target until the heading has been rendered and scrolled to
+        let is_target_pending = target_block.is_some() && cache.scroll_to_id_target().is_some();
+        if let Some(target) = self.deferred_scroll_to_heading.take() {
+            *cache.scroll_to_id_target_mut() = Some(target);
+        } else if !is_target_pending {
+            *cache.scroll_to_id_target_mut() = None;
         }
     }
 
-    fn table<'e>(
+    /// Shows the blocks as a document of their own. The first block does not start a
+    /// new line and the last block does not end its line.
+    fn render(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        ui: &mut Ui,
+        blocks: &[Block],
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
-        ui: &mut Ui,
         max_width: f32,
     ) {
-        if self.is_table {
-            self.line.try_insert_start(ui);
-
-            let id = ui.id().with("_table").with(self.curr_table);
-            self.curr_table += 1;
-
-            egui::Frame::group(ui.style()).show(ui, |ui| {
-                let Table { header, rows } = parse_table(events);
-
-                egui::Grid::new(id).striped(true).show(ui, |ui| {
-                    for col in header {
-                        ui.horizontal(|ui| {
-                            for (e, src_span) in col {
-                                let tmp_start =
-                                    std::mem::replace(&mut self.line.should_start_newline, false);
-                                let tmp_end =
-                                    std::mem::replace(&mut self.line.should_end_newline, false);
-                                self.event(ui, e, src_span, cache, options, max_width);
-                                self.line.should_start_newline = tmp_start;
-                                self.line.should_end_newline = tmp_end;
-                            }
-                        });
-                    }
-
-                    ui.end_row();
-
-                    for row in rows {
-                        for col in row {
-                            ui.horizontal(|ui| {
-                                for (e, src_span) in col {
-                                    let tmp_start = std::mem::replace(
-                                        &mut self.line.should_start_newline,
-                                        false,
-                                    );
-                                    let tmp_end =
-                                        std::mem::replace(&mut self.line.should_end_newline, false);
-                                    self.event(ui, e, src_span, cache, options, max_width);
-                                    self.line.should_start_newline = tmp_start;
-                                    self.line.should_end_newline = tmp_end;
-                                }
-                            });
-                        }
-
-                        ui.end_row();
-                    }
-                });
-            });
-
-            self.is_table = false;
-            if events.peek().is_none() {
-                self.line.should_end_newline_forced = false;
-            }
-
-            self.line.try_insert_end(ui);
-        }
+        self.blocks(ui, blocks, true, cache, options, max_width);
     }
 
-    fn event(
+    /// Shows the blocks of a container. A `<details>` section that an html block opens
+    /// contains the blocks that follow it up until the html block that closes it.
+    #[allow(clippy::too_many_arguments)]
+    fn blocks(
         &mut self,
         ui: &mut Ui,
-        event: pulldown_cmark::Event,
-        src_span: Range<usize>,
+        blocks: &[Block],
+        ends_document: bool,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
-        match event {
-            pulldown_cmark::Event::Start(tag) => self.start_tag(ui, tag, cache, options),
-            pulldown_cmark::Event::End(tag) => self.end_tag(ui, tag, cache, options, max_width),
-            pulldown_cmark::Event::Text(text) => {
-                self.event_text(text, ui);
-            }
-            pulldown_cmark::Event::Code(text) => {
-                self.text_style.code = true;
-                self.event_text(text, ui);
-                self.text_style.code = false;
-            }
-            pulldown_cmark::Event::InlineHtml(text) => {
-                self.event_text(text, ui);
-            }
-
-            pulldown_cmark::Event::Html(text) => {
-                if options.html_fn.is_some() {
-                    self.html_block.push_str(&text);
-                } else {
-                    self.event_text(text, ui);
-                }
-            }
-            pulldown_cmark::Event::FootnoteReference(footnote) => {
-                footnote_start(ui, &footnote);
-            }
-            pulldown_cmark::Event::SoftBreak => {
-                soft_break(ui);
-            }
-            pulldown_cmark::Event::HardBreak => newline(ui),
-            pulldown_cmark::Event::Rule => {
-                self.line.try_insert_start(ui);
-                rule(ui, self.line.can_insert_end());
-            }
-            pulldown_cmark::Event::TaskListMarker(mut checkbox) => {
-                if options.mutable {
-                    if ui
-                        .add(egui::Checkbox::without_text(&mut checkbox))
-                        .clicked()
-                    {
-                        self.checkbox_events.push(CheckboxClickEvent {
-                            checked: checkbox,
-                            span: src_span,
-                        });
-                    }
-                } else {
-                    ui.add(ImmutableCheckbox::without_text(&mut checkbox));
-                }
-            }
-            pulldown_cmark::Event::InlineMath(tex) => {
-                if let Some(math_fn) = options.math_fn {
-                    math_fn(ui, &tex, true);
-                }
-            }
-            pulldown_cmark::Event::DisplayMath(tex) => {
-                if let Some(math_fn) = options.math_fn {
-                    math_fn(ui, &tex, false);
-                }
+        let mut i = 0;
+        while i < blocks.len() {
+            i += 1;
+            let is_last = ends_document && i == blocks.len();
+            self.block(ui, &blocks[i - 1], is_last, cache, options, max_width);
+
+            while let Some(details) = self.details.take() {
+                let (len, closing_html) = details_end(&blocks[i..]);
+                let content = &blocks[i..i + len];
+                i = (i + len + 1).min(blocks.len());
+
+                let is_last = ends_document && i == blocks.len();
+                self.details_section(ui, &details, content, is_last, cache, options, max_width);
+
+                // Whatever follows the end tag in the same html block, which may open the
+                // next section
+                self.show_html_block(ui, &closing_html, cache, options, max_width);
             }
         }
     }
 
-    fn event_text(&mut self, text: CowStr, ui: &mut Ui) {
-        let rich_text = self.text_style.to_richtext(ui, &text);
-        if let Some(image) = &mut self.image {
-            image.alt_text.push(rich_text);
-        } else if let Some(block) = &mut self.code_block {
-            block.content.push_str(&text);
-        } else if let Some(link) = &mut self.link {
-            link.text.push(rich_text);
-        } else {
-            ui.label(rich_text);
+    /// Starts a new line for a block unless it is the first block
+    fn start_line(&mut self, ui: &mut Ui) {
+        self.line.try_insert_start(ui);
+        self.line.should_not_start_newline_forced = false;
+    }
+
+    /// Ends the line of a block unless it is the last block
+    fn end_line(&mut self, ui: &mut Ui, is_last: bool) {
+        if is_last {
+            self.line.should_end_newline_forced = false;
         }
+        self.line.try_insert_end(ui);
     }
 
-    fn start_tag(
+    fn block(
         &mut self,
         ui: &mut Ui,
-        tag: pulldown_cmark::Tag,
+        block: &Block,
+        is_last: bool,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
+        max_width: f32,
     ) {
-        match tag {
-            pulldown_cmark::Tag::Paragraph => {
-                self.line.try_insert_start(ui);
+        match &block.kind {
+            BlockKind::Paragraph(content) => {
+                self.start_line(ui);
+                self.inlines(ui, content, cache, options);
+                self.end_inline_html(ui, cache, options);
+                self.end_line(ui, is_last);
+            }
+            BlockKind::Plain(content) => {
+                self.line.should_not_start_newline_forced = false;
+                self.inlines(ui, content, cache, options);
+                self.end_inline_html(ui, cache, options);
             }
-            pulldown_cmark::Tag::Heading { level, id, .. } => {
+            BlockKind::Heading {
+                level, id, content, ..
+            } => {
                 if let Some(scroll_target) = cache.scroll_to_id_target()
-                    && let Some(id) = id
-                    && id.into_string() == scroll_target
+                    && id.as_deref() == Some(scroll_target)
                 {
                     ui.scroll_to_cursor(Some(egui::Align::TOP));
                     cache.scroll_to_id_target_mut().take();
@@ -623,6 +712,7 @@ impl CommonMarkViewerInternal {
                 // Headings should always insert a newline even if it is at the start.
                 // Whether this is okay in all scenarios is a different question.
                 newline(ui);
+                self.line.should_not_start_newline_forced = false;
                 self.text_style.heading = Some(match level {
                     HeadingLevel::H1 => 0,
                     HeadingLevel::H2 => 1,
@@ -631,199 +721,960 @@ impl CommonMarkViewerInternal {
                     HeadingLevel::H5 => 4,
                     HeadingLevel::H6 => 5,
                 });
+                self.inlines(ui, content, cache, options);
+                self.end_inline_html(ui, cache, options);
+                self.end_line(ui, is_last);
+                self.text_style.heading = None;
             }
-
             // deliberately not using the built in alerts from pulldown-cmark as
             // the markdown itself cannot be localized :( e.g: [!TIP]
-            pulldown_cmark::Tag::BlockQuote(_) => {
-                self.is_blockquote = true;
-            }
-            pulldown_cmark::Tag::CodeBlock(c) => {
-                match c {
-                    pulldown_cmark::CodeBlockKind::Fenced(lang) => {
-                        self.code_block = Some(crate::CodeBlock {
-                            lang: Some(lang.to_string()),
-                            content: "".to_string(),
-                        });
-                    }
-                    pulldown_cmark::CodeBlockKind::Indented => {
-                        self.code_block = Some(crate::CodeBlock {
-                            lang: None,
-                            content: "".to_string(),
-                        });
-                    }
+            BlockKind::BlockQuote { blocks, .. } => {
+                // Currently the blockquotes are made in such a way that they need a newline
+                // at the end and the start, which the blocks within them also get when this
+                // is the first element in the markdown
+                self.start_line(ui);
+
+                if let Some((alert, first)) = parse_alerts(&options.alerts, blocks) {
+                    egui_commonmark_backend::alert_ui(alert, ui, &options.theme, |ui| {
+                        if let Some(first) = &first {
+                            self.block(ui, first, false, cache, options, max_width);
+                        }
+                        self.blocks(ui, &blocks[1..], false, cache, options, max_width);
+                    })
+                } else {
+                    blockquote(ui, &options.theme, options.theme.quote_color(ui), |ui| {
+                        let quote = std::mem::replace(&mut self.text_style.quote, true);
+                        self.blocks(ui, blocks, false, cache, options, max_width);
+                        self.text_style.quote = quote;
+                    });
                 }
-                self.line.try_insert_start(ui);
-            }
 
-            pulldown_cmark::Tag::List(point) => {
+                self.end_line(ui, is_last);
+            }
+            BlockKind::CodeBlock { info, content } => {
+                self.start_line(ui);
+                let block = CodeBlock {
+                    lang: info.as_deref(),
+                    content,
+                };
+                block.end(ui, cache, options, max_width, self.curr_table);
+                if block.csv_delimiter().is_some() {
+                    self.curr_table += 1;
+                }
+                self.end_line(ui, is_last);
+            }
+            BlockKind::HtmlBlock(lines) => {
+                if let Some(html_fn) = options.html_fn {
+                    self.start_line(ui);
+                    html_fn(ui, &html_text(lines));
+                } else if options.render_html {
+                    // The built in renderer starts the line once it knows that there is
+                    // something to show
+                    if is_last {
+                        self.line.should_end_newline_forced = false;
+                    }
+                    self.show_html_block(ui, &html_text(lines), cache, options, max_width);
+                } else {
+                    self.start_line(ui);
+                    self.inlines(ui, lines, cache, options);
+                }
+            }
+            BlockKind::List { start, items } => {
                 if !self.list.is_inside_a_list() && self.line.can_insert_start() {
                     newline(ui);
                 }
+                self.line.should_not_start_newline_forced = false;
 
-                if let Some(number) = point {
-                    self.list.start_level_with_number(number);
+                if let Some(number) = start {
+                    self.list.start_level_with_number(*number);
                 } else {
                     self.list.start_level_without_number();
                 }
                 self.line.should_start_newline = false;
                 self.line.should_end_newline = false;
-            }
 
-            pulldown_cmark::Tag::Item => {
-                self.is_list_item = true;
-                self.list.start_item(ui, options);
-            }
+                for item in items {
+                    self.list.start_item(ui, options);
+
+                    // Required to ensure that the content of the list item is aligned with
+                    // the * or - when wrapping. Nested lists start their items on lines of
+                    // their own so they are left out.
+                    let nested = item
+                        .blocks
+                        .iter()
+                        .position(|block| matches!(block.kind, BlockKind::List { .. }))
+                        .unwrap_or(item.blocks.len());
+                    ui.horizontal_wrapped(|ui| {
+                        self.blocks(ui, &item.blocks[..nested], false, cache, options, max_width);
+                    });
+                    self.blocks(ui, &item.blocks[nested..], false, cache, options, max_width);
+                }
 
-            pulldown_cmark::Tag::FootnoteDefinition(note) => {
-                self.line.try_insert_start(ui);
+                if self.list.is_last_level() {
+                    self.line.should_start_newline = true;
+                    self.line.should_end_newline = true;
+                }
+
+                if is_last {
+                    self.line.should_end_newline_forced = false;
+                }
+                self.list.end_level(ui, self.line.can_insert_end());
+
+                if !self.list.is_inside_a_list() {
+                    // Reset all the state and make it ready for the next list that occurs
+                    self.list = List::default();
+                }
+            }
+            BlockKind::FootnoteDefinition { label, blocks } => {
+                self.start_line(ui);
 
                 self.line.should_start_newline = false;
                 self.line.should_end_newline = false;
-                footnote(ui, &note);
-            }
-            pulldown_cmark::Tag::Table(_) => {
-                self.is_table = true;
-            }
-            pulldown_cmark::Tag::TableHead => {}
-            pulldown_cmark::Tag::TableRow => {}
-            pulldown_cmark::Tag::TableCell => {}
-            pulldown_cmark::Tag::Emphasis => {
-                self.text_style.emphasis = true;
-            }
-            pulldown_cmark::Tag::Strong => {
-                self.text_style.strong = true;
-            }
-            pulldown_cmark::Tag::Strikethrough => {
-                self.text_style.strikethrough = true;
+                footnote(ui, cache, label, &mut self.deferred_scroll_to_heading);
+                self.blocks(ui, blocks, false, cache, options, max_width);
+
+                self.line.should_start_newline = true;
+                self.line.should_end_newline = true;
+                self.end_line(ui, is_last);
             }
-            pulldown_cmark::Tag::Link { dest_url, .. } => {
-                self.link = Some(crate::Link {
-                    destination: dest_url.to_string(),
-                    text: Vec::new(),
+            BlockKind::Table {
+                alignments,
+                header,
+                rows,
+            } => {
+                self.start_line(ui);
+
+                let id = ui.id().with("_table").with(self.curr_table);
+                self.curr_table += 1;
+
+                egui::Frame::group(ui.style()).show(ui, |ui| {
+                    options.theme.table_grid(id).show(ui, |ui| {
+                        let mut alignment =
+                            TableAlignment::new(ui, id.with("alignment"), alignments.clone());
+
+                        for (r, row) in std::iter::once(header).chain(rows).enumerate() {
+                            for (c, cell) in row.cells.iter().enumerate() {
+                                alignment.cell(ui, r, c, |ui| {
+                                    self.table_cell(ui, c, &cell.content, cache, options);
+                                });
+                            }
+
+                            ui.end_row();
+                        }
+
+                        alignment.finish(ui);
+                    });
                 });
-            }
-            pulldown_cmark::Tag::Image { dest_url, .. } => {
-                self.image = Some(crate::Image::new(&dest_url, options));
-            }
-            pulldown_cmark::Tag::HtmlBlock => {
-                self.line.try_insert_start(ui);
-            }
-            pulldown_cmark::Tag::MetadataBlock(_) => {}
 
-            pulldown_cmark::Tag::DefinitionList => {
-                self.line.try_insert_start(ui);
-                self.def_list.is_first_item = true;
+                self.end_line(ui, is_last);
             }
-            pulldown_cmark::Tag::DefinitionListTitle => {
-                // we disable newline as the first title should not insert a newline
-                // as we have already done that upon the DefinitionList Tag
-                if !self.def_list.is_first_item {
-                    self.line.try_insert_start(ui)
-                } else {
-                    self.def_list.is_first_item = false;
+            BlockKind::DefinitionList(items) => {
+                self.start_line(ui);
+
+                for (i, item) in items.iter().enumerate() {
+                    match item {
+                        DefinitionListItem::Title { content, .. } => {
+                            // The list has already started the line of the first title
+                            if i > 0 {
+                                self.line.try_insert_start(ui);
+                            }
+                            self.inlines(ui, content, cache, options);
+                            self.end_inline_html(ui, cache, options);
+                        }
+                        DefinitionListItem::Definition { blocks, .. } => {
+                            self.definition(ui, blocks, cache, options, max_width);
+
+                            // Only end the definition items line if it is not the last
+                            // element in the list
+                            if i + 1 < items.len() {
+                                self.line.try_insert_end(ui);
+                            }
+                        }
+                    }
                 }
+
+                self.end_line(ui, is_last);
             }
-            pulldown_cmark::Tag::DefinitionListDefinition => {
-                self.def_list.is_def_list_def = true;
+            // Front matter is only returned to the caller
+            BlockKind::MetadataBlock { .. } => {}
+            BlockKind::Rule => {
+                if is_last {
+                    self.line.should_end_newline_forced = false;
+                }
+                self.start_line(ui);
+                rule(ui, &options.theme, self.line.can_insert_end());
             }
-            // Not yet supported
-            pulldown_cmark::Tag::Superscript | pulldown_cmark::Tag::Subscript => {}
         }
     }
 
-    fn end_tag(
+    fn table_cell(
         &mut self,
         ui: &mut Ui,
-        tag: pulldown_cmark::TagEnd,
+        column: usize,
+        content: &[Inline],
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        // Ensure space between cells
+        if column > 0 {
+            ui.label("  ");
+        }
+
+        let tmp_start = std::mem::replace(&mut self.line.should_start_newline, false);
+        let tmp_end = std::mem::replace(&mut self.line.should_end_newline, false);
+        self.inlines(ui, content, cache, options);
+        self.end_inline_html(ui, cache, options);
+        self.line.should_start_newline = tmp_start;
+        self.line.should_end_newline = tmp_end;
+    }
+
+    fn definition(
+        &mut self,
+        ui: &mut Ui,
+        blocks: &[Block],
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
-        match tag {
-            pulldown_cmark::TagEnd::Paragraph => {
-                self.line.try_insert_end(ui);
+        self.line.try_insert_start(ui);
+        ui.label(" ".repeat(options.indentation_spaces));
+
+        // The first block continues the line of the indentation so that we do not insert
+        // spaces where we do not want them
+        self.line.should_start_newline = false;
+        self.line.should_end_newline = false;
+        // Required to ensure that the content is aligned with the identation
+        ui.horizontal_wrapped(|ui| {
+            let (first, rest) = blocks.split_at(blocks.len().min(1));
+            self.blocks(ui, first, false, cache, options, max_width);
+            self.line.should_start_newline = true;
+            self.blocks(ui, rest, false, cache, options, max_width);
+        });
+        self.line.should_start_newline = true;
+        self.line.should_end_newline = true;
+    }
+
+    fn inlines(
+        &mut self,
+        ui: &mut Ui,
+        inlines: &[Inline],
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        for inline in inlines {
+            self.inline(ui, inline, cache, options);
+        }
+    }
+
+    fn inline(
+        &mut self,
+        ui: &mut Ui,
+        inline: &Inline,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        match &inline.kind {
+            InlineKind::Text(text) | InlineKind::Html(text) => {
+                self.text(text, ui, options);
             }
-            pulldown_cmark::TagEnd::Heading { .. } => {
-                self.line.try_insert_end(ui);
-                self.text_style.heading = None;
+            InlineKind::Code(text) => {
+                let code = std::mem::replace(&mut self.text_style.code, true);
+                self.text(text, ui, options);
+                self.text_style.code = code;
             }
-            pulldown_cmark::TagEnd::BlockQuote(_) => {}
-            pulldown_cmark::TagEnd::CodeBlock => {
-                self.end_code_block(ui, cache, options, max_width);
+            InlineKind::InlineHtml(text) => {
+                if options.render_html {
+                    self.inline_html(text, ui, cache, options);
+                } else {
+                    self.text(text, ui, options);
+                }
             }
-
-            pulldown_cmark::TagEnd::List(_) => {
-                if self.list.is_last_level() {
-                    self.line.should_start_newline = true;
-                    self.line.should_end_newline = true;
+            InlineKind::FootnoteReference(footnote) => {
+                let response =
+                    footnote_start(ui, cache, footnote, &mut self.deferred_scroll_to_heading);
+                if let Some(blocks) = self.document.and_then(|d| d.footnote_definition(footnote)) {
+                    response.on_hover_ui(|ui| footnote_tooltip(ui, blocks, cache, options));
                 }
-
-                self.list.end_level(ui, self.line.can_insert_end());
-
-                if !self.list.is_inside_a_list() {
-                    // Reset all the state and make it ready for the next list that occurs
-                    self.list = List::default();
+            }
+            InlineKind::SoftBreak => soft_break(ui),
+            InlineKind::HardBreak => newline(ui),
+            InlineKind::TaskListMarker(checked) => {
+                let mut checkbox = *checked;
+                if options.mutable {
+                    if ui
+                        .add(egui::Checkbox::without_text(&mut checkbox))
+                        .clicked()
+                    {
+                        self.checkbox_events.push(CheckboxClickEvent {
+                            checked: checkbox,
+                            span: inline.span.clone(),
+                        });
+                    }
+                } else {
+                    ui.add(ImmutableCheckbox::without_text(&mut checkbox));
                 }
             }
-            pulldown_cmark::TagEnd::Item => {}
-            pulldown_cmark::TagEnd::FootnoteDefinition => {
-                self.line.should_start_newline = true;
-                self.line.should_end_newline = true;
-                self.line.try_insert_end(ui);
+            InlineKind::InlineMath(tex) => {
+                if let Some(math_fn) = options.math_fn {
+                    math_fn(ui, tex, true);
+                }
             }
-            pulldown_cmark::TagEnd::Table => {}
-            pulldown_cmark::TagEnd::TableHead => {}
-            pulldown_cmark::TagEnd::TableRow => {}
-            pulldown_cmark::TagEnd::TableCell => {
-                // Ensure space between cells
-                ui.label("  ");
+            InlineKind::DisplayMath(tex) => {
+                if let Some(math_fn) = options.math_fn {
+                    math_fn(ui, tex, false);
+                }
             }
-            pulldown_cmark::TagEnd::Emphasis => {
-                self.text_style.emphasis = false;
+            InlineKind::Emphasis(content) => {
+                let emphasis = std::mem::replace(&mut self.text_style.emphasis, true);
+                self.inlines(ui, content, cache, options);
+                self.text_style.emphasis = emphasis;
             }
-            pulldown_cmark::TagEnd::Strong => {
-                self.text_style.strong = false;
+            InlineKind::Strong(content) => {
+                let strong = std::mem::replace(&mut self.text_style.strong, true);
+                self.inlines(ui, content, cache, options);
+                self.text_style.strong = strong;
             }
-            pulldown_cmark::TagEnd::Strikethrough => {
-                self.text_style.strikethrough = false;
+            InlineKind::Strikethrough(content) => {
+                let strikethrough = std::mem::replace(&mut self.text_style.strikethrough, true);
+                self.inlines(ui, content, cache, options);
+                self.text_style.strikethrough = strikethrough;
             }
-            pulldown_cmark::TagEnd::Link => {
+            InlineKind::Superscript(content) => {
+                let superscript = std::mem::replace(&mut self.text_style.superscript, true);
+                self.inlines(ui, content, cache, options);
+                self.text_style.superscript = superscript;
+            }
+            InlineKind::Subscript(content) => {
+                let subscript = std::mem::replace(&mut self.text_style.subscript, true);
+                self.inlines(ui, content, cache, options);
+                self.text_style.subscript = subscript;
+            }
+            InlineKind::Link {
+                destination,
+                title,
+                content,
+                ..
+            } => {
+                self.link = Some(crate::Link {
+                    destination: destination.clone(),
+                    title: title.clone(),
+                    text: Vec::new(),
+                });
+                self.inlines(ui, content, cache, options);
                 if let Some(link) = self.link.take() {
                     link.end(ui, cache, options, &mut self.deferred_scroll_to_heading);
                 }
             }
-            pulldown_cmark::TagEnd::Image => {
+            InlineKind::Image {
+                destination,
+                content,
+                ..
+            } => {
+                self.image = Some(crate::Image::new(destination, options));
+                self.inlines(ui, content, cache, options);
                 if let Some(image) = self.image.take() {
                     image.end(ui, options);
                 }
             }
-            pulldown_cmark::TagEnd::HtmlBlock => {
-                if let Some(html_fn) = options.html_fn {
-                    html_fn(ui, &self.html_block);
-                    self.html_block.clear();
+        }
+    }
+
+    fn text(&mut self, text: &str, ui: &mut Ui, options: &CommonMarkOptions) {
+        let rich_text = self.text_style.to_richtext(ui, &options.theme, text);
+        if let Some(image) = &mut self.image {
+            image.alt_text.push(rich_text);
+        } else if let Some(kbd) = &mut self.kbd {
+            kbd.push_str(text);
+        } else if let Some(link) = &mut self.link {
+            link.text.push(rich_text);
+        } else {
+            ui.label(rich_text);
+        }
+    }
+
+    /// Inline html within a paragraph. The elements apply to the markdown between their
+    /// start and end tags.
+    fn inline_html(
+        &mut self,
+        html: &str,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        for token in html::tokenize(html) {
+            match token {
+                html::Token::Start { name, attrs, raw } => {
+                    if !self.html_start(ui, &name, &attrs, cache, options) {
+                        self.unknown_html(raw, ui, options);
+                    }
+                }
+                html::Token::End { name, raw } => {
+                    if !self.html_end(ui, &name, cache, options) {
+                        self.unknown_html(raw, ui, options);
+                    }
                 }
+                html::Token::Text(text) => {
+                    self.text(&html::decode_entities(text), ui, options);
+                }
+                html::Token::Other(_) => {}
             }
+        }
+    }
 
-            pulldown_cmark::TagEnd::MetadataBlock(_) => {}
+    /// Applies the start tag of an inline element. Returns false if the element is not
+    /// supported.
+    fn html_start(
+        &mut self,
+        ui: &mut Ui,
+        name: &str,
+        attrs: &[(String, String)],
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) -> bool {
+        if let Some(style) = HtmlStyle::from_name(name) {
+            let flag = style.flag(&mut self.text_style);
+            self.html_styles.push((style, *flag));
+            *flag = true;
+            return true;
+        }
 
-            pulldown_cmark::TagEnd::DefinitionList => self.line.try_insert_end(ui),
-            pulldown_cmark::TagEnd::DefinitionListTitle
-            | pulldown_cmark::TagEnd::DefinitionListDefinition => {}
-            pulldown_cmark::TagEnd::Superscript | pulldown_cmark::TagEnd::Subscript => {}
+        match name {
+            "kbd" => self.kbd = Some(String::new()),
+            "br" => newline(ui),
+            "a" => {
+                if self.html_link_depth > 0 {
+                    self.html_link_depth += 1;
+                } else if self.link.is_none()
+                    && let Some(href) = html::attr(attrs, "href")
+                {
+                    self.link = Some(crate::Link {
+                        destination: href.to_owned(),
+                        title: html::attr(attrs, "title").unwrap_or_default().to_owned(),
+                        text: Vec::new(),
+                    });
+                    self.html_link_depth = 1;
+                }
+            }
+            "img" => {
+                if let Some(src) = html::attr(attrs, "src") {
+                    self.html_img(ui, src, attrs, cache, options);
+                }
+            }
+            _ => return html::is_supported(name),
         }
+        true
     }
 
-    fn end_code_block(
+    /// Applies the end tag of an inline element. Returns false if the element is not
+    /// supported.
+    fn html_end(
         &mut self,
         ui: &mut Ui,
+        name: &str,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) -> bool {
+        if let Some(style) = HtmlStyle::from_name(name) {
+            // Restore the flag to what it was before the element, which markdown around
+            // it may have set. An end tag without a start tag changes nothing.
+            if let Some(i) = self
+                .html_styles
+                .iter()
+                .rposition(|(open, _)| *open == style)
+            {
+                let (_, previous) = self.html_styles.remove(i);
+                *style.flag(&mut self.text_style) = previous;
+            }
+            return true;
+        }
+
+        match name {
+            "kbd" => {
+                if let Some(text) = self.kbd.take() {
+                    kbd(ui, &text);
+                }
+            }
+            "a" => {
+                if self.html_link_depth == 1
+                    && let Some(link) = self.link.take()
+                {
+                    link.end(ui, cache, options, &mut self.deferred_scroll_to_heading);
+                }
+                self.html_link_depth = self.html_link_depth.saturating_sub(1);
+            }
+            _ => return html::is_supported(name),
+        }
+        true
+    }
+
+    /// An `<img>`. Within a link the image is clickable like the text of the link.
+    fn html_img(
+        &mut self,
+        ui: &mut Ui,
+        src: &str,
+        attrs: &[(String, String)],
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        // Show the text of the link before the image to keep them in order
+        if let Some(link) = &mut self.link
+            && !link.text.is_empty()
+        {
+            let text = crate::Link {
+                destination: link.destination.clone(),
+                title: link.title.clone(),
+                text: std::mem::take(&mut link.text),
+            };
+            text.end(ui, cache, options, &mut self.deferred_scroll_to_heading);
+        }
+
+        let size = |name| {
+            html::attr(attrs, name).and_then(|size| size.trim().trim_end_matches("px").parse().ok())
+        };
+        let uri = crate::Image::new(src, options).uri;
+        let response = html_image(
+            ui,
+            &uri,
+            size("width"),
+            size("height"),
+            html::attr(attrs, "alt"),
+            options,
+        );
+
+        if let Some(link) = &self.link {
+            link.respond(
+                response,
+                ui,
+                cache,
+                options,
+                &mut self.deferred_scroll_to_heading,
+            );
+        }
+    }
+
+    /// Inline html elements end with the block they are in, even if their end tag is
+    /// missing. The text of an unclosed `<kbd>` is shown as normal text.
+    fn end_inline_html(
+        &mut self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        if let Some(text) = self.kbd.take() {
+            self.text(&text, ui, options);
+        }
+
+        if self.html_link_depth > 0 {
+            self.html_link_depth = 0;
+            if let Some(link) = self.link.take() {
+                link.end(ui, cache, options, &mut self.deferred_scroll_to_heading);
+            }
+        }
+
+        // Markdown never continues these across blocks, so only html can have set them
+        self.html_styles.clear();
+        self.text_style.strong = false;
+        self.text_style.emphasis = false;
+        self.text_style.strikethrough = false;
+        self.text_style.code = false;
+        self.text_style.superscript = false;
+        self.text_style.subscript = false;
+    }
+
+    fn unknown_html(&mut self, raw: &str, ui: &mut Ui, options: &CommonMarkOptions) {
+        if !options.strip_unknown_html {
+            self.text(raw, ui, options);
+        }
+    }
+
+    fn show_html_block(
+        &mut self,
+        ui: &mut Ui,
+        html: &str,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
-        if let Some(block) = self.code_block.take() {
-            block.end(ui, cache, options, max_width);
+        let mut nodes = html::parse(html);
+        let details = html::split_open_details(&mut nodes);
+        // Blocks with only comments take no space at all
+        if is_blank_html(nodes.iter()) && details.is_none() {
+            return;
+        }
+
+        self.start_line(ui);
+        if !is_blank_html(nodes.iter()) {
+            let layout = egui::Layout::top_down(egui::Align::LEFT);
+            ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+                ui.set_max_width(max_width);
+                self.html_flow(ui, &nodes, egui::Align::LEFT, cache, options, max_width);
+            });
+
+            if details.is_some() {
+                newline(ui);
+            }
+        }
+
+        if details.is_some() {
+            // The section ends the line once its content has been shown
+            self.details = details;
+        } else {
             self.line.try_insert_end(ui);
         }
     }
+
+    /// Shows the blocks between an html block that opens a `<details>` element and the
+    /// html block that closes it in a collapsing header
+    #[allow(clippy::too_many_arguments)]
+    fn details_section(
+        &mut self,
+        ui: &mut Ui,
+        details: &html::Element,
+        blocks: &[Block],
+        is_last: bool,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let summary = details.summary().unwrap_or_else(|| "Details".to_owned());
+        let content: Vec<_> = details
+            .children
+            .iter()
+            .filter(|node| !matches!(node, html::Node::Element(e) if e.name == "summary"))
+            .cloned()
+            .collect();
+        let contains_scroll_target = cache
+            .scroll_to_id_target()
+            .is_some_and(|target| blocks.iter().any(|block| contains_target(block, target)));
+
+        let id_salt = self.details_id_salt(summary.clone());
+        let scope_id = self.details_id.map(|id| id.with(&id_salt));
+        let mut header = egui::CollapsingHeader::new(&summary)
+            .id_salt(id_salt)
+            .default_open(details.attr("open").is_some());
+        if contains_scroll_target {
+            header = header.open(Some(true));
+        }
+
+        let layout = egui::Layout::top_down(egui::Align::LEFT);
+        ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+            ui.set_max_width(max_width);
+            details_scope(ui, scope_id, |ui| {
+                header.show(ui, |ui| {
+                    let max_width = ui.available_width();
+                    if !is_blank_html(content.iter()) {
+                        self.html_flow(ui, &content, egui::Align::LEFT, cache, options, max_width);
+                    }
+
+                    let layout =
+                        egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+                    ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+                        ui.spacing_mut().item_spacing.x = 0.0;
+                        ui.set_row_height(ui.text_style_height(&TextStyle::Body));
+
+                        let mut viewer = CommonMarkViewerInternal::new();
+                        viewer.document = self.document;
+                        viewer.line.should_not_start_newline_forced = true;
+                        viewer.render(ui, blocks, cache, options, max_width);
+
+                        self.checkbox_events.append(&mut viewer.checkbox_events);
+                        if let Some(target) = viewer.deferred_scroll_to_heading.take() {
+                            self.deferred_scroll_to_heading = Some(target);
+                        }
+                    });
+                });
+            });
+        });
+
+        self.end_line(ui, is_last);
+    }
+
+    /// Sections with the same summary are told apart by the order they are shown in
+    fn details_id_salt(&mut self, summary: String) -> (&'static str, String, usize) {
+        let count = self.details_count.entry(summary.clone()).or_default();
+        *count += 1;
+        ("details", summary, *count)
+    }
+
+    /// Places block elements below each other with the inline content between them on
+    /// lines of their own
+    fn html_flow(
+        &mut self,
+        ui: &mut Ui,
+        nodes: &[html::Node],
+        align: egui::Align,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let mut line = Vec::new();
+        for node in nodes {
+            match node {
+                html::Node::Element(element) if element.name == "br" => {
+                    if is_blank_html(line.iter().copied()) {
+                        ui.add_space(ui.text_style_height(&TextStyle::Body));
+                    }
+                    self.html_line(ui, &std::mem::take(&mut line), align, cache, options);
+                }
+                html::Node::Element(element) if element.is_block() => {
+                    self.html_line(ui, &std::mem::take(&mut line), align, cache, options);
+                    self.html_block_element(ui, element, align, cache, options, max_width);
+                }
+                node => line.push(node),
+            }
+        }
+        self.html_line(ui, &line, align, cache, options);
+    }
+
+    fn html_block_element(
+        &mut self,
+        ui: &mut Ui,
+        element: &html::Element,
+        align: egui::Align,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let align = match element.attr("align") {
+            Some(a) if a.eq_ignore_ascii_case("left") => egui::Align::LEFT,
+            Some(a) if a.eq_ignore_ascii_case("center") => egui::Align::Center,
+            Some(a) if a.eq_ignore_ascii_case("right") => egui::Align::RIGHT,
+            _ if element.name == "center" => egui::Align::Center,
+            _ => align,
+        };
+
+        match element.name.as_str() {
+            "hr" => rule(ui, &options.theme, false),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = element.name[1..].parse::<u8>().unwrap_or(1) - 1;
+                let prev = self.text_style.heading.replace(level);
+                self.html_flow(ui, &element.children, align, cache, options, max_width);
+                self.text_style.heading = prev;
+            }
+            "details" => {
+                let summary = element.summary();
+                let content: Vec<_> = element
+                    .children
+                    .iter()
+                    .filter(|node| !matches!(node, html::Node::Element(e) if e.name == "summary"))
+                    .cloned()
+                    .collect();
+
+                let title = summary.unwrap_or_else(|| "Details".to_owned());
+                let id_salt = self.details_id_salt(title.clone());
+                let scope_id = self.details_id.map(|id| id.with(&id_salt));
+                details_scope(ui, scope_id, |ui| {
+                    egui::CollapsingHeader::new(&title)
+                        .id_salt(id_salt)
+                        .default_open(element.attr("open").is_some())
+                        .show(ui, |ui| {
+                            self.html_flow(
+                                ui,
+                                &content,
+                                egui::Align::LEFT,
+                                cache,
+                                options,
+                                max_width,
+                            );
+                        });
+                });
+            }
+            name if html::is_supported(name) => {
+                self.html_flow(ui, &element.children, align, cache, options, max_width);
+            }
+            _ => {
+                self.html_line(
+                    ui,
+                    &[&html::Node::Element(element.clone())],
+                    align,
+                    cache,
+                    options,
+                );
+            }
+        }
+    }
+
+    /// A line of inline elements and text
+    fn html_line(
+        &mut self,
+        ui: &mut Ui,
+        nodes: &[&html::Node],
+        align: egui::Align,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        if is_blank_html(nodes.iter().copied()) {
+            return;
+        }
+
+        if align == egui::Align::LEFT {
+            let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+            ui.with_layout(layout, |ui| {
+                ui.spacing_mut().item_spacing.x = 0.0;
+                ui.set_row_height(ui.text_style_height(&TextStyle::Body));
+                self.html_inline(ui, nodes, cache, options);
+            });
+        } else {
+            aligned_line(ui, align, |ui| self.html_inline(ui, nodes, cache, options));
+        }
+    }
+
+    fn html_inline(
+        &mut self,
+        ui: &mut Ui,
+        nodes: &[&html::Node],
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        for (i, node) in nodes.iter().enumerate() {
+            match node {
+                html::Node::Text(text) => {
+                    let text = collapse_whitespace(text, i == 0, i + 1 == nodes.len());
+                    if !text.is_empty() {
+                        self.text(&text, ui, options);
+                    }
+                }
+                html::Node::Element(element) => {
+                    let children: Vec<_> = element.children.iter().collect();
+                    if self.html_start(ui, &element.name, &element.attrs, cache, options) {
+                        self.html_inline(ui, &children, cache, options);
+                        self.html_end(ui, &element.name, cache, options);
+                    } else {
+                        self.unknown_html(&element.start, ui, options);
+                        self.html_inline(ui, &children, cache, options);
+                        if let Some(end) = &element.end {
+                            self.unknown_html(end, ui, options);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DETAILS: &str = "\
+Intro
+
+<details>
+<summary>A</summary>
+
+Inside
+
+</details>
+
+Between
+
+<details>
+<summary>A</summary>
+
+<details>
+<summary>B</summary>
+
+Nested
+
+</details>
+
+</details>
+";
+
+    fn units(render_html: bool) -> Vec<(Range<usize>, usize)> {
+        let options = CommonMarkOptions {
+            render_html,
+            ..Default::default()
+        };
+        let document = Document::parse(DETAILS, parser_options_for(&options));
+        block_units(&document.blocks, &options)
+            .into_iter()
+            .map(|unit| {
+                (
+                    unit.blocks,
+                    unit.details_count.get("A").copied().unwrap_or(0),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn details_sections_are_shown_together() {
+        assert_eq!(units(true), [(0..1, 0), (1..4, 0), (4..5, 1), (5..10, 1)]);
+        assert_eq!(units(false).len(), 10);
+    }
+
+    #[test]
+    fn layout_key_changes_with_the_options() {
+        let options = CommonMarkOptions::default();
+        let key = layout_key(DETAILS, &options);
+        assert_eq!(layout_key(DETAILS, &CommonMarkOptions::default()), key);
+        assert_ne!(layout_key("Other", &options), key);
+
+        let mut theme = crate::CommonMarkTheme::default();
+        theme.headings[0].scale = 2.0;
+        for options in [
+            CommonMarkOptions {
+                enable_superscript_and_subscript: true,
+                ..Default::default()
+            },
+            CommonMarkOptions {
+                render_html: true,
+                ..Default::default()
+            },
+            CommonMarkOptions {
+                code_block_max_height: Some(100.0),
+                ..Default::default()
+            },
+            CommonMarkOptions {
+                code_block_header: !options.code_block_header,
+                ..Default::default()
+            },
+            CommonMarkOptions {
+                theme,
+                ..Default::default()
+            },
+        ] {
+            assert_ne!(layout_key(DETAILS, &options), key);
+        }
+    }
+
+    #[test]
+    fn html_emphasis_keeps_markdown_emphasis() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        let options = CommonMarkOptions {
+            render_html: true,
+            ..Default::default()
+        };
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let mut viewer = CommonMarkViewerInternal::new();
+            // **bold <b>x</b> still bold**
+            viewer.text_style.strong = true;
+            viewer.inline_html("<b>", ui, &mut cache, &options);
+            viewer.inline_html("</b>", ui, &mut cache, &options);
+            assert!(viewer.text_style.strong);
+
+            // <em>a <i>b</i> c</em>
+            viewer.inline_html("<em>", ui, &mut cache, &options);
+            viewer.inline_html("<i>", ui, &mut cache, &options);
+            viewer.inline_html("</i>", ui, &mut cache, &options);
+            assert!(viewer.text_style.emphasis);
+            viewer.inline_html("</em>", ui, &mut cache, &options);
+            assert!(!viewer.text_style.emphasis);
+
+            // A stray end tag leaves the markdown emphasis alone
+            viewer.inline_html("</strong>", ui, &mut cache, &options);
+            assert!(viewer.text_style.strong);
+        });
+    }
+
+    #[test]
+    fn details_end_at_their_own_end_tag() {
+        let document = Document::parse(DETAILS, pulldown_cmark::Options::empty());
+        assert_eq!(details_end(&document.blocks[2..]).0, 1);
+        // The nested section is closed first
+        assert_eq!(details_end(&document.blocks[6..]).0, 3);
+        assert_eq!(details_end(&document.blocks[..1]), (1, String::new()));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::elements::{blockquote, newline};
+use crate::theme::CommonMarkTheme;
 use egui::Ui;
 use std::collections::HashMap;
 
@@ -15,8 +16,13 @@ pub struct Alert {
 }
 
 // Seperate function to not leak into the public API
-pub fn alert_ui(alert: &Alert, ui: &mut Ui, add_contents: impl FnOnce(&mut Ui)) {
-    blockquote(ui, alert.accent_color, |ui| {
+pub fn alert_ui(
+    alert: &Alert,
+    ui: &mut Ui,
+    theme: &CommonMarkTheme,
+    add_contents: impl FnOnce(&mut Ui),
+) {
+    blockquote(ui, theme, alert.accent_color, |ui| {
         newline(ui);
         ui.colored_label(alert.accent_color, alert.icon.to_string());
         ui.add_space(3.0);
@@ -0,0 +1,153 @@
+//! Parsing of delimiter separated values as described in RFC 4180
+
+/// Split `text` into records of fields. Fields may be quoted with `"` in which case they
+/// can contain the delimiter, newlines and `""` as an escaped quote. Empty lines are skipped.
+pub fn parse(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    // Whether anything has been written to the current record, so that empty lines
+    // can be told apart from a record with a single empty field
+    let mut has_content = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => {
+                in_quotes = true;
+                has_content = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if has_content {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                has_content = false;
+            }
+            c if c == delimiter => {
+                record.push(std::mem::take(&mut field));
+                has_content = true;
+            }
+            c => {
+                field.push(c);
+                has_content = true;
+            }
+        }
+    }
+
+    if has_content {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+pub fn is_numeric(field: &str) -> bool {
+    let field = field.trim();
+    let field = field.strip_suffix('%').unwrap_or(field);
+    !field.is_empty() && field.replace('_', "").parse::<f64>().is_ok()
+}
+
+/// Guess whether the first record is a header. It is assumed to be one unless any of its
+/// fields are empty or numeric.
+pub fn has_header(records: &[Vec<String>]) -> bool {
+    records.first().is_some_and(|first| {
+        first
+            .iter()
+            .all(|field| !field.trim().is_empty() && !is_numeric(field))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(records: &[&[&str]]) -> Vec<Vec<String>> {
+        records
+            .iter()
+            .map(|record| record.iter().map(|field| (*field).to_owned()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(
+            parse("a,b,c\n1,,3\n", ','),
+            records(&[&["a", "b", "c"], &["1", "", "3"]])
+        );
+        assert_eq!(parse("a\tb,c", '\t'), records(&[&["a", "b,c"]]));
+    }
+
+    #[test]
+    fn parse_quoted_fields() {
+        assert_eq!(
+            parse("\"a,b\",\"say \"\"hi\"\"\",\"\"", ','),
+            records(&[&["a,b", "say \"hi\"", ""]])
+        );
+        // A quote within an unquoted field is kept as is
+        assert_eq!(parse("a\"b,c", ','), records(&[&["a\"b", "c"]]));
+    }
+
+    #[test]
+    fn parse_embedded_newlines() {
+        assert_eq!(
+            parse("\"line 1\nline 2\",b\nc,d", ','),
+            records(&[&["line 1\nline 2", "b"], &["c", "d"]])
+        );
+        assert_eq!(parse("\"a\r\nb\",c\r\n", ','), records(&[&["a\r\nb", "c"]]));
+    }
+
+    #[test]
+    fn parse_crlf_and_empty_lines() {
+        assert_eq!(
+            parse("a,b\r\n\r\nc,d\r\n", ','),
+            records(&[&["a", "b"], &["c", "d"]])
+        );
+        assert_eq!(parse(",\n\n", ','), records(&[&["", ""]]));
+        assert!(parse("\n\r\n", ',').is_empty());
+    }
+
+    #[test]
+    fn parse_ragged_rows() {
+        assert_eq!(
+            parse("a,b,c\n1\n2,3,4,5", ','),
+            records(&[&["a", "b", "c"], &["1"], &["2", "3", "4", "5"]])
+        );
+    }
+
+    #[test]
+    fn numeric() {
+        assert!(is_numeric(" 1.5 "));
+        assert!(is_numeric("-3"));
+        assert!(is_numeric("1_000"));
+        assert!(is_numeric("50%"));
+        assert!(!is_numeric(""));
+        assert!(!is_numeric("%"));
+        assert!(!is_numeric("one"));
+    }
+
+    #[test]
+    fn header() {
+        assert!(has_header(&records(&[&["name", "size"], &["a", "1"]])));
+        assert!(!has_header(&records(&[&["a", "1"], &["b", "2"]])));
+        assert!(!has_header(&records(&[&["name", " "], &["a", "1"]])));
+        assert!(!has_header(&[]));
+    }
+}
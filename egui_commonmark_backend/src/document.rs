@@ -0,0 +1,1595 @@
+//! An owned tree representation of a markdown document.
+//!
+//! A [`Document`] is built once from the pulldown_cmark event stream and can be
+//! inspected, transformed or stored before it is rendered. Both the runtime viewer and
+//! the `commonmark!` macros render from this representation.
+//!
+//! # Example
+//!
+//! ```
+//! # use egui_commonmark_backend::document::*;
+//! let document = Document::parse("# Title\n\nSome *text*", Options::empty());
+//!
+//! assert_eq!(document.blocks.len(), 2);
+//! assert!(matches!(
+//!     document.blocks[0].kind,
+//!     BlockKind::Heading { level: HeadingLevel::H1, .. }
+//! ));
+//! ```
+
+use std::ops::{Range, RangeInclusive};
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+
+pub use pulldown_cmark::{
+    Alignment, BlockQuoteKind, HeadingLevel, LinkType, MetadataBlockKind, Options,
+};
+
+/// A parsed markdown document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+}
+
+/// A block level element together with its location in the source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockKind {
+    Paragraph(Vec<Inline>),
+    /// Inline content that is not wrapped in a paragraph. This is what the items of
+    /// tight lists consist of.
+    Plain(Vec<Inline>),
+    Heading {
+        level: HeadingLevel,
+        /// Only populated when heading attributes are enabled, e.g. `# Heading {#id}`,
+        /// or by [`Document::assign_heading_ids`] and [`Document::assign_heading_slugs`]
+        id: Option<String>,
+        classes: Vec<String>,
+        attrs: Vec<(String, Option<String>)>,
+        content: Vec<Inline>,
+    },
+    BlockQuote {
+        kind: Option<BlockQuoteKind>,
+        blocks: Vec<Block>,
+    },
+    CodeBlock {
+        /// The info string of a fenced code block. `None` for indented code blocks.
+        info: Option<String>,
+        content: String,
+    },
+    /// Contains the [`InlineKind::Html`] lines that make up the block
+    HtmlBlock(Vec<Inline>),
+    List {
+        /// The number of the first item if the list is ordered
+        start: Option<u64>,
+        items: Vec<ListItem>,
+    },
+    FootnoteDefinition {
+        label: String,
+        blocks: Vec<Block>,
+    },
+    Table {
+        alignments: Vec<Alignment>,
+        header: TableRow,
+        rows: Vec<TableRow>,
+    },
+    DefinitionList(Vec<DefinitionListItem>),
+    MetadataBlock {
+        kind: MetadataBlockKind,
+        content: String,
+    },
+    Rule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub blocks: Vec<Block>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRow {
+    pub cells: Vec<TableCell>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableCell {
+    pub content: Vec<Inline>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefinitionListItem {
+    Title {
+        content: Vec<Inline>,
+        span: Range<usize>,
+    },
+    Definition {
+        blocks: Vec<Block>,
+        span: Range<usize>,
+    },
+}
+
+/// An inline element together with its location in the source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inline {
+    pub kind: InlineKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InlineKind {
+    Text(String),
+    Code(String),
+    /// A line of html inside a [`BlockKind::HtmlBlock`]
+    Html(String),
+    InlineHtml(String),
+    InlineMath(String),
+    DisplayMath(String),
+    FootnoteReference(String),
+    SoftBreak,
+    HardBreak,
+    TaskListMarker(bool),
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Strikethrough(Vec<Inline>),
+    Superscript(Vec<Inline>),
+    Subscript(Vec<Inline>),
+    Link {
+        link_type: LinkType,
+        destination: String,
+        title: String,
+        id: String,
+        content: Vec<Inline>,
+    },
+    Image {
+        link_type: LinkType,
+        destination: String,
+        title: String,
+        id: String,
+        /// The alt text
+        content: Vec<Inline>,
+    },
+}
+
+/// Metadata at the start of a document, see [`Document::front_matter`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontMatter {
+    /// [`MetadataBlockKind::YamlStyle`] for `---` and [`MetadataBlockKind::PlusesStyle`]
+    /// for `+++`, which is usually TOML
+    pub kind: MetadataBlockKind,
+    /// The text between the delimiters
+    pub content: String,
+}
+
+/// A heading in the outline of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineEntry {
+    pub level: HeadingLevel,
+    /// The content of the heading without any formatting
+    pub text: String,
+    pub id: Option<String>,
+    pub span: Range<usize>,
+    /// The headings of a lower level that follow this heading
+    pub children: Vec<OutlineEntry>,
+}
+
+impl Document {
+    /// Parse markdown with the given pulldown_cmark options
+    pub fn parse(text: &str, options: Options) -> Self {
+        let metadata = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+            | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+        let parse_rest = |rest: &str| {
+            Self::from_events(
+                pulldown_cmark::Parser::new_ext(rest, options.difference(metadata))
+                    .into_offset_iter(),
+            )
+        };
+
+        // pulldown_cmark accepts metadata blocks anywhere, which turns a rule followed by
+        // a setext heading into one. Only a block at the very start is front matter.
+        let may_have_front_matter = (options.contains(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS)
+            && text.starts_with("---"))
+            || (options.contains(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS)
+                && text.starts_with("+++"));
+        if !may_have_front_matter {
+            return parse_rest(text);
+        }
+
+        let mut parser = pulldown_cmark::Parser::new_ext(text, options).into_offset_iter();
+        let mut front_matter = Vec::new();
+        if let Some((event @ Event::Start(Tag::MetadataBlock(_)), span)) = parser.next()
+            && span.start == 0
+        {
+            front_matter.push((event, span));
+            for (event, span) in parser {
+                let is_end = matches!(event, Event::End(TagEnd::MetadataBlock(_)));
+                front_matter.push((event, span));
+                if is_end {
+                    break;
+                }
+            }
+        }
+        let Some(front_matter_end) = front_matter.first().map(|(_, span)| span.end) else {
+            return parse_rest(text);
+        };
+
+        // The front matter is replaced with whitespace so that the spans of the rest of
+        // the document still point into the text
+        let rest: String = text[..front_matter_end]
+            .chars()
+            .flat_map(|c| {
+                let blank = if c == '\n' { '\n' } else { ' ' };
+                std::iter::repeat_n(blank, c.len_utf8())
+            })
+            .chain(text[front_matter_end..].chars())
+            .collect();
+
+        let mut document = Self::from_events(front_matter);
+        document.blocks.extend(parse_rest(&rest).blocks);
+        document
+    }
+
+    /// Build a document from an already existing event stream, e.g. one that has been
+    /// filtered. Events that are out of place are handled leniently: end tags without a
+    /// start tag are skipped, containers without their parent keep their content and
+    /// blocks within inline content are flattened into it.
+    pub fn from_events<'e>(events: impl IntoIterator<Item = (Event<'e>, Range<usize>)>) -> Self {
+        let mut builder = Builder {
+            events: events.into_iter().peekable(),
+        };
+
+        // An end tag without a start tag ends the blocks early, continue after it
+        let mut blocks = Vec::new();
+        while builder.events.peek().is_some() {
+            blocks.extend(builder.blocks());
+        }
+
+        Self { blocks }
+    }
+
+    /// Convert the document back into a flat event stream. The spans of the events
+    /// point into the text the document was originally parsed from. The text of code and
+    /// metadata blocks has the span of the whole block.
+    pub fn events(&self) -> Vec<(Event<'_>, Range<usize>)> {
+        let mut events = Vec::new();
+        for block in &self.blocks {
+            block.push_events(&mut events);
+        }
+        events
+    }
+
+    /// Give every heading without an id the id `heading-<n>` where `n` is the position
+    /// of the heading in the document starting from 1.
+    pub fn assign_heading_ids(&mut self) {
+        let mut headings = Vec::new();
+        headings_mut(&mut self.blocks, &mut headings);
+
+        let mut used = headings
+            .iter()
+            .filter_map(|block| match &block.kind {
+                BlockKind::Heading { id, .. } => id.clone(),
+                _ => None,
+            })
+            .collect::<std::collections::HashSet<_>>();
+
+        for (i, block) in headings.into_iter().enumerate() {
+            if let BlockKind::Heading { id: id @ None, .. } = &mut block.kind {
+                let mut generated = format!("heading-{}", i + 1);
+                let mut suffix = 0;
+                while used.contains(&generated) {
+                    suffix += 1;
+                    generated = format!("heading-{}-{suffix}", i + 1);
+                }
+
+                used.insert(generated.clone());
+                *id = Some(generated);
+            }
+        }
+    }
+
+    /// Give every heading without an id a slug derived from its text the same way
+    /// GitHub does, e.g. `## Installation Guide` gets the id `installation-guide`.
+    /// Duplicates are suffixed with `-1`, `-2` and so on.
+    pub fn assign_heading_slugs(&mut self) {
+        let mut headings = Vec::new();
+        headings_mut(&mut self.blocks, &mut headings);
+
+        // Number of times a slug has been seen. Explicit ids are reserved
+        let mut occurrences = headings
+            .iter()
+            .filter_map(|block| match &block.kind {
+                BlockKind::Heading { id, .. } => id.clone().map(|id| (id, 0)),
+                _ => None,
+            })
+            .collect::<std::collections::HashMap<_, usize>>();
+
+        for block in headings {
+            if let BlockKind::Heading {
+                id: id @ None,
+                content,
+                ..
+            } = &mut block.kind
+            {
+                let original = slugify(&plain_text(content));
+                let mut slug = original.clone();
+                while occurrences.contains_key(&slug) {
+                    let count = occurrences.entry(original.clone()).or_default();
+                    *count += 1;
+                    slug = format!("{original}-{count}");
+                }
+
+                occurrences.insert(slug.clone(), 0);
+                *id = Some(slug);
+            }
+        }
+    }
+
+    /// The headings of the document as a tree, including the ones within block quotes
+    /// and lists.
+    pub fn outline(&self) -> Vec<OutlineEntry> {
+        let mut headings = Vec::new();
+        headings_ref(&self.blocks, &mut headings);
+
+        // The entries that can still receive children, ordered by level
+        let mut stack: Vec<OutlineEntry> = Vec::new();
+        let mut outline = Vec::new();
+
+        for block in headings {
+            let BlockKind::Heading {
+                level, id, content, ..
+            } = &block.kind
+            else {
+                continue;
+            };
+
+            while stack.last().is_some_and(|last| last.level >= *level) {
+                pop_outline_entry(&mut stack, &mut outline);
+            }
+
+            stack.push(OutlineEntry {
+                level: *level,
+                text: plain_text(content),
+                id: id.clone(),
+                span: block.span.clone(),
+                children: Vec::new(),
+            });
+        }
+
+        while !stack.is_empty() {
+            pop_outline_entry(&mut stack, &mut outline);
+        }
+
+        outline
+    }
+
+    /// The front matter of the document. Only parsed when
+    /// [`Options::ENABLE_YAML_STYLE_METADATA_BLOCKS`] or
+    /// [`Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS`] is enabled and the first line
+    /// of the text starts it.
+    pub fn front_matter(&self) -> Option<FrontMatter> {
+        match &self.blocks.first()?.kind {
+            BlockKind::MetadataBlock { kind, content } => Some(FrontMatter {
+                kind: *kind,
+                content: content.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Move the footnote definitions to the end of the document below a rule, the way
+    /// GitHub shows them. The labels of the footnotes are replaced with their number in
+    /// the order they are first referenced. Definitions that are never referenced are
+    /// numbered last.
+    ///
+    /// ```
+    /// # use egui_commonmark_backend::document::*;
+    /// let text = "[^b]: B\n\nFirst[^a] second[^b]\n\n[^a]: A";
+    /// let mut document = Document::parse(text, Options::ENABLE_FOOTNOTES);
+    /// document.gather_footnotes();
+    ///
+    /// assert!(matches!(document.blocks[1].kind, BlockKind::Rule));
+    ///
+    /// // `a` is referenced first so it becomes footnote 1
+    /// let BlockKind::FootnoteDefinition { label, blocks } = &document.blocks[2].kind else {
+    ///     panic!("expected a footnote definition");
+    /// };
+    /// assert_eq!(label, "1");
+    /// assert!(matches!(&blocks[0].kind, BlockKind::Paragraph(content) if plain_text(content) == "A"));
+    /// ```
+    pub fn gather_footnotes(&mut self) {
+        let mut definitions = Vec::new();
+        take_footnote_definitions(&mut self.blocks, &mut definitions);
+        if definitions.is_empty() {
+            return;
+        }
+
+        let defined = definitions
+            .iter()
+            .filter_map(|block| match &block.kind {
+                BlockKind::FootnoteDefinition { label, .. } => Some(label.clone()),
+                _ => None,
+            })
+            .collect::<std::collections::HashSet<_>>();
+
+        // Definitions may reference other footnotes, so they are included when looking
+        // for the first references
+        let mut numbers = std::collections::HashMap::new();
+        let mut first_reference = |label: &mut String| {
+            if defined.contains(label.as_str()) {
+                let next = numbers.len() + 1;
+                numbers.entry(label.clone()).or_insert(next);
+            }
+        };
+        footnote_references_mut(&mut self.blocks, &mut first_reference);
+        footnote_references_mut(&mut definitions, &mut first_reference);
+
+        // Unreferenced definitions keep their order in the source
+        for definition in &definitions {
+            if let BlockKind::FootnoteDefinition { label, .. } = &definition.kind {
+                let next = numbers.len() + 1;
+                numbers.entry(label.clone()).or_insert(next);
+            }
+        }
+
+        definitions.sort_by_key(|block| match &block.kind {
+            BlockKind::FootnoteDefinition { label, .. } => numbers[label],
+            _ => 0,
+        });
+
+        let end = self.blocks.last().map_or(0, |block| block.span.end);
+        self.blocks.push(Block {
+            kind: BlockKind::Rule,
+            span: end..end,
+        });
+        for definition in &mut definitions {
+            if let BlockKind::FootnoteDefinition { label, .. } = &mut definition.kind {
+                *label = numbers[label.as_str()].to_string();
+            }
+        }
+        self.blocks.append(&mut definitions);
+        footnote_references_mut(&mut self.blocks, &mut |label| {
+            if let Some(number) = numbers.get(label.as_str()) {
+                *label = number.to_string();
+            }
+        });
+    }
+
+    /// The blocks of the definition of the footnote `label`, e.g. `note` for `[^note]: Text`
+    pub fn footnote_definition(&self, label: &str) -> Option<&[Block]> {
+        footnote_definition(&self.blocks, label)
+    }
+}
+
+fn footnote_definition<'a>(blocks: &'a [Block], label: &str) -> Option<&'a [Block]> {
+    blocks.iter().find_map(|block| match &block.kind {
+        BlockKind::FootnoteDefinition {
+            label: definition,
+            blocks,
+        } if definition == label => Some(blocks.as_slice()),
+        BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+            footnote_definition(blocks, label)
+        }
+        BlockKind::List { items, .. } => items
+            .iter()
+            .find_map(|item| footnote_definition(&item.blocks, label)),
+        BlockKind::DefinitionList(items) => items.iter().find_map(|item| match item {
+            DefinitionListItem::Definition { blocks, .. } => footnote_definition(blocks, label),
+            DefinitionListItem::Title { .. } => None,
+        }),
+        _ => None,
+    })
+}
+
+/// Remove the footnote definitions from the blocks, including the ones within block
+/// quotes and lists
+fn take_footnote_definitions(blocks: &mut Vec<Block>, definitions: &mut Vec<Block>) {
+    for mut block in std::mem::take(blocks) {
+        match &mut block.kind {
+            BlockKind::FootnoteDefinition { .. } => {
+                definitions.push(block);
+                continue;
+            }
+            BlockKind::BlockQuote { blocks, .. } => take_footnote_definitions(blocks, definitions),
+            BlockKind::List { items, .. } => {
+                for item in items {
+                    take_footnote_definitions(&mut item.blocks, definitions);
+                }
+            }
+            BlockKind::DefinitionList(items) => {
+                for item in items {
+                    if let DefinitionListItem::Definition { blocks, .. } = item {
+                        take_footnote_definitions(blocks, definitions);
+                    }
+                }
+            }
+            _ => {}
+        }
+        blocks.push(block);
+    }
+}
+
+/// Call `f` with the label of every footnote reference in the blocks, in document order
+fn footnote_references_mut(blocks: &mut [Block], f: &mut impl FnMut(&mut String)) {
+    for block in blocks {
+        match &mut block.kind {
+            BlockKind::Paragraph(content)
+            | BlockKind::Plain(content)
+            | BlockKind::Heading { content, .. } => inline_footnote_references_mut(content, f),
+            BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+                footnote_references_mut(blocks, f);
+            }
+            BlockKind::List { items, .. } => {
+                for item in items {
+                    footnote_references_mut(&mut item.blocks, f);
+                }
+            }
+            BlockKind::Table { header, rows, .. } => {
+                for row in std::iter::once(header).chain(rows) {
+                    for cell in &mut row.cells {
+                        inline_footnote_references_mut(&mut cell.content, f);
+                    }
+                }
+            }
+            BlockKind::DefinitionList(items) => {
+                for item in items {
+                    match item {
+                        DefinitionListItem::Title { content, .. } => {
+                            inline_footnote_references_mut(content, f);
+                        }
+                        DefinitionListItem::Definition { blocks, .. } => {
+                            footnote_references_mut(blocks, f);
+                        }
+                    }
+                }
+            }
+            BlockKind::CodeBlock { .. }
+            | BlockKind::HtmlBlock(_)
+            | BlockKind::MetadataBlock { .. }
+            | BlockKind::Rule => {}
+        }
+    }
+}
+
+fn inline_footnote_references_mut(inlines: &mut [Inline], f: &mut impl FnMut(&mut String)) {
+    for inline in inlines {
+        match &mut inline.kind {
+            InlineKind::FootnoteReference(label) => f(label),
+            InlineKind::Emphasis(content)
+            | InlineKind::Strong(content)
+            | InlineKind::Strikethrough(content)
+            | InlineKind::Superscript(content)
+            | InlineKind::Subscript(content)
+            | InlineKind::Link { content, .. }
+            | InlineKind::Image { content, .. } => inline_footnote_references_mut(content, f),
+            _ => {}
+        }
+    }
+}
+
+fn pop_outline_entry(stack: &mut Vec<OutlineEntry>, outline: &mut Vec<OutlineEntry>) {
+    if let Some(entry) = stack.pop() {
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(entry);
+        } else {
+            outline.push(entry);
+        }
+    }
+}
+
+/// Collect the headings in document order
+fn headings_ref<'a>(blocks: &'a [Block], headings: &mut Vec<&'a Block>) {
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Heading { .. } => headings.push(block),
+            BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+                headings_ref(blocks, headings);
+            }
+            BlockKind::List { items, .. } => {
+                for item in items {
+                    headings_ref(&item.blocks, headings);
+                }
+            }
+            BlockKind::DefinitionList(items) => {
+                for item in items {
+                    if let DefinitionListItem::Definition { blocks, .. } = item {
+                        headings_ref(blocks, headings);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collect the headings in document order
+fn headings_mut<'a>(blocks: &'a mut [Block], headings: &mut Vec<&'a mut Block>) {
+    for block in blocks {
+        if matches!(block.kind, BlockKind::Heading { .. }) {
+            headings.push(block);
+            continue;
+        }
+
+        match &mut block.kind {
+            BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+                headings_mut(blocks, headings);
+            }
+            BlockKind::List { items, .. } => {
+                for item in items {
+                    headings_mut(&mut item.blocks, headings);
+                }
+            }
+            BlockKind::DefinitionList(items) => {
+                for item in items {
+                    if let DefinitionListItem::Definition { blocks, .. } = item {
+                        headings_mut(blocks, headings);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Convert the text of a heading into an anchor the same way as GitHub. The text is
+/// lowercased, everything but letters, combining marks, digits, `-` and `_` is removed
+/// and spaces are replaced with `-`.
+///
+/// ```
+/// # use egui_commonmark_backend::document::slugify;
+/// assert_eq!(slugify("Installation Guide: Linux & macOS"), "installation-guide-linux--macos");
+/// ```
+pub fn slugify(text: &str) -> String {
+    use unicode_general_category::{GeneralCategory::*, get_general_category};
+
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' => Some(c),
+            c => match get_general_category(c) {
+                UppercaseLetter | LowercaseLetter | TitlecaseLetter | ModifierLetter
+                | OtherLetter | NonspacingMark | SpacingMark | EnclosingMark | DecimalNumber
+                | ConnectorPunctuation => Some(c),
+                _ => None,
+            },
+        })
+        .collect()
+}
+
+/// The text of the inlines without any formatting
+pub fn plain_text(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    for inline in inlines {
+        inline.push_plain_text(&mut text);
+    }
+    text
+}
+
+/// The attributes of a fenced code block's info string such as
+/// ```` ```rust {1,3-5} title="main.rs" showLineNumbers ````
+///
+/// ```
+/// # use egui_commonmark_backend::document::CodeBlockInfo;
+/// let info = CodeBlockInfo::parse(r#"rust {1,3-5} title="main.rs" showLineNumbers"#);
+///
+/// assert_eq!(info.language.as_deref(), Some("rust"));
+/// assert_eq!(info.title.as_deref(), Some("main.rs"));
+/// assert!(info.show_line_numbers);
+/// assert!(info.is_highlighted(4) && !info.is_highlighted(2));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CodeBlockInfo {
+    /// The first word of the info string unless it is an attribute
+    pub language: Option<String>,
+    /// Lines to highlight, starting from 1. Given as `{1,3-5}`
+    pub highlighted_lines: Vec<RangeInclusive<usize>>,
+    /// Given as `title="main.rs"`
+    pub title: Option<String>,
+    /// Given as `showLineNumbers`
+    pub show_line_numbers: bool,
+    /// Words that are not recognized, e.g. `ignore` in `rust ignore`
+    pub flags: Vec<String>,
+}
+
+impl CodeBlockInfo {
+    pub fn parse(info: &str) -> Self {
+        let mut result = Self::default();
+
+        for (i, word) in info_words(info).into_iter().enumerate() {
+            if let Some(ranges) = word.strip_prefix('{').and_then(|w| w.strip_suffix('}')) {
+                result
+                    .highlighted_lines
+                    .extend(ranges.split(',').filter_map(|range| {
+                        let range = range.trim();
+                        let (start, end) = range.split_once('-').unwrap_or((range, range));
+                        Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+                    }));
+            } else if let Some((key, value)) = word.split_once('=') {
+                if key == "title" {
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                        .unwrap_or(value);
+                    result.title = Some(value.to_owned());
+                }
+            } else if word.eq_ignore_ascii_case("showLineNumbers") {
+                result.show_line_numbers = true;
+            } else if i == 0 {
+                result.language = Some(word.to_owned());
+            } else {
+                result.flags.push(word.to_owned());
+            }
+        }
+
+        result
+    }
+
+    /// Whether the line, starting from 1, should be highlighted
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted_lines
+            .iter()
+            .any(|range| range.contains(&line))
+    }
+}
+
+/// Split an info string on whitespace outside of quotes and braces
+fn info_words(info: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut closing = None;
+
+    for (i, c) in info.char_indices() {
+        match closing {
+            Some(close) if c == close => closing = None,
+            Some(_) => {}
+            None if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    words.push(&info[start..i]);
+                }
+            }
+            None => {
+                start.get_or_insert(i);
+                closing = match c {
+                    '"' => Some('"'),
+                    '\'' => Some('\''),
+                    '{' => Some('}'),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    if let Some(start) = start {
+        words.push(&info[start..]);
+    }
+
+    words
+}
+
+/// Recursive descent over the event stream where every container consumes its own
+/// end tag.
+struct Builder<I: Iterator> {
+    events: std::iter::Peekable<I>,
+}
+
+impl<'e, I: Iterator<Item = (Event<'e>, Range<usize>)>> Builder<I> {
+    fn blocks(&mut self) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut plain = Vec::new();
+
+        while let Some((event, span)) = self.events.next() {
+            match event {
+                Event::End(_) => break,
+                // The content of containers whose parent is missing from the events
+                Event::Start(
+                    Tag::Item | Tag::TableHead | Tag::TableRow | Tag::DefinitionListDefinition,
+                ) => {
+                    flush_plain(&mut blocks, &mut plain);
+                    blocks.extend(self.blocks());
+                }
+                Event::Start(tag) if is_block_tag(&tag) => {
+                    flush_plain(&mut blocks, &mut plain);
+                    blocks.push(self.block(tag, span));
+                }
+                Event::Rule => {
+                    flush_plain(&mut blocks, &mut plain);
+                    blocks.push(Block {
+                        kind: BlockKind::Rule,
+                        span,
+                    });
+                }
+                event => plain.extend(self.inline(event, span)),
+            }
+        }
+
+        flush_plain(&mut blocks, &mut plain);
+        blocks
+    }
+
+    fn block(&mut self, tag: Tag<'e>, span: Range<usize>) -> Block {
+        let kind = match tag {
+            Tag::Paragraph => BlockKind::Paragraph(self.inlines()),
+            Tag::Heading {
+                level,
+                id,
+                classes,
+                attrs,
+            } => BlockKind::Heading {
+                level,
+                id: id.map(CowStr::into_string),
+                classes: classes.into_iter().map(CowStr::into_string).collect(),
+                attrs: attrs
+                    .into_iter()
+                    .map(|(k, v)| (k.into_string(), v.map(CowStr::into_string)))
+                    .collect(),
+                content: self.inlines(),
+            },
+            Tag::BlockQuote(kind) => BlockKind::BlockQuote {
+                kind,
+                blocks: self.blocks(),
+            },
+            Tag::CodeBlock(kind) => BlockKind::CodeBlock {
+                info: match kind {
+                    CodeBlockKind::Fenced(info) => Some(info.into_string()),
+                    CodeBlockKind::Indented => None,
+                },
+                content: self.text(),
+            },
+            Tag::HtmlBlock => BlockKind::HtmlBlock(self.inlines()),
+            Tag::List(start) => {
+                let mut items = Vec::new();
+                while let Some((event, span)) = self.events.next() {
+                    match event {
+                        Event::Start(Tag::Item) => items.push(ListItem {
+                            blocks: self.blocks(),
+                            span,
+                        }),
+                        _ => break,
+                    }
+                }
+                BlockKind::List { start, items }
+            }
+            Tag::FootnoteDefinition(label) => BlockKind::FootnoteDefinition {
+                label: label.into_string(),
+                blocks: self.blocks(),
+            },
+            Tag::Table(alignments) => {
+                let mut header = TableRow {
+                    cells: Vec::new(),
+                    span: span.start..span.start,
+                };
+                let mut rows = Vec::new();
+                while let Some((event, span)) = self.events.next() {
+                    match event {
+                        Event::Start(Tag::TableHead) => header = self.table_row(span),
+                        Event::Start(Tag::TableRow) => rows.push(self.table_row(span)),
+                        _ => break,
+                    }
+                }
+                BlockKind::Table {
+                    alignments,
+                    header,
+                    rows,
+                }
+            }
+            Tag::DefinitionList => {
+                let mut items = Vec::new();
+                while let Some((event, span)) = self.events.next() {
+                    match event {
+                        Event::Start(Tag::DefinitionListTitle) => {
+                            items.push(DefinitionListItem::Title {
+                                content: self.inlines(),
+                                span,
+                            })
+                        }
+                        Event::Start(Tag::DefinitionListDefinition) => {
+                            items.push(DefinitionListItem::Definition {
+                                blocks: self.blocks(),
+                                span,
+                            })
+                        }
+                        _ => break,
+                    }
+                }
+                BlockKind::DefinitionList(items)
+            }
+            Tag::MetadataBlock(kind) => BlockKind::MetadataBlock {
+                kind,
+                content: self.text(),
+            },
+            // Table cells and definition list titles without their parent, see `blocks`
+            _ => BlockKind::Plain(self.inlines()),
+        };
+
+        Block { kind, span }
+    }
+
+    fn table_row(&mut self, span: Range<usize>) -> TableRow {
+        let mut cells = Vec::new();
+        while let Some((event, span)) = self.events.next() {
+            match event {
+                Event::Start(Tag::TableCell) => cells.push(TableCell {
+                    content: self.inlines(),
+                    span,
+                }),
+                _ => break,
+            }
+        }
+        TableRow { cells, span }
+    }
+
+    fn inlines(&mut self) -> Vec<Inline> {
+        let mut inlines = Vec::new();
+        while let Some((event, span)) = self.events.next() {
+            match event {
+                Event::End(_) => break,
+                // Blocks where only inline content is expected are flattened
+                Event::Start(tag) if is_block_tag(&tag) => inlines.extend(self.inlines()),
+                event => inlines.extend(self.inline(event, span)),
+            }
+        }
+        inlines
+    }
+
+    /// `None` for events that are not inline, which the callers handle
+    fn inline(&mut self, event: Event<'e>, span: Range<usize>) -> Option<Inline> {
+        let kind = match event {
+            Event::Text(text) => InlineKind::Text(text.into_string()),
+            Event::Code(text) => InlineKind::Code(text.into_string()),
+            Event::Html(text) => InlineKind::Html(text.into_string()),
+            Event::InlineHtml(text) => InlineKind::InlineHtml(text.into_string()),
+            Event::InlineMath(text) => InlineKind::InlineMath(text.into_string()),
+            Event::DisplayMath(text) => InlineKind::DisplayMath(text.into_string()),
+            Event::FootnoteReference(label) => InlineKind::FootnoteReference(label.into_string()),
+            Event::SoftBreak => InlineKind::SoftBreak,
+            Event::HardBreak => InlineKind::HardBreak,
+            Event::TaskListMarker(checked) => InlineKind::TaskListMarker(checked),
+            Event::Start(Tag::Emphasis) => InlineKind::Emphasis(self.inlines()),
+            Event::Start(Tag::Strong) => InlineKind::Strong(self.inlines()),
+            Event::Start(Tag::Strikethrough) => InlineKind::Strikethrough(self.inlines()),
+            Event::Start(Tag::Superscript) => InlineKind::Superscript(self.inlines()),
+            Event::Start(Tag::Subscript) => InlineKind::Subscript(self.inlines()),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => InlineKind::Link {
+                link_type,
+                destination: dest_url.into_string(),
+                title: title.into_string(),
+                id: id.into_string(),
+                content: self.inlines(),
+            },
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => InlineKind::Image {
+                link_type,
+                destination: dest_url.into_string(),
+                title: title.into_string(),
+                id: id.into_string(),
+                content: self.inlines(),
+            },
+            Event::Start(_) | Event::End(_) | Event::Rule => return None,
+        };
+
+        Some(Inline { kind, span })
+    }
+
+    /// Concatenate all text until the end of the current tag
+    fn text(&mut self) -> String {
+        let mut content = String::new();
+        for (event, _) in self.events.by_ref() {
+            match event {
+                Event::Text(text) => content.push_str(&text),
+                Event::End(_) => break,
+                _ => {}
+            }
+        }
+        content
+    }
+}
+
+fn is_block_tag(tag: &Tag) -> bool {
+    !matches!(
+        tag,
+        Tag::Emphasis
+            | Tag::Strong
+            | Tag::Strikethrough
+            | Tag::Superscript
+            | Tag::Subscript
+            | Tag::Link { .. }
+            | Tag::Image { .. }
+    )
+}
+
+fn flush_plain(blocks: &mut Vec<Block>, plain: &mut Vec<Inline>) {
+    if let (Some(first), Some(last)) = (plain.first(), plain.last()) {
+        let span = first.span.start..last.span.end;
+        blocks.push(Block {
+            kind: BlockKind::Plain(std::mem::take(plain)),
+            span,
+        });
+    }
+}
+
+fn borrowed(s: &str) -> CowStr<'_> {
+    CowStr::Borrowed(s)
+}
+
+impl Block {
+    /// Convert the block into a flat event stream. See [`Document::events`]
+    pub fn events(&self) -> Vec<(Event<'_>, Range<usize>)> {
+        let mut events = Vec::new();
+        self.push_events(&mut events);
+        events
+    }
+
+    fn push_events<'a>(&'a self, events: &mut Vec<(Event<'a>, Range<usize>)>) {
+        let span = self.span.clone();
+        let tag = match &self.kind {
+            BlockKind::Plain(inlines) => {
+                push_inlines(inlines, events);
+                return;
+            }
+            BlockKind::Rule => {
+                events.push((Event::Rule, span));
+                return;
+            }
+            BlockKind::Paragraph(_) => Tag::Paragraph,
+            BlockKind::Heading {
+                level,
+                id,
+                classes,
+                attrs,
+                ..
+            } => Tag::Heading {
+                level: *level,
+                id: id.as_deref().map(borrowed),
+                classes: classes.iter().map(|c| borrowed(c)).collect(),
+                attrs: attrs
+                    .iter()
+                    .map(|(k, v)| (borrowed(k), v.as_deref().map(borrowed)))
+                    .collect(),
+            },
+            BlockKind::BlockQuote { kind, .. } => Tag::BlockQuote(*kind),
+            BlockKind::CodeBlock { info, .. } => Tag::CodeBlock(match info {
+                Some(info) => CodeBlockKind::Fenced(borrowed(info)),
+                None => CodeBlockKind::Indented,
+            }),
+            BlockKind::HtmlBlock(_) => Tag::HtmlBlock,
+            BlockKind::List { start, .. } => Tag::List(*start),
+            BlockKind::FootnoteDefinition { label, .. } => Tag::FootnoteDefinition(borrowed(label)),
+            BlockKind::Table { alignments, .. } => Tag::Table(alignments.clone()),
+            BlockKind::DefinitionList(_) => Tag::DefinitionList,
+            BlockKind::MetadataBlock { kind, .. } => Tag::MetadataBlock(*kind),
+        };
+
+        let end = tag.to_end();
+        events.push((Event::Start(tag), span.clone()));
+
+        match &self.kind {
+            BlockKind::Paragraph(inlines)
+            | BlockKind::Heading {
+                content: inlines, ..
+            }
+            | BlockKind::HtmlBlock(inlines) => push_inlines(inlines, events),
+            BlockKind::BlockQuote { blocks, .. } | BlockKind::FootnoteDefinition { blocks, .. } => {
+                for block in blocks {
+                    block.push_events(events);
+                }
+            }
+            BlockKind::CodeBlock { content, .. } | BlockKind::MetadataBlock { content, .. } => {
+                if !content.is_empty() {
+                    events.push((Event::Text(borrowed(content)), span.clone()));
+                }
+            }
+            BlockKind::List { items, .. } => {
+                for item in items {
+                    events.push((Event::Start(Tag::Item), item.span.clone()));
+                    for block in &item.blocks {
+                        block.push_events(events);
+                    }
+                    events.push((Event::End(TagEnd::Item), item.span.clone()));
+                }
+            }
+            BlockKind::Table { header, rows, .. } => {
+                header.push_events(Tag::TableHead, events);
+                for row in rows {
+                    row.push_events(Tag::TableRow, events);
+                }
+            }
+            BlockKind::DefinitionList(items) => {
+                for item in items {
+                    match item {
+                        DefinitionListItem::Title { content, span } => {
+                            events.push((Event::Start(Tag::DefinitionListTitle), span.clone()));
+                            push_inlines(content, events);
+                            events.push((Event::End(TagEnd::DefinitionListTitle), span.clone()));
+                        }
+                        DefinitionListItem::Definition { blocks, span } => {
+                            events
+                                .push((Event::Start(Tag::DefinitionListDefinition), span.clone()));
+                            for block in blocks {
+                                block.push_events(events);
+                            }
+                            events
+                                .push((Event::End(TagEnd::DefinitionListDefinition), span.clone()));
+                        }
+                    }
+                }
+            }
+            BlockKind::Plain(_) | BlockKind::Rule => {}
+        }
+
+        events.push((Event::End(end), span));
+    }
+}
+
+impl TableRow {
+    fn push_events<'a>(&'a self, tag: Tag<'a>, events: &mut Vec<(Event<'a>, Range<usize>)>) {
+        let end = tag.to_end();
+        events.push((Event::Start(tag), self.span.clone()));
+        for cell in &self.cells {
+            events.push((Event::Start(Tag::TableCell), cell.span.clone()));
+            push_inlines(&cell.content, events);
+            events.push((Event::End(TagEnd::TableCell), cell.span.clone()));
+        }
+        events.push((Event::End(end), self.span.clone()));
+    }
+}
+
+fn push_inlines<'a>(inlines: &'a [Inline], events: &mut Vec<(Event<'a>, Range<usize>)>) {
+    for inline in inlines {
+        inline.push_events(events);
+    }
+}
+
+impl Inline {
+    fn push_plain_text(&self, text: &mut String) {
+        match &self.kind {
+            InlineKind::Text(s)
+            | InlineKind::Code(s)
+            | InlineKind::InlineMath(s)
+            | InlineKind::DisplayMath(s) => text.push_str(s),
+            InlineKind::SoftBreak | InlineKind::HardBreak => text.push(' '),
+            InlineKind::Html(_)
+            | InlineKind::InlineHtml(_)
+            | InlineKind::FootnoteReference(_)
+            | InlineKind::TaskListMarker(_) => {}
+            InlineKind::Emphasis(content)
+            | InlineKind::Strong(content)
+            | InlineKind::Strikethrough(content)
+            | InlineKind::Superscript(content)
+            | InlineKind::Subscript(content)
+            | InlineKind::Link { content, .. }
+            | InlineKind::Image { content, .. } => {
+                for inline in content {
+                    inline.push_plain_text(text);
+                }
+            }
+        }
+    }
+
+    fn push_events<'a>(&'a self, events: &mut Vec<(Event<'a>, Range<usize>)>) {
+        let span = self.span.clone();
+        let (tag, content) = match &self.kind {
+            InlineKind::Text(text) => return events.push((Event::Text(borrowed(text)), span)),
+            InlineKind::Code(text) => return events.push((Event::Code(borrowed(text)), span)),
+            InlineKind::Html(text) => return events.push((Event::Html(borrowed(text)), span)),
+            InlineKind::InlineHtml(text) => {
+                return events.push((Event::InlineHtml(borrowed(text)), span));
+            }
+            InlineKind::InlineMath(text) => {
+                return events.push((Event::InlineMath(borrowed(text)), span));
+            }
+            InlineKind::DisplayMath(text) => {
+                return events.push((Event::DisplayMath(borrowed(text)), span));
+            }
+            InlineKind::FootnoteReference(label) => {
+                return events.push((Event::FootnoteReference(borrowed(label)), span));
+            }
+            InlineKind::SoftBreak => return events.push((Event::SoftBreak, span)),
+            InlineKind::HardBreak => return events.push((Event::HardBreak, span)),
+            InlineKind::TaskListMarker(checked) => {
+                return events.push((Event::TaskListMarker(*checked), span));
+            }
+            InlineKind::Emphasis(content) => (Tag::Emphasis, content),
+            InlineKind::Strong(content) => (Tag::Strong, content),
+            InlineKind::Strikethrough(content) => (Tag::Strikethrough, content),
+            InlineKind::Superscript(content) => (Tag::Superscript, content),
+            InlineKind::Subscript(content) => (Tag::Subscript, content),
+            InlineKind::Link {
+                link_type,
+                destination,
+                title,
+                id,
+                content,
+            } => (
+                Tag::Link {
+                    link_type: *link_type,
+                    dest_url: borrowed(destination),
+                    title: borrowed(title),
+                    id: borrowed(id),
+                },
+                content,
+            ),
+            InlineKind::Image {
+                link_type,
+                destination,
+                title,
+                id,
+                content,
+            } => (
+                Tag::Image {
+                    link_type: *link_type,
+                    dest_url: borrowed(destination),
+                    title: borrowed(title),
+                    id: borrowed(id),
+                },
+                content,
+            ),
+        };
+
+        let end = tag.to_end();
+        events.push((Event::Start(tag), span.clone()));
+        push_inlines(content, events);
+        events.push((Event::End(end), span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: Options = Options::ENABLE_TABLES
+        .union(Options::ENABLE_FOOTNOTES)
+        .union(Options::ENABLE_STRIKETHROUGH)
+        .union(Options::ENABLE_TASKLISTS)
+        .union(Options::ENABLE_DEFINITION_LIST)
+        .union(Options::ENABLE_GFM)
+        .union(Options::ENABLE_MATH)
+        .union(Options::ENABLE_SUPERSCRIPT)
+        .union(Options::ENABLE_SUBSCRIPT)
+        .union(Options::ENABLE_HEADING_ATTRIBUTES);
+
+    const MARKDOWN: &str = r#"# Heading {#id .class}
+
+Some *emphasis*, **strong**, ~~strike~~, ^sup^, ~sub~, `code`, $x^2$ and a [link](https://example.org "title").
+A soft break and a hard\
+break with <b>inline html</b> and ![an image](img.png)[^note].
+
+> [!NOTE]
+> A quote
+>
+> > Nested
+
+- tight
+- list
+  1. nested
+  2. ordered
+
+* [x] done
+
+  loose item with a paragraph
+
+```rust
+fn main() {}
+```
+
+    indented code
+
+<div>
+html block
+</div>
+
+| a | b |
+|:--|--:|
+| 1 | 2 |
+
+Term
+: Definition
+
+$$
+display math
+$$
+
+---
+
+[^note]: The footnote
+"#;
+
+    fn pulldown_events(text: &str, options: Options) -> Vec<(Event<'_>, Range<usize>)> {
+        pulldown_cmark::Parser::new_ext(text, options)
+            .into_offset_iter()
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let document = Document::parse(MARKDOWN, ALL);
+
+        // Code blocks only keep the span of the whole block
+        let mut expected = pulldown_events(MARKDOWN, ALL);
+        let mut code_block_span = None;
+        for (event, span) in &mut expected {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => code_block_span = Some(span.clone()),
+                Event::End(TagEnd::CodeBlock) => code_block_span = None,
+                Event::Text(_) => {
+                    if let Some(code_block_span) = &code_block_span {
+                        *span = code_block_span.clone();
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(document.events(), expected);
+
+        let rebuilt = Document::from_events(document.events());
+        assert_eq!(rebuilt, document);
+    }
+
+    #[test]
+    fn stray_end_tags_are_skipped() {
+        let mut events = pulldown_events("First\n\nSecond", Options::empty());
+        events.insert(3, (Event::End(TagEnd::BlockQuote(None)), 5..5));
+        events.insert(0, (Event::End(TagEnd::Emphasis), 0..0));
+
+        let document = Document::from_events(events);
+        assert_eq!(
+            document,
+            Document::parse("First\n\nSecond", Options::empty())
+        );
+    }
+
+    #[test]
+    fn filtered_events() {
+        let text = "- item\n\n| a |\n|---|\n| b |\n\nTerm\n: Definition\n";
+        let events = pulldown_events(text, ALL).into_iter().filter(|(event, _)| {
+            !matches!(
+                event,
+                Event::Start(Tag::List(_) | Tag::Table(_) | Tag::DefinitionList)
+                    | Event::End(TagEnd::List(_) | TagEnd::Table | TagEnd::DefinitionList)
+            )
+        });
+
+        let document = Document::from_events(events);
+        let text: Vec<_> = document
+            .blocks
+            .iter()
+            .map(|block| match &block.kind {
+                BlockKind::Plain(content) | BlockKind::Paragraph(content) => plain_text(content),
+                kind => panic!("unexpected block {kind:?}"),
+            })
+            .collect();
+        assert_eq!(text, ["item", "a", "b", "Term", "Definition"]);
+    }
+
+    #[test]
+    fn blocks_within_inline_content() {
+        let events = vec![
+            (Event::Start(Tag::Paragraph), 0..0),
+            (Event::Text("a".into()), 0..0),
+            (Event::Start(Tag::BlockQuote(None)), 0..0),
+            (Event::Start(Tag::Paragraph), 0..0),
+            (Event::Text("b".into()), 0..0),
+            (Event::End(TagEnd::Paragraph), 0..0),
+            (Event::End(TagEnd::BlockQuote(None)), 0..0),
+            (Event::Rule, 0..0),
+            (Event::End(TagEnd::Paragraph), 0..0),
+        ];
+
+        let document = Document::from_events(events);
+        let [
+            Block {
+                kind: BlockKind::Paragraph(content),
+                ..
+            },
+        ] = document.blocks.as_slice()
+        else {
+            panic!("{document:?}");
+        };
+        assert_eq!(plain_text(content), "ab");
+    }
+
+    const FRONT_MATTER: Options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        .union(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+
+    #[test]
+    fn front_matter_at_the_start() {
+        let text = "---\ntitle: Hello\n---\n# Heading\n";
+        let document = Document::parse(text, FRONT_MATTER);
+
+        assert_eq!(
+            document.front_matter(),
+            Some(FrontMatter {
+                kind: MetadataBlockKind::YamlStyle,
+                content: "title: Hello\n".to_owned(),
+            })
+        );
+        assert_eq!(document.blocks.len(), 2);
+        assert!(matches!(
+            document.blocks[1].kind,
+            BlockKind::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }
+        ));
+        assert_eq!(&text[document.blocks[1].span.clone()], "# Heading\n");
+    }
+
+    #[test]
+    fn front_matter_is_only_at_the_start() {
+        // A rule followed by a setext heading, not a metadata block
+        let text = "Intro\n\n---\nSome title\n---\n\n+++\nnot = \"toml\"\n+++\n";
+        let document = Document::parse(text, FRONT_MATTER);
+
+        assert_eq!(document.front_matter(), None);
+        assert_eq!(document, Document::parse(text, Options::empty()));
+        assert!(matches!(document.blocks[1].kind, BlockKind::Rule));
+        assert!(matches!(
+            &document.blocks[2].kind,
+            BlockKind::Heading { level: HeadingLevel::H2, content, .. }
+                if plain_text(content) == "Some title"
+        ));
+    }
+
+    #[test]
+    fn front_matter_followed_by_metadata_like_blocks() {
+        let text = "+++\na = 1\n+++\n\nText\n\n---\nSome title\n---\n";
+        let document = Document::parse(text, FRONT_MATTER);
+
+        assert_eq!(
+            document.front_matter().unwrap().kind,
+            MetadataBlockKind::PlusesStyle
+        );
+        assert_eq!(document.blocks.len(), 4);
+        assert!(matches!(document.blocks[2].kind, BlockKind::Rule));
+        assert!(matches!(document.blocks[3].kind, BlockKind::Heading { .. }));
+    }
+
+    #[test]
+    fn outline_tree() {
+        fn shape(entries: &[OutlineEntry]) -> String {
+            entries
+                .iter()
+                .map(|entry| {
+                    if entry.children.is_empty() {
+                        entry.text.clone()
+                    } else {
+                        format!("{}({})", entry.text, shape(&entry.children))
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+
+        let document = Document::parse(
+            "## Z\n# A\n### B\n## C\n#### D\n# E\n> ## F\n- ###### G",
+            Options::empty(),
+        );
+        let outline = document.outline();
+        assert_eq!(shape(&outline), "Z A(B C(D)) E(F(G))");
+        assert_eq!(outline[1].children[0].level, HeadingLevel::H3);
+        assert_eq!(outline[2].children[0].span, 33..38);
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("snake_case and 2nd"), "snake_case-and-2nd");
+        assert_eq!(slugify("Café"), "café");
+        // A decomposed é keeps its combining accent
+        assert_eq!(slugify("Cafe\u{301}"), "cafe\u{301}");
+        assert_eq!(slugify("Größe 日本語"), "größe-日本語");
+        assert_eq!(slugify("🎉 Party ²"), "-party-");
+    }
+
+    #[test]
+    fn duplicate_slugs() {
+        let ids = |text: &str| {
+            let mut document = Document::parse(text, Options::ENABLE_HEADING_ATTRIBUTES);
+            document.assign_heading_slugs();
+            document
+                .outline()
+                .into_iter()
+                .map(|entry| entry.id.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("# A\n# A\n# A"), ["a", "a-1", "a-2"]);
+        // A heading whose slug equals an earlier suffixed one
+        assert_eq!(ids("# A\n# A\n# A 1"), ["a", "a-1", "a-1-1"]);
+        assert_eq!(ids("# A 1\n# A\n# A"), ["a-1", "a", "a-2"]);
+        // Explicit ids are reserved, even when they come later
+        assert_eq!(ids("# A\n# B {#a-1}\n# A"), ["a", "a-1", "a-2"]);
+    }
+
+    #[test]
+    fn gathered_footnotes_are_numbered_by_first_reference() {
+        let text = "[^a] [^c] [^a]\n\n[^b]: B\n\n[^c]: C\n\n[^a]: A [^d]\n\n[^d]: D";
+        let mut document = Document::parse(text, Options::ENABLE_FOOTNOTES);
+        document.gather_footnotes();
+
+        let definitions = document
+            .blocks
+            .iter()
+            .skip_while(|block| !matches!(block.kind, BlockKind::Rule))
+            .skip(1)
+            .map(|block| match &block.kind {
+                BlockKind::FootnoteDefinition { label, blocks } => match &blocks[0].kind {
+                    BlockKind::Paragraph(content) => (label.as_str(), plain_text(content)),
+                    _ => panic!("expected a paragraph"),
+                },
+                _ => panic!("expected a footnote definition"),
+            })
+            .collect::<Vec<_>>();
+
+        // `d` is only referenced from within `a` and `b` is not referenced at all
+        assert_eq!(
+            definitions,
+            [
+                ("1", "A ".to_owned()),
+                ("2", "C".to_owned()),
+                ("3", "D".to_owned()),
+                ("4", "B".to_owned()),
+            ]
+        );
+
+        let references = document
+            .events()
+            .into_iter()
+            .filter_map(|(event, _)| match event {
+                Event::FootnoteReference(label) => Some(label.into_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(references, ["1", "2", "1", "3"]);
+    }
+
+    #[test]
+    fn code_block_info() {
+        let info = CodeBlockInfo::parse(r#"rust {1,3-5} title="my main.rs" ignore"#);
+        assert_eq!(info.language.as_deref(), Some("rust"));
+        assert_eq!(info.highlighted_lines, [1..=1, 3..=5]);
+        assert_eq!(info.title.as_deref(), Some("my main.rs"));
+        assert_eq!(info.flags, ["ignore"]);
+        assert!(!info.show_line_numbers);
+
+        // Attributes in any order and without a language
+        let info = CodeBlockInfo::parse("title='a b' { 2 , 7 - 8 } showlinenumbers");
+        assert_eq!(info.language, None);
+        assert_eq!(info.title.as_deref(), Some("a b"));
+        assert_eq!(info.highlighted_lines, [2..=2, 7..=8]);
+        assert!(info.show_line_numbers);
+
+        let info = CodeBlockInfo::parse("rust title=main.rs");
+        assert_eq!(info.title.as_deref(), Some("main.rs"));
+
+        // Bad ranges are skipped and reversed ranges highlight nothing
+        let info = CodeBlockInfo::parse("rust {a,2-x,-3,4,5-3,}");
+        assert_eq!(info.highlighted_lines.len(), 2);
+        assert!(info.is_highlighted(4));
+        assert!(!(1..=10).any(|line| line != 4 && info.is_highlighted(line)));
+
+        // An unterminated brace swallows the rest of the info string
+        let info = CodeBlockInfo::parse("rust {1,2 title=x");
+        assert!(info.highlighted_lines.is_empty());
+        assert_eq!(info.title, None);
+        assert!(info.flags.is_empty());
+
+        assert_eq!(CodeBlockInfo::parse(""), CodeBlockInfo::default());
+    }
+}
@@ -1,8 +1,21 @@
+use crate::document::CodeBlockInfo;
+use crate::misc::{CommonMarkCache, CommonMarkOptions, DiffLine, diff_language};
+use crate::theme::CommonMarkTheme;
 use egui::{self, NumExt, RichText, Sense, TextBuffer, TextStyle, Ui, Vec2, epaint};
 
 #[inline]
-pub fn rule(ui: &mut Ui, end_line: bool) {
-    ui.add(egui::Separator::default().horizontal());
+pub fn rule(ui: &mut Ui, theme: &CommonMarkTheme, end_line: bool) {
+    if let Some(stroke) = theme.rule_stroke {
+        // The separator has no way of setting the stroke so change the style temporarily
+        let prev = std::mem::replace(
+            &mut ui.visuals_mut().widgets.noninteractive.bg_stroke,
+            stroke,
+        );
+        ui.add(egui::Separator::default().horizontal());
+        ui.visuals_mut().widgets.noninteractive.bg_stroke = prev;
+    } else {
+        ui.add(egui::Separator::default().horizontal());
+    }
     // This does not add a new line, but instead ends the separator
     if end_line {
         newline(ui);
@@ -19,32 +32,32 @@ pub fn newline(ui: &mut Ui) {
     ui.label("\n");
 }
 
-pub fn bullet_point(ui: &mut Ui) {
+pub fn bullet_point(ui: &mut Ui, theme: &CommonMarkTheme) {
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, height_body(ui)),
         Sense::hover(),
     );
     ui.painter().circle_filled(
         rect.center(),
-        rect.height() / 6.0,
-        ui.visuals().strong_text_color(),
+        rect.height() * theme.bullet_radius,
+        theme.list_marker_color(ui),
     );
 }
 
-pub fn bullet_point_hollow(ui: &mut Ui) {
+pub fn bullet_point_hollow(ui: &mut Ui, theme: &CommonMarkTheme) {
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, height_body(ui)),
         Sense::hover(),
     );
     ui.painter().circle(
         rect.center(),
-        rect.height() / 6.0,
+        rect.height() * theme.bullet_radius,
         egui::Color32::TRANSPARENT,
-        egui::Stroke::new(0.6, ui.visuals().strong_text_color()),
+        egui::Stroke::new(0.6, theme.list_marker_color(ui)),
     );
 }
 
-pub fn number_point(ui: &mut Ui, number: &str) {
+pub fn number_point(ui: &mut Ui, theme: &CommonMarkTheme, number: &str) {
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, height_body(ui)),
         Sense::hover(),
@@ -54,16 +67,122 @@ pub fn number_point(ui: &mut Ui, number: &str) {
         egui::Align2::RIGHT_CENTER,
         format!("{number}."),
         TextStyle::Body.resolve(ui.style()),
-        ui.visuals().strong_text_color(),
+        theme.list_marker_color(ui),
     );
 }
 
-#[inline]
-pub fn footnote_start(ui: &mut Ui, note: &str) {
-    ui.label(RichText::new(note).raised().strong().small());
+/// Lays out widgets in a row that is aligned to the center or right. Rows cannot be
+/// aligned before their width is known, so the width of the previous pass is used.
+pub fn aligned_line(ui: &mut Ui, align: egui::Align, add_contents: impl FnOnce(&mut Ui)) {
+    let id = ui.auto_id_with("_aligned_line");
+    let width = ui.data(|d| d.get_temp::<f32>(id));
+    let available = ui.available_width();
+    let offset = width.map_or(0.0, |width| match align {
+        egui::Align::Min => 0.0,
+        egui::Align::Center => (available - width) / 2.0,
+        egui::Align::Max => available - width,
+    });
+
+    let response = ui.horizontal(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        ui.style_mut().wrap_mode = Some(egui::TextWrapMode::Wrap);
+        ui.add_space(offset.at_least(0.0));
+        add_contents(ui);
+    });
+
+    let new_width = response.response.rect.width() - offset.at_least(0.0);
+    if width.is_none_or(|width| (width - new_width).abs() > 0.5) {
+        ui.data_mut(|d| d.insert_temp(id, new_width));
+        ui.ctx().request_discard("aligned html line changed width");
+    }
+}
+
+/// Text within `<kbd>` drawn as a keyboard key
+pub fn kbd(ui: &mut Ui, text: &str) {
+    egui::Frame::new()
+        .fill(ui.visuals().faint_bg_color)
+        .stroke(ui.visuals().widgets.noninteractive.bg_stroke)
+        .corner_radius(3.0)
+        .inner_margin(egui::Margin::symmetric(4, 0))
+        .show(ui, |ui| {
+            ui.label(RichText::new(text).monospace());
+        });
+}
+
+/// An `<img>` with an optional `width` and `height` in pixels. Only one of them is
+/// needed to keep the aspect ratio of the image.
+pub fn html_image(
+    ui: &mut Ui,
+    uri: &str,
+    width: Option<f32>,
+    height: Option<f32>,
+    alt: Option<&str>,
+    options: &CommonMarkOptions,
+) -> egui::Response {
+    let mut image = egui::Image::from_uri(uri);
+    image = match (width, height) {
+        (None, None) => image.fit_to_original_size(1.0),
+        (width, height) => image
+            .fit_to_exact_size(egui::vec2(
+                width.unwrap_or(f32::INFINITY),
+                height.unwrap_or(f32::INFINITY),
+            ))
+            .maintain_aspect_ratio(width.is_none() || height.is_none()),
+    };
+
+    let response = ui.add(image.max_width(options.max_width(ui)));
+    match alt {
+        Some(alt) if !alt.is_empty() && options.show_alt_text_on_hover => {
+            response.on_hover_text_at_pointer(alt)
+        }
+        _ => response,
+    }
 }
 
-pub fn footnote(ui: &mut Ui, text: &str) {
+/// The id of a footnote definition. Clicking a reference to the footnote scrolls to it.
+pub fn footnote_id(label: &str) -> String {
+    format!("fn-{label}")
+}
+
+/// The id of the first reference to a footnote. The "↩" of the definition scrolls to it.
+pub fn footnote_reference_id(label: &str) -> String {
+    format!("fnref-{label}")
+}
+
+/// A reference to a footnote such as `[^1]`. Clicking it scrolls to the definition.
+pub fn footnote_start(
+    ui: &mut Ui,
+    cache: &mut CommonMarkCache,
+    note: &str,
+    scroll_to: &mut Option<String>,
+) -> egui::Response {
+    let response = ui.link(RichText::new(note).raised().strong().small());
+
+    if cache.scroll_to_id_target() == Some(footnote_reference_id(note).as_str()) {
+        response.scroll_to_me(Some(egui::Align::Center));
+        cache.scroll_to_id_target_mut().take();
+    }
+
+    if response.clicked() {
+        scroll_to.replace(footnote_id(note));
+    }
+
+    response
+}
+
+/// The number in front of a footnote definition followed by a "↩" that scrolls back to
+/// the first reference.
+pub fn footnote(
+    ui: &mut Ui,
+    cache: &mut CommonMarkCache,
+    text: &str,
+    scroll_to: &mut Option<String>,
+) {
+    if cache.scroll_to_id_target() == Some(footnote_id(text).as_str()) {
+        ui.scroll_to_cursor(Some(egui::Align::TOP));
+        cache.scroll_to_id_target_mut().take();
+    }
+
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, height_body(ui)),
         Sense::hover(),
@@ -75,6 +194,15 @@ pub fn footnote(ui: &mut Ui, text: &str) {
         TextStyle::Small.resolve(ui.style()),
         ui.visuals().strong_text_color(),
     );
+
+    if ui
+        .link(RichText::new("↩").small())
+        .on_hover_text("Back to reference")
+        .clicked()
+    {
+        scroll_to.replace(footnote_reference_id(text));
+    }
+    ui.label(" ");
 }
 
 fn height_body(ui: &Ui) -> f32 {
@@ -86,72 +214,272 @@ fn width_body_space(ui: &Ui) -> f32 {
     ui.fonts_mut(|f| f.glyph_width(&id, ' '))
 }
 
-/// Enhanced/specialized version of egui's code blocks. This one features copy button and borders
+/// Enhanced/specialized version of egui's code blocks. This one features copy button and
+/// borders as well as the line numbers, highlighted lines and title given in the info string.
+/// The language is shown in the header when [`CommonMarkOptions::code_block_header`] is set
 pub fn code_block<'t>(
     ui: &mut Ui,
+    options: &CommonMarkOptions,
     max_width: f32,
     text: &str,
+    info: &CodeBlockInfo,
+    language: Option<&str>,
     layouter: &'t mut dyn FnMut(&Ui, &dyn TextBuffer, f32) -> std::sync::Arc<egui::Galley>,
 ) {
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 0.0;
+        let width = max_width.at_most(ui.available_width());
+        let corner_radius = ui.style().noninteractive().corner_radius;
+        let padding = ui.spacing().button_padding;
+        let font = TextStyle::Monospace.resolve(ui.style());
+
+        let language = language.filter(|_| options.code_block_header);
+        let header_rect = (info.title.is_some() || language.is_some()).then(|| {
+            let (rect, _) = ui.allocate_exact_size(
+                Vec2::new(
+                    width,
+                    ui.fonts_mut(|f| f.row_height(&font)) + padding.y * 2.0,
+                ),
+                Sense::hover(),
+            );
+
+            ui.painter().add(epaint::RectShape::new(
+                rect,
+                egui::CornerRadius {
+                    sw: 0,
+                    se: 0,
+                    ..corner_radius
+                },
+                ui.visuals().widgets.noninteractive.weak_bg_fill,
+                ui.visuals().widgets.noninteractive.bg_stroke,
+                egui::StrokeKind::Outside,
+            ));
+            rect
+        });
+
+        let corner_radius = if header_rect.is_some() {
+            egui::CornerRadius {
+                nw: 0,
+                ne: 0,
+                ..corner_radius
+            }
+        } else {
+            corner_radius
+        };
+
+        let copied = code_block_text(ui, options, width, text, info, corner_radius, layouter);
+
+        if let Some(rect) = header_rect {
+            if let Some(title) = &info.title {
+                ui.painter().text(
+                    rect.left_center() + Vec2::new(padding.x, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    title,
+                    font.clone(),
+                    ui.visuals().strong_text_color(),
+                );
+            }
+
+            let label = if copied && language.is_some() {
+                Some("Copied!")
+            } else {
+                language
+            };
+            if let Some(label) = label {
+                ui.painter().text(
+                    rect.right_center() - Vec2::new(padding.x, 0.0),
+                    egui::Align2::RIGHT_CENTER,
+                    label,
+                    font,
+                    ui.visuals().weak_text_color(),
+                );
+            }
+        }
+    });
+}
+
+fn code_block_text<'t>(
+    ui: &mut Ui,
+    options: &CommonMarkOptions,
+    width: f32,
+    text: &str,
+    info: &CodeBlockInfo,
+    corner_radius: egui::CornerRadius,
+    layouter: &'t mut dyn FnMut(&Ui, &dyn TextBuffer, f32) -> std::sync::Arc<egui::Galley>,
+) -> bool {
     let mut text = text.strip_suffix('\n').unwrap_or(text);
+    let line_count = text.lines().count().max(1);
+    let is_diff = info.language.as_deref().and_then(diff_language).is_some();
+
+    let number_font = TextStyle::Monospace.resolve(ui.style());
+    let gutter_width = if info.show_line_numbers {
+        let digits = line_count.to_string().len();
+        let digit_width = ui.fonts_mut(|f| f.glyph_width(&number_font, '0'));
+        digits as f32 * digit_width + ui.spacing().button_padding.x * 2.0
+    } else {
+        0.0
+    };
 
     // To manually add background color to the code block, we imitate what
     // TextEdit does internally
     let where_to_put_background = ui.painter().add(egui::Shape::Noop);
 
-    // We use a `TextEdit` to make the text selectable.
-    // Note that we take a `&mut` to a non-`mut` `&str`, which is
-    // the how to tell `egui` that the text is not editable.
-    let output = egui::TextEdit::multiline(&mut text)
-        .layouter(layouter)
-        .desired_width(max_width)
-        // prevent trailing lines
-        .desired_rows(1)
-        .show(ui);
+    // Taken before anything is added so that it stays the same when the block is expanded
+    let id = ui.auto_id_with("_code_block");
+    let expanded_id = ui.make_persistent_id(id.with("expanded"));
+    let expanded = ui.memory_mut(|m| *m.data.get_temp_mut_or_default::<bool>(expanded_id));
+    let wrap_id = ui.make_persistent_id(id.with("wrap"));
+    let wrap = ui.memory_mut(|m| *m.data.get_temp_mut_or(wrap_id, options.code_block_wrap));
+
+    let mut layouter = |ui: &Ui, text: &dyn TextBuffer, wrap_width: f32| {
+        layouter(ui, text, if wrap { wrap_width } else { f32::INFINITY })
+    };
+
+    let mut show_text = |ui: &mut Ui| {
+        let where_to_put_highlights = ui.painter().add(egui::Shape::Noop);
+
+        let output = ui
+            .horizontal_top(|ui| {
+                ui.spacing_mut().item_spacing.x = 0.0;
+                if gutter_width > 0.0 {
+                    ui.add_space(gutter_width);
+                }
+
+                // We use a `TextEdit` to make the text selectable.
+                // Note that we take a `&mut` to a non-`mut` `&str`, which is
+                // the how to tell `egui` that the text is not editable.
+                egui::TextEdit::multiline(&mut text)
+                    .layouter(&mut layouter)
+                    .desired_width(width - gutter_width)
+                    // prevent trailing lines
+                    .desired_rows(1)
+                    .show(ui)
+            })
+            .inner;
+
+        let mut text_rect = output.response.rect;
+        text_rect.min.x -= gutter_width;
+
+        let diff_lines: Vec<_> = if is_diff {
+            DiffLine::parse_lines(text.lines())
+        } else {
+            Vec::new()
+        };
+
+        // Line numbers and highlighted lines. A line can span several rows when it is wrapped
+        let mut highlights = Vec::new();
+        let mut line = 1;
+        let mut is_line_start = true;
+        for row in &output.galley.rows {
+            let rect = row.rect().translate(output.galley_pos.to_vec2());
+
+            if let Some(color) = diff_lines
+                .get(line - 1)
+                .and_then(|diff_line| options.theme.diff_background(ui, *diff_line))
+            {
+                highlights.push(egui::Shape::rect_filled(
+                    egui::Rect::from_x_y_ranges(text_rect.x_range(), rect.y_range()),
+                    0.0,
+                    color,
+                ));
+            }
+
+            if info.is_highlighted(line) {
+                highlights.push(egui::Shape::rect_filled(
+                    egui::Rect::from_x_y_ranges(text_rect.x_range(), rect.y_range()),
+                    0.0,
+                    options.theme.code_highlight_color(ui),
+                ));
+            }
+
+            if info.show_line_numbers && is_line_start {
+                ui.painter().text(
+                    egui::pos2(
+                        text_rect.left() + gutter_width - ui.spacing().button_padding.x,
+                        rect.center().y,
+                    ),
+                    egui::Align2::RIGHT_CENTER,
+                    line.to_string(),
+                    number_font.clone(),
+                    ui.visuals().weak_text_color(),
+                );
+            }
+
+            is_line_start = row.ends_with_newline;
+            if row.ends_with_newline {
+                line += 1;
+            }
+        }
+        ui.painter().set(where_to_put_highlights, highlights);
+
+        (output, text_rect)
+    };
+
+    let max_height = options.code_block_max_height.filter(|_| !expanded);
+    let (output, frame_rect, is_overflowing, is_wide) = if max_height.is_some() || !wrap {
+        let mut scroll = egui::ScrollArea::new([!wrap, max_height.is_some()])
+            .id_salt(id)
+            .max_width(width);
+        if let Some(max_height) = max_height {
+            scroll = scroll
+                .min_scrolled_height(max_height)
+                .max_height(max_height);
+        }
+
+        let scroll = scroll.show(ui, show_text);
+        let (output, _) = scroll.inner;
+        // inner_rect is only the available size of the scroll area, so shrink it to the content
+        // the same way that the scroll area does
+        let mut size = scroll.content_size;
+        if !wrap {
+            size.x = size.x.at_most(scroll.inner_rect.width());
+        }
+        if max_height.is_some() {
+            size.y = size.y.at_most(scroll.inner_rect.height());
+        }
+        let frame_rect = egui::Rect::from_min_size(scroll.inner_rect.min, size);
+
+        let is_overflowing = max_height.is_some_and(|h| scroll.content_size.y > h);
+        let is_wide = if wrap {
+            output.galley.rows.len() > line_count
+        } else {
+            scroll.content_size.x > frame_rect.width()
+        };
+        (output, frame_rect, is_overflowing, is_wide)
+    } else {
+        let (output, text_rect) = show_text(ui);
+        let is_wide = output.galley.rows.len() > line_count;
+        (output, text_rect, false, is_wide)
+    };
+    let is_overflowing = is_overflowing || (expanded && options.code_block_max_height.is_some());
 
     // Background color + frame (This is lost when TextEdit it not editable)
-    let frame_rect = output.response.rect;
     ui.painter().set(
         where_to_put_background,
         epaint::RectShape::new(
             frame_rect,
-            ui.style().noninteractive().corner_radius,
+            corner_radius,
             ui.visuals().extreme_bg_color,
             ui.visuals().widgets.noninteractive.bg_stroke,
             egui::StrokeKind::Outside,
         ),
     );
 
-    // Copy icon
+    // Buttons in the top right corner from right to left
     let spacing = &ui.style().spacing;
-    let position = egui::pos2(
+    let mut position = egui::pos2(
         frame_rect.right_top().x - spacing.icon_width * 0.5 - spacing.button_padding.x,
         frame_rect.right_top().y + spacing.button_padding.y * 2.0,
     );
+    let button_step = spacing.icon_width + spacing.item_spacing.x;
 
     // Check if we should show ✔ instead of 🗐 if the text was copied and the mouse is hovered
     let persistent_id = ui.make_persistent_id(output.response.id);
     let copied_icon = ui.memory_mut(|m| *m.data.get_temp_mut_or_default::<bool>(persistent_id));
 
-    let copy_button = ui
-        .put(
-            egui::Rect {
-                min: position,
-                max: position,
-            },
-            egui::Button::new(if copied_icon { "✔" } else { "🗐" })
-                .small()
-                .frame(false)
-                .fill(egui::Color32::TRANSPARENT),
-        )
-        // workaround for a regression after egui 0.27 where the edit cursor was shown even when
-        // hovering over the button. We try interact_cursor first to allow the cursor to be
-        // overriden
-        .on_hover_cursor(
-            ui.visuals()
-                .interact_cursor
-                .unwrap_or(egui::CursorIcon::Default),
-        );
+    let copy_button = code_block_button(ui, position, if copied_icon { "✔" } else { "🗐" })
+        .on_hover_text(if copied_icon { "Copied!" } else { "Copy" });
+    position.x -= button_step;
 
     // Update icon state in persistent memory
     if copied_icon && !copy_button.hovered() {
@@ -176,6 +504,53 @@ pub fn code_block<'t>(
         };
         ui.copy_text(copy_text);
     }
+
+    if is_wide {
+        let wrap_button = code_block_button(ui, position, if wrap { "↩" } else { "↔" })
+            .on_hover_text(if wrap {
+                "Scroll long lines"
+            } else {
+                "Wrap long lines"
+            });
+        position.x -= button_step;
+
+        if wrap_button.clicked() {
+            ui.memory_mut(|m| m.data.insert_temp(wrap_id, !wrap));
+        }
+    }
+
+    if is_overflowing {
+        let expand_button = code_block_button(ui, position, if expanded { "⏶" } else { "⏷" })
+            .on_hover_text(if expanded { "Collapse" } else { "Expand" });
+
+        if expand_button.clicked() {
+            ui.memory_mut(|m| m.data.insert_temp(expanded_id, !expanded));
+        }
+    }
+
+    (copied_icon && copy_button.hovered()) || copy_button.clicked()
+}
+
+/// A small frameless button centered on `position`
+fn code_block_button(ui: &mut Ui, position: egui::Pos2, icon: &str) -> egui::Response {
+    ui.put(
+        egui::Rect {
+            min: position,
+            max: position,
+        },
+        egui::Button::new(icon)
+            .small()
+            .frame(false)
+            .fill(egui::Color32::TRANSPARENT),
+    )
+    // workaround for a regression after egui 0.27 where the edit cursor was shown even when
+    // hovering over the button. We try interact_cursor first to allow the cursor to be
+    // overriden
+    .on_hover_cursor(
+        ui.visuals()
+            .interact_cursor
+            .unwrap_or(egui::CursorIcon::Default),
+    )
 }
 
 // Stripped down version of egui's Checkbox. The only difference is that this
@@ -231,13 +606,18 @@ impl egui::Widget for ImmutableCheckbox<'_> {
     }
 }
 
-pub fn blockquote(ui: &mut Ui, accent: egui::Color32, add_contents: impl FnOnce(&mut Ui)) {
+pub fn blockquote(
+    ui: &mut Ui,
+    theme: &CommonMarkTheme,
+    accent: egui::Color32,
+    add_contents: impl FnOnce(&mut Ui),
+) {
     let start = ui.painter().add(egui::Shape::Noop);
     let response = egui::Frame::new()
         // offset the frame so that we can use the space for the horizontal line and other stuff
         // By not using a separator we have better control
         .outer_margin(egui::Margin {
-            left: 10,
+            left: theme.quote_indent,
             ..Default::default()
         })
         .show(ui, add_contents)
@@ -255,7 +635,275 @@ pub fn blockquote(ui: &mut Ui, accent: egui::Color32, add_contents: impl FnOnce(
                     response.rect.left_bottom().y - 5.0,
                 ),
             ],
-            egui::Stroke::new(3.0, accent),
+            egui::Stroke::new(theme.quote_bar_width, accent),
         ),
     );
 }
+
+/// Aligns the cells of a table within their column. The width of a column is not
+/// known until the whole table has been shown, so the widths from the previous
+/// frame are used.
+pub struct TableAlignment {
+    id: egui::Id,
+    alignments: Vec<pulldown_cmark::Alignment>,
+    prev_widths: Vec<Vec<f32>>,
+    widths: Vec<Vec<f32>>,
+}
+
+impl TableAlignment {
+    pub fn new(ui: &Ui, id: egui::Id, alignments: Vec<pulldown_cmark::Alignment>) -> Self {
+        let prev_widths = ui
+            .data(|d| d.get_temp::<Vec<Vec<f32>>>(id))
+            .unwrap_or_default();
+
+        Self {
+            id,
+            alignments,
+            prev_widths,
+            widths: Vec::new(),
+        }
+    }
+
+    fn is_aligned(&self) -> bool {
+        self.alignments.iter().any(|a| {
+            matches!(
+                a,
+                pulldown_cmark::Alignment::Center | pulldown_cmark::Alignment::Right
+            )
+        })
+    }
+
+    pub fn cell(
+        &mut self,
+        ui: &mut Ui,
+        row: usize,
+        col: usize,
+        add_contents: impl FnOnce(&mut Ui),
+    ) {
+        let factor = match self.alignments.get(col) {
+            Some(pulldown_cmark::Alignment::Center) => 0.5,
+            Some(pulldown_cmark::Alignment::Right) => 1.0,
+            _ => {
+                ui.horizontal(add_contents);
+                return;
+            }
+        };
+
+        let offset = self
+            .prev_widths
+            .get(row)
+            .and_then(|r| r.get(col))
+            .map_or(0.0, |width| {
+                let col_width = self
+                    .prev_widths
+                    .iter()
+                    .filter_map(|r| r.get(col))
+                    .fold(0.0, |a: f32, b| a.max(*b));
+                (col_width - width) * factor
+            });
+
+        let response = ui
+            .horizontal(|ui| {
+                if offset > 0.0 {
+                    ui.add_space(offset);
+                }
+                add_contents(ui);
+            })
+            .response;
+
+        if self.widths.len() <= row {
+            self.widths.resize_with(row + 1, Vec::new);
+        }
+        let widths = &mut self.widths[row];
+        if widths.len() <= col {
+            widths.resize(col + 1, 0.0);
+        }
+        widths[col] = response.rect.width() - offset;
+    }
+
+    /// Must be called after all the cells have been shown
+    pub fn finish(self, ui: &Ui) {
+        if !self.is_aligned() {
+            return;
+        }
+
+        // Allow for some rounding errors so that we don't end up discarding every frame
+        let is_changed = self.widths.len() != self.prev_widths.len()
+            || self.widths.iter().zip(&self.prev_widths).any(|(a, b)| {
+                a.len() != b.len() || a.iter().zip(b).any(|(a, b)| (a - b).abs() > 0.5)
+            });
+
+        if is_changed {
+            ui.ctx().request_discard("Table column widths changed");
+            ui.data_mut(|d| d.insert_temp(self.id, self.widths));
+        }
+    }
+}
+
+/// A table of delimiter separated values in the same style as markdown tables. Columns
+/// where every value is a number are right aligned. The header is guessed when `header`
+/// is `None`.
+pub fn csv_table(
+    ui: &mut Ui,
+    id: egui::Id,
+    theme: &CommonMarkTheme,
+    text: &str,
+    delimiter: char,
+    header: Option<bool>,
+) {
+    let records = crate::csv::parse(text, delimiter);
+    let header = header.unwrap_or_else(|| crate::csv::has_header(&records));
+    let columns = records.iter().map(Vec::len).max().unwrap_or(0);
+    let body = usize::from(header);
+
+    let alignments = (0..columns)
+        .map(|col| {
+            let mut values = records[body.min(records.len())..]
+                .iter()
+                .filter_map(|record| record.get(col))
+                .filter(|field| !field.trim().is_empty())
+                .peekable();
+            if values.peek().is_some() && values.all(|field| crate::csv::is_numeric(field)) {
+                pulldown_cmark::Alignment::Right
+            } else {
+                pulldown_cmark::Alignment::None
+            }
+        })
+        .collect();
+
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        theme.table_grid(id).show(ui, |ui| {
+            let mut alignment = TableAlignment::new(ui, id.with("alignment"), alignments);
+
+            for (r, record) in records.iter().enumerate() {
+                for (c, field) in record.iter().enumerate() {
+                    alignment.cell(ui, r, c, |ui| {
+                        if r == 0 && header {
+                            ui.label(RichText::new(field).strong());
+                        } else {
+                            ui.label(field);
+                        }
+                    });
+                }
+
+                ui.end_row();
+            }
+
+            alignment.finish(ui);
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Alignment;
+
+    /// Show a single column table and return the rects of its cells
+    fn show_column(ctx: &egui::Context, alignment: Alignment, cells: &[&str]) -> Vec<egui::Rect> {
+        let mut rects = Vec::new();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            // The pass may be discarded and shown again
+            rects.clear();
+            let mut table = TableAlignment::new(ui, egui::Id::new("table"), vec![alignment]);
+            for (row, text) in cells.iter().enumerate() {
+                table.cell(ui, row, 0, |ui| rects.push(ui.label(*text).rect));
+            }
+            table.finish(ui);
+        });
+        rects
+    }
+
+    fn stored_widths(ctx: &egui::Context) -> Option<Vec<Vec<f32>>> {
+        ctx.data(|d| d.get_temp(egui::Id::new("table")))
+    }
+
+    /// Show a code block without highlighting and return the size it takes up
+    fn code_block_size(options: &CommonMarkOptions, text: &str) -> Vec2 {
+        let mut size = Vec2::ZERO;
+        let _ = egui::Context::default().run_ui(Default::default(), |ui| {
+            let mut layouter = |ui: &Ui, text: &dyn TextBuffer, wrap_width: f32| {
+                let font = TextStyle::Monospace.resolve(ui.style());
+                let job = egui::text::LayoutJob::simple(
+                    text.as_str().to_owned(),
+                    font,
+                    ui.visuals().text_color(),
+                    wrap_width,
+                );
+                ui.fonts_mut(|f| f.layout_job(job))
+            };
+            let info = CodeBlockInfo::default();
+            size = ui
+                .scope(|ui| code_block(ui, options, 200.0, text, &info, None, &mut layouter))
+                .response
+                .rect
+                .size();
+        });
+        size
+    }
+
+    #[test]
+    fn code_blocks_are_limited_to_the_max_height() {
+        let long = "line\n".repeat(50);
+        let short = "line\nline\n";
+        let limited = CommonMarkOptions {
+            code_block_max_height: Some(100.0),
+            ..Default::default()
+        };
+        let unlimited = CommonMarkOptions::default();
+
+        assert!(code_block_size(&unlimited, &long).y > 200.0);
+        // Leave room for the frame
+        assert!(code_block_size(&limited, &long).y <= 110.0);
+        assert_eq!(
+            code_block_size(&limited, short).y,
+            code_block_size(&unlimited, short).y
+        );
+    }
+
+    #[test]
+    fn code_blocks_wrap_or_scroll_long_lines() {
+        let line = "word ".repeat(40);
+        let wrapped = code_block_size(&CommonMarkOptions::default(), &line);
+        let scrolled = code_block_size(
+            &CommonMarkOptions {
+                code_block_wrap: false,
+                ..Default::default()
+            },
+            &line,
+        );
+
+        assert!(wrapped.x <= 200.0 && scrolled.x <= 200.0);
+        assert!(wrapped.y > scrolled.y);
+    }
+
+    #[test]
+    fn table_alignment_uses_the_widths_of_the_previous_pass() {
+        let ctx = egui::Context::default();
+        let cells = ["a", "a much wider cell"];
+
+        let rects = show_column(&ctx, Alignment::Right, &cells);
+        let widths = stored_widths(&ctx).expect("widths are stored for aligned columns");
+        assert_eq!(widths.len(), 2);
+        assert!((widths[0][0] - rects[0].width()).abs() < 0.5);
+        assert!((widths[1][0] - rects[1].width()).abs() < 0.5);
+
+        // The offsets are not part of the stored widths so they settle
+        let rects = show_column(&ctx, Alignment::Right, &cells);
+        assert!((rects[0].right() - rects[1].right()).abs() < 0.5);
+        assert_eq!(stored_widths(&ctx), Some(widths));
+
+        let ctx = egui::Context::default();
+        show_column(&ctx, Alignment::Center, &cells);
+        let rects = show_column(&ctx, Alignment::Center, &cells);
+        assert!((rects[0].center().x - rects[1].center().x).abs() < 0.5);
+    }
+
+    #[test]
+    fn table_alignment_stores_nothing_for_left_aligned_columns() {
+        let ctx = egui::Context::default();
+        let rects = show_column(&ctx, Alignment::None, &["a", "a much wider cell"]);
+        assert_eq!(rects[0].left(), rects[1].left());
+        assert_eq!(stored_widths(&ctx), None);
+    }
+}
@@ -0,0 +1,464 @@
+//! Syntax highlighting of code blocks.
+//!
+//! Code blocks are highlighted with [`SyntectHighlighter`] when the
+//! `better_syntax_highlighting` feature is enabled and [`SimpleHighlighter`] otherwise.
+//! A custom [`Highlighter`] can be given to the viewer to highlight languages that these
+//! do not support, the built in highlighting is used for every language it returns `None` for.
+
+use egui::{Ui, text::LayoutJob};
+
+/// Turns the code of a code block into a [`LayoutJob`]
+pub trait Highlighter {
+    /// Highlight `code` written in `language`, e.g. `rust` for ```` ```rust ````. Returns
+    /// `None` if the language is not supported.
+    fn highlight(&self, ui: &Ui, language: &str, code: &str) -> Option<LayoutJob>;
+
+    /// The name of the language that is shown in the code block header, e.g. `Rust`
+    /// for `rs`
+    fn language_name(&self, _language: &str) -> Option<String> {
+        None
+    }
+
+    /// Highlighted code is cached between frames. The hash must change whenever
+    /// something other than the code and language changes the result, such as the
+    /// colors used in dark and light mode or the grammars of the highlighter.
+    fn style_hash(&self, _ui: &Ui) -> u64 {
+        0
+    }
+}
+
+/// Highlighting of a few common languages from [`egui_extras::syntax_highlighting`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleHighlighter;
+
+impl Highlighter for SimpleHighlighter {
+    fn highlight(&self, ui: &Ui, language: &str, code: &str) -> Option<LayoutJob> {
+        Some(egui_extras::syntax_highlighting::highlight(
+            ui.ctx(),
+            ui.style(),
+            &egui_extras::syntax_highlighting::CodeTheme::from_style(ui.style()),
+            code,
+            language,
+        ))
+    }
+
+    fn style_hash(&self, ui: &Ui) -> u64 {
+        egui::util::hash(egui_extras::syntax_highlighting::CodeTheme::from_style(
+            ui.style(),
+        ))
+    }
+}
+
+/// Highlighting with the syntaxes and themes of syntect
+#[cfg(feature = "better_syntax_highlighting")]
+#[derive(Debug, Clone, Copy)]
+pub struct SyntectHighlighter<'a> {
+    pub syntaxes: &'a syntect::parsing::SyntaxSet,
+    pub theme: &'a syntect::highlighting::Theme,
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+impl Highlighter for SyntectHighlighter<'_> {
+    fn highlight(&self, ui: &Ui, language: &str, code: &str) -> Option<LayoutJob> {
+        use syntect::{easy::HighlightLines, util::LinesWithEndings};
+
+        let syntax = find_syntax(self.syntaxes, language)?;
+        let mut job = LayoutJob::default();
+        let mut h = HighlightLines::new(syntax, self.theme);
+
+        for line in LinesWithEndings::from(code) {
+            let ranges = h.highlight_line(line, self.syntaxes).ok()?;
+            for v in ranges {
+                let front = v.0.foreground;
+                job.append(
+                    v.1,
+                    0.0,
+                    egui::TextFormat::simple(
+                        egui::TextStyle::Monospace.resolve(ui.style()),
+                        syntect_color_to_egui(front),
+                    ),
+                );
+            }
+        }
+
+        Some(job)
+    }
+
+    fn language_name(&self, language: &str) -> Option<String> {
+        find_syntax(self.syntaxes, language).map(|syntax| syntax.name.clone())
+    }
+
+    fn style_hash(&self, ui: &Ui) -> u64 {
+        // The syntaxes are told apart by the languages they are looked up by, so that
+        // code highlighted before its syntax was added is highlighted again
+        let syntaxes: Vec<_> = self
+            .syntaxes
+            .syntaxes()
+            .iter()
+            .map(|syntax| (&syntax.name, &syntax.file_extensions))
+            .collect();
+        egui::util::hash((
+            &self.theme.name,
+            syntaxes,
+            egui::TextStyle::Monospace.resolve(ui.style()),
+        ))
+    }
+}
+
+/// The syntax of a code block language, looked up by extension (`rs`) and then by name
+/// (`rust`)
+#[cfg(feature = "better_syntax_highlighting")]
+pub(crate) fn find_syntax<'a>(
+    syntaxes: &'a syntect::parsing::SyntaxSet,
+    language: &str,
+) -> Option<&'a syntect::parsing::SyntaxReference> {
+    syntaxes.find_syntax_by_token(language)
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+pub(crate) fn syntect_color_to_egui(color: syntect::highlighting::Color) -> egui::Color32 {
+    egui::Color32::from_rgb(color.r, color.g, color.b)
+}
+
+/// The highlight names that [`TreeSitterHighlighter`] gives a color. The captures of the
+/// highlight queries are matched against these, so `@function.method` is colored as
+/// `function`.
+#[cfg(feature = "tree_sitter")]
+pub const HIGHLIGHT_NAMES: &[&str] = &[
+    "attribute",
+    "comment",
+    "constant",
+    "constructor",
+    "escape",
+    "function",
+    "keyword",
+    "label",
+    "number",
+    "operator",
+    "property",
+    "punctuation",
+    "string",
+    "tag",
+    "type",
+    "variable",
+];
+
+/// Highlighting with tree-sitter grammars
+///
+/// ```
+/// # use egui_commonmark_backend::highlighting::TreeSitterHighlighter;
+/// # use egui_commonmark_backend::tree_sitter_highlight::HighlightConfiguration;
+/// let config = HighlightConfiguration::new(
+///     tree_sitter_toml_ng::LANGUAGE.into(),
+///     "toml",
+///     tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+///     "",
+///     "",
+/// )
+/// .unwrap();
+///
+/// let mut highlighter = TreeSitterHighlighter::default();
+/// highlighter.add_language(["toml"], config);
+/// highlighter.set_color(
+///     "string",
+///     egui::Color32::from_rgb(0x98, 0xc3, 0x79),
+///     egui::Color32::from_rgb(0x50, 0xa1, 0x4f),
+/// );
+/// ```
+#[cfg(feature = "tree_sitter")]
+pub struct TreeSitterHighlighter {
+    languages: std::collections::HashMap<
+        String,
+        std::sync::Arc<tree_sitter_highlight::HighlightConfiguration>,
+    >,
+    /// The colors of the [`HIGHLIGHT_NAMES`] in dark mode. Highlights without a color
+    /// are shown in the text color, or the weak text color for comments.
+    pub dark_colors: std::collections::BTreeMap<String, egui::Color32>,
+    /// The colors of the [`HIGHLIGHT_NAMES`] in light mode
+    pub light_colors: std::collections::BTreeMap<String, egui::Color32>,
+}
+
+#[cfg(feature = "tree_sitter")]
+impl Default for TreeSitterHighlighter {
+    fn default() -> Self {
+        use egui::Color32;
+
+        let mut highlighter = Self {
+            languages: Default::default(),
+            dark_colors: Default::default(),
+            light_colors: Default::default(),
+        };
+
+        let colors = [
+            (
+                ["keyword", "operator"].as_slice(),
+                [0xc6, 0x78, 0xdd],
+                [0xa6, 0x26, 0xa4],
+            ),
+            (
+                &["string", "escape"],
+                [0x98, 0xc3, 0x79],
+                [0x50, 0xa1, 0x4f],
+            ),
+            (
+                &["number", "constant"],
+                [0xd1, 0x9a, 0x66],
+                [0x98, 0x68, 0x01],
+            ),
+            (
+                &["function", "constructor"],
+                [0x61, 0xaf, 0xef],
+                [0x40, 0x78, 0xf2],
+            ),
+            (
+                &["type", "attribute", "tag", "label"],
+                [0xe5, 0xc0, 0x7b],
+                [0xc1, 0x84, 0x01],
+            ),
+        ];
+        for (highlights, [dr, dg, db], [lr, lg, lb]) in colors {
+            for highlight in highlights {
+                highlighter.set_color(
+                    *highlight,
+                    Color32::from_rgb(dr, dg, db),
+                    Color32::from_rgb(lr, lg, lb),
+                );
+            }
+        }
+
+        highlighter
+    }
+}
+
+#[cfg(feature = "tree_sitter")]
+impl TreeSitterHighlighter {
+    /// Use `config` for code blocks with any of the languages in `names`. The
+    /// configuration is configured with [`HIGHLIGHT_NAMES`]. The names are also used to
+    /// find the grammars of injected languages.
+    pub fn add_language<S: Into<String>>(
+        &mut self,
+        names: impl IntoIterator<Item = S>,
+        mut config: tree_sitter_highlight::HighlightConfiguration,
+    ) {
+        config.configure(HIGHLIGHT_NAMES);
+        let config = std::sync::Arc::new(config);
+        for name in names {
+            self.languages.insert(name.into(), config.clone());
+        }
+    }
+
+    /// Show `highlight`, one of the [`HIGHLIGHT_NAMES`], in the given colors in dark
+    /// and light mode
+    pub fn set_color(
+        &mut self,
+        highlight: impl Into<String>,
+        dark: egui::Color32,
+        light: egui::Color32,
+    ) {
+        let highlight = highlight.into();
+        self.dark_colors.insert(highlight.clone(), dark);
+        self.light_colors.insert(highlight, light);
+    }
+
+    fn colors(&self, ui: &Ui) -> &std::collections::BTreeMap<String, egui::Color32> {
+        if ui.visuals().dark_mode {
+            &self.dark_colors
+        } else {
+            &self.light_colors
+        }
+    }
+
+    fn color(&self, ui: &Ui, highlight: &str) -> egui::Color32 {
+        match self.colors(ui).get(highlight) {
+            Some(color) => *color,
+            None if highlight == "comment" => ui.visuals().weak_text_color(),
+            None => ui.visuals().text_color(),
+        }
+    }
+}
+
+#[cfg(feature = "tree_sitter")]
+impl Highlighter for TreeSitterHighlighter {
+    fn highlight(&self, ui: &Ui, language: &str, code: &str) -> Option<LayoutJob> {
+        use tree_sitter_highlight::HighlightEvent;
+
+        let config = self.languages.get(language)?;
+        let mut highlighter = tree_sitter_highlight::Highlighter::new();
+        let events = highlighter
+            .highlight(config, code.as_bytes(), None, |name| {
+                self.languages.get(name).map(|config| &**config)
+            })
+            .ok()?;
+
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let mut job = LayoutJob::default();
+        let mut highlights = Vec::new();
+        for event in events {
+            match event.ok()? {
+                HighlightEvent::HighlightStart(highlight) => highlights.push(highlight.0),
+                HighlightEvent::HighlightEnd => {
+                    highlights.pop();
+                }
+                HighlightEvent::Source { start, end } => {
+                    let color = highlights.last().map_or(ui.visuals().text_color(), |i| {
+                        self.color(ui, HIGHLIGHT_NAMES[*i])
+                    });
+                    job.append(
+                        &code[start..end],
+                        0.0,
+                        egui::TextFormat::simple(font.clone(), color),
+                    );
+                }
+            }
+        }
+
+        Some(job)
+    }
+
+    fn style_hash(&self, ui: &Ui) -> u64 {
+        // The grammars are told apart by their language and queries
+        let mut languages = self
+            .languages
+            .iter()
+            .map(|(name, config)| {
+                (
+                    name,
+                    &config.language_name,
+                    config.query.pattern_count(),
+                    config.query.capture_names(),
+                )
+            })
+            .collect::<Vec<_>>();
+        languages.sort_unstable_by_key(|(name, ..)| *name);
+
+        egui::util::hash((
+            languages,
+            self.colors(ui),
+            ui.visuals().text_color(),
+            ui.visuals().weak_text_color(),
+            egui::TextStyle::Monospace.resolve(ui.style()),
+        ))
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "better_syntax_highlighting", feature = "tree_sitter")
+))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn syntect_languages() {
+        let syntaxes = syntect::parsing::SyntaxSet::load_defaults_newlines();
+        let themes = syntect::highlighting::ThemeSet::load_defaults();
+        let highlighter = SyntectHighlighter {
+            syntaxes: &syntaxes,
+            theme: &themes.themes["base16-ocean.dark"],
+        };
+
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            // By extension and by name
+            for language in ["rs", "rust"] {
+                assert_eq!(highlighter.language_name(language).as_deref(), Some("Rust"));
+                assert!(
+                    highlighter
+                        .highlight(ui, language, "fn main() {}")
+                        .is_some()
+                );
+            }
+
+            assert_eq!(highlighter.language_name("not-a-language"), None);
+            assert!(highlighter.highlight(ui, "not-a-language", "").is_none());
+        });
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn syntect_style_hash() {
+        use syntect::parsing::{SyntaxDefinition, SyntaxSet};
+
+        let themes = syntect::highlighting::ThemeSet::load_defaults();
+        let theme = &themes.themes["base16-ocean.dark"];
+        let syntaxes = SyntaxSet::load_defaults_newlines();
+        let mut builder = syntaxes.clone().into_builder();
+        builder.add(
+            SyntaxDefinition::load_from_str(
+                "name: Example\nfile_extensions: [example]\nscope: source.example\ncontexts:\n  main: []\n",
+                true,
+                None,
+            )
+            .unwrap(),
+        );
+        let more_syntaxes = builder.build();
+
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let hash = |syntaxes: &SyntaxSet| SyntectHighlighter { syntaxes, theme }.style_hash(ui);
+            assert_eq!(hash(&syntaxes), hash(&SyntaxSet::load_defaults_newlines()));
+            assert_ne!(hash(&syntaxes), hash(&more_syntaxes));
+        });
+    }
+
+    #[cfg(feature = "tree_sitter")]
+    fn toml_highlighter() -> TreeSitterHighlighter {
+        let config = tree_sitter_highlight::HighlightConfiguration::new(
+            tree_sitter_toml_ng::LANGUAGE.into(),
+            "toml",
+            tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+            "",
+            "",
+        )
+        .unwrap();
+
+        let mut highlighter = TreeSitterHighlighter::default();
+        highlighter.add_language(["toml"], config);
+        highlighter
+    }
+
+    #[cfg(feature = "tree_sitter")]
+    #[test]
+    fn tree_sitter_colors() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let mut highlighter = toml_highlighter();
+            highlighter.set_color("string", egui::Color32::RED, egui::Color32::BLUE);
+
+            let job = highlighter.highlight(ui, "toml", "a = \"b\"").unwrap();
+            let string = job
+                .sections
+                .iter()
+                .find(|section| {
+                    &job.text[section.byte_range.start.0..section.byte_range.end.0] == "\"b\""
+                })
+                .unwrap();
+            let expected = if ui.visuals().dark_mode {
+                egui::Color32::RED
+            } else {
+                egui::Color32::BLUE
+            };
+            assert_eq!(string.format.color, expected);
+
+            assert!(highlighter.highlight(ui, "rust", "").is_none());
+        });
+    }
+
+    #[cfg(feature = "tree_sitter")]
+    #[test]
+    fn tree_sitter_style_hash() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            // Highlighters with the same grammars and colors share cached highlighting
+            let hash = toml_highlighter().style_hash(ui);
+            assert_eq!(toml_highlighter().style_hash(ui), hash);
+
+            assert_ne!(TreeSitterHighlighter::default().style_hash(ui), hash);
+
+            let mut highlighter = toml_highlighter();
+            highlighter.set_color("comment", egui::Color32::RED, egui::Color32::RED);
+            assert_ne!(highlighter.style_hash(ui), hash);
+        });
+    }
+}
@@ -0,0 +1,592 @@
+//! Parsing of the small subset of html that is rendered when there is no
+//! [`RenderHtmlFn`](crate::RenderHtmlFn). This is not a compliant html parser, it only
+//! needs to understand the html that is commonly found in READMEs.
+
+use std::borrow::Cow;
+
+/// Elements that never have any content or end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements that start on a new line
+const BLOCK_ELEMENTS: &[&str] = &[
+    "center", "details", "div", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "p", "summary",
+];
+
+/// Elements that are rendered. Everything else is stripped or shown as text.
+const SUPPORTED_ELEMENTS: &[&str] = &[
+    "a", "b", "br", "center", "code", "del", "details", "div", "em", "h1", "h2", "h3", "h4", "h5",
+    "h6", "hr", "i", "img", "kbd", "p", "picture", "s", "source", "span", "strike", "strong",
+    "sub", "summary", "sup",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+    /// `<name attr="value">` or `<name />`
+    Start {
+        /// The lowercase name of the element
+        name: String,
+        attrs: Vec<(String, String)>,
+        /// The tag as it is written
+        raw: &'a str,
+    },
+    /// `</name>`
+    End { name: String, raw: &'a str },
+    /// Text with its entities still encoded
+    Text(&'a str),
+    /// Comments, doctypes and processing instructions
+    Other(&'a str),
+}
+
+/// Split html into tags and text. A `<` that does not start a tag is part of the text.
+pub fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        let Some((token, end)) = tag(html, start) else {
+            pos = start + 1;
+            continue;
+        };
+
+        if text_start < start {
+            tokens.push(Token::Text(&html[text_start..start]));
+        }
+        tokens.push(token);
+        text_start = end;
+        pos = end;
+    }
+
+    if text_start < html.len() {
+        tokens.push(Token::Text(&html[text_start..]));
+    }
+
+    tokens
+}
+
+/// Parse the tag starting at `start`. Returns the token and where it ends
+fn tag(html: &str, start: usize) -> Option<(Token<'_>, usize)> {
+    let rest = &html[start..];
+
+    if rest.starts_with("<!--") {
+        let end = rest.find("-->").map_or(html.len(), |end| start + end + 3);
+        return Some((Token::Other(&html[start..end]), end));
+    }
+
+    if rest.starts_with("<!") || rest.starts_with("<?") {
+        let end = start + rest.find('>')? + 1;
+        return Some((Token::Other(&html[start..end]), end));
+    }
+
+    let (is_end, name_start) = match rest.strip_prefix("</") {
+        Some(_) => (true, 2),
+        None => (false, 1),
+    };
+
+    let name_len = rest[name_start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(rest.len() - name_start);
+    if name_len == 0 || !rest[name_start..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name = rest[name_start..name_start + name_len].to_ascii_lowercase();
+
+    let mut chars = rest[name_start + name_len..].char_indices().peekable();
+    let mut attrs = Vec::new();
+    let end = loop {
+        let (i, c) = chars.next()?;
+        match c {
+            '>' => break start + name_start + name_len + i + 1,
+            c if c.is_whitespace() || c == '/' => {}
+            _ => {
+                let mut attr_name = String::from(c);
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '=' | '>' | '/') {
+                        break;
+                    }
+                    attr_name.push(c);
+                    chars.next();
+                }
+
+                while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+                let mut value = String::new();
+                if chars.next_if(|&(_, c)| c == '=').is_some() {
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    match chars.peek().map(|&(_, c)| c) {
+                        Some(quote @ ('"' | '\'')) => {
+                            chars.next();
+                            for (_, c) in chars.by_ref() {
+                                if c == quote {
+                                    break;
+                                }
+                                value.push(c);
+                            }
+                        }
+                        _ => {
+                            while let Some((_, c)) =
+                                chars.next_if(|&(_, c)| !c.is_whitespace() && c != '>')
+                            {
+                                value.push(c);
+                            }
+                        }
+                    }
+                }
+
+                attrs.push((
+                    attr_name.to_ascii_lowercase(),
+                    decode_entities(&value).into_owned(),
+                ));
+            }
+        }
+    };
+
+    let raw = &html[start..end];
+    let token = if is_end {
+        Token::End { name, raw }
+    } else {
+        Token::Start { name, attrs, raw }
+    };
+    Some((token, end))
+}
+
+/// Replace character references such as `&amp;` and `&#169;` with the characters
+/// they stand for. Unknown references are kept as they are.
+pub fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 32)
+            .and_then(|end| Some((entity(&rest[1..end])?, end)));
+        if let Some((c, end)) = entity {
+            decoded.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            decoded.push('&');
+            rest = &rest[1..];
+        }
+    }
+    decoded.push_str(rest);
+
+    Cow::Owned(decoded)
+}
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "middot" => '·',
+        "times" => '×',
+        "larr" => '←',
+        "rarr" => '→',
+        "uarr" => '↑',
+        "darr" => '↓',
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    /// Decoded text
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    /// The lowercase name of the element
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+    /// The start tag as it is written
+    pub start: String,
+    /// The end tag as it is written, if there is one
+    pub end: Option<String>,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        attr(&self.attrs, name)
+    }
+
+    /// The text of the element and its descendants
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        push_text(&self.children, &mut text);
+        text
+    }
+
+    /// The text of the `<summary>` of a `<details>` element
+    pub fn summary(&self) -> Option<String> {
+        self.children.iter().find_map(|node| match node {
+            Node::Element(element) if element.name == "summary" => {
+                Some(element.text().trim().to_owned())
+            }
+            _ => None,
+        })
+    }
+
+    /// Whether the element starts on a new line
+    pub fn is_block(&self) -> bool {
+        BLOCK_ELEMENTS.contains(&self.name.as_str())
+    }
+}
+
+fn push_text(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Element(element) => push_text(&element.children, text),
+            Node::Text(s) => text.push_str(s),
+        }
+    }
+}
+
+pub fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(attr, _)| attr == name)
+        .map(|(_, value)| value.as_str())
+}
+
+pub fn is_supported(name: &str) -> bool {
+    SUPPORTED_ELEMENTS.contains(&name)
+}
+
+/// Build a tree of the html. Elements that are not closed end where their parent ends
+/// and end tags without a matching start tag are ignored. Comments are dropped.
+pub fn parse(html: &str) -> Vec<Node> {
+    // The elements that have not been closed yet with the root at the bottom
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = Vec::new();
+
+    fn push(stack: &mut [Element], root: &mut Vec<Node>, node: Node) {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root.push(node),
+        }
+    }
+
+    for token in tokenize(html) {
+        match token {
+            Token::Start { name, attrs, raw } => {
+                let element = Element {
+                    name,
+                    attrs,
+                    children: Vec::new(),
+                    start: raw.to_owned(),
+                    end: None,
+                };
+
+                if VOID_ELEMENTS.contains(&element.name.as_str()) || raw.ends_with("/>") {
+                    push(&mut stack, &mut root, Node::Element(element));
+                } else {
+                    stack.push(element);
+                }
+            }
+            Token::End { name, raw } => {
+                let Some(index) = stack.iter().rposition(|element| element.name == name) else {
+                    continue;
+                };
+
+                while stack.len() > index {
+                    let mut element = stack.pop().unwrap();
+                    if stack.len() == index {
+                        element.end = Some(raw.to_owned());
+                    }
+                    push(&mut stack, &mut root, Node::Element(element));
+                }
+            }
+            Token::Text(text) => {
+                push(
+                    &mut stack,
+                    &mut root,
+                    Node::Text(decode_entities(text).into_owned()),
+                );
+            }
+            Token::Other(_) => {}
+        }
+    }
+
+    while let Some(element) = stack.pop() {
+        push(&mut stack, &mut root, Node::Element(element));
+    }
+
+    root
+}
+
+/// Removes a `<details>` element that is still open at the end of the html. Its content
+/// continues in the markdown that follows until the end tag.
+pub fn split_open_details(nodes: &mut Vec<Node>) -> Option<Element> {
+    while matches!(nodes.last(), Some(Node::Text(text)) if text.trim().is_empty()) {
+        nodes.pop();
+    }
+
+    match nodes.last() {
+        Some(Node::Element(element)) if element.name == "details" && element.end.is_none() => {
+            match nodes.pop() {
+                Some(Node::Element(element)) => Some(element),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Follows the `<details>` elements that are opened and closed in the html, starting
+/// with `depth` elements open. Returns the html without the end tag that closes the
+/// outermost element if the html closes it.
+pub fn close_details(html: &str, depth: &mut usize) -> Option<String> {
+    let mut pos = 0;
+    for token in tokenize(html) {
+        let raw = match token {
+            Token::Start { name, raw, .. } => {
+                if name == "details" && !raw.ends_with("/>") {
+                    *depth += 1;
+                }
+                raw
+            }
+            Token::End { name, raw } => {
+                if name == "details" {
+                    *depth = depth.saturating_sub(1);
+                    if *depth == 0 {
+                        return Some(format!("{}{}", &html[..pos], &html[pos + raw.len()..]));
+                    }
+                }
+                raw
+            }
+            Token::Text(raw) | Token::Other(raw) => raw,
+        };
+        pos += raw.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(name: &str, children: Vec<Node>, start: &str, end: Option<&str>) -> Node {
+        Node::Element(Element {
+            name: name.to_owned(),
+            attrs: Vec::new(),
+            children,
+            start: start.to_owned(),
+            end: end.map(str::to_owned),
+        })
+    }
+
+    fn text(text: &str) -> Node {
+        Node::Text(text.to_owned())
+    }
+
+    #[test]
+    fn tokenize_tags_and_text() {
+        assert_eq!(
+            tokenize("a <B>b</B> <br/>"),
+            vec![
+                Token::Text("a "),
+                Token::Start {
+                    name: "b".to_owned(),
+                    attrs: Vec::new(),
+                    raw: "<B>"
+                },
+                Token::Text("b"),
+                Token::End {
+                    name: "b".to_owned(),
+                    raw: "</B>"
+                },
+                Token::Text(" "),
+                Token::Start {
+                    name: "br".to_owned(),
+                    attrs: Vec::new(),
+                    raw: "<br/>"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_attributes() {
+        let tokens = tokenize(r#"<img src="a.png" alt='x > y' width=100 hidden title="&amp;">"#);
+        let [Token::Start { name, attrs, .. }] = tokens.as_slice() else {
+            panic!("{tokens:?}");
+        };
+        assert_eq!(name, "img");
+        assert_eq!(
+            attrs,
+            &[
+                ("src".to_owned(), "a.png".to_owned()),
+                ("alt".to_owned(), "x > y".to_owned()),
+                ("width".to_owned(), "100".to_owned()),
+                ("hidden".to_owned(), String::new()),
+                ("title".to_owned(), "&".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_stray_less_than() {
+        assert_eq!(tokenize("1 < 2 <3 a<"), vec![Token::Text("1 < 2 <3 a<")]);
+        // A tag that never ends is text
+        assert_eq!(tokenize("x <b class="), vec![Token::Text("x <b class=")]);
+    }
+
+    #[test]
+    fn tokenize_comments() {
+        assert_eq!(
+            tokenize("a<!-- <b> -->b<!DOCTYPE html>"),
+            vec![
+                Token::Text("a"),
+                Token::Other("<!-- <b> -->"),
+                Token::Text("b"),
+                Token::Other("<!DOCTYPE html>"),
+            ]
+        );
+        // An unterminated comment continues to the end
+        assert_eq!(
+            tokenize("a<!-- <b>b"),
+            vec![Token::Text("a"), Token::Other("<!-- <b>b")]
+        );
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_entities("no entities"), "no entities");
+        assert_eq!(decode_entities("a &amp; b &lt;c&gt;"), "a & b <c>");
+        assert_eq!(decode_entities("&#65;&#x42;&#X43;"), "ABC");
+        assert_eq!(decode_entities("&amp;amp;"), "&amp;");
+        // Unknown and invalid references are kept
+        assert_eq!(
+            decode_entities("&unknown; &#xZZ; &#1114112;"),
+            "&unknown; &#xZZ; &#1114112;"
+        );
+        assert_eq!(decode_entities("fish & chips;"), "fish & chips;");
+        assert_eq!(decode_entities("trailing &"), "trailing &");
+    }
+
+    #[test]
+    fn parse_tree() {
+        assert_eq!(
+            parse("<p>a <b>b</b><br>c</p>"),
+            vec![element(
+                "p",
+                vec![
+                    text("a "),
+                    element("b", vec![text("b")], "<b>", Some("</b>")),
+                    element("br", Vec::new(), "<br>", None),
+                    text("c"),
+                ],
+                "<p>",
+                Some("</p>"),
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_unclosed_and_stray_end_tags() {
+        // Unclosed elements end with their parent
+        assert_eq!(
+            parse("<div><b>bold</div>after"),
+            vec![
+                element(
+                    "div",
+                    vec![element("b", vec![text("bold")], "<b>", None)],
+                    "<div>",
+                    Some("</div>"),
+                ),
+                text("after"),
+            ]
+        );
+        // End tags without a start tag are ignored
+        assert_eq!(parse("a</i>b"), vec![text("a"), text("b")]);
+        // Unclosed at the end of the html
+        assert_eq!(
+            parse("<span>x"),
+            vec![element("span", vec![text("x")], "<span>", None)]
+        );
+        assert_eq!(parse("<!-- unterminated <b>"), Vec::new());
+    }
+
+    #[test]
+    fn parse_decodes_text() {
+        assert_eq!(parse("&lt;b&gt; &copy;"), vec![text("<b> ©")]);
+    }
+
+    #[test]
+    fn open_details() {
+        let mut nodes = parse("<p>x</p>\n<details open>\n<summary>Title <b>here</b></summary>\n");
+        let details = split_open_details(&mut nodes).unwrap();
+        assert_eq!(details.summary().as_deref(), Some("Title here"));
+        assert_eq!(details.attr("open"), Some(""));
+        assert_eq!(
+            nodes,
+            vec![
+                element("p", vec![text("x")], "<p>", Some("</p>")),
+                text("\n")
+            ]
+        );
+
+        let mut closed = parse("<details><summary>S</summary>body</details>");
+        assert_eq!(split_open_details(&mut closed), None);
+        assert_eq!(closed.len(), 1);
+
+        // Only a section at the end continues in the markdown
+        let mut nested = parse("<details><summary>S</summary></details><p>");
+        assert_eq!(split_open_details(&mut nested), None);
+    }
+
+    #[test]
+    fn close() {
+        let mut depth = 1;
+        assert_eq!(close_details("</p>\n", &mut depth), None);
+        assert_eq!(depth, 1);
+
+        assert_eq!(close_details("</details>", &mut depth).as_deref(), Some(""));
+        assert_eq!(depth, 0);
+
+        // Nested sections have to be closed first
+        let mut depth = 1;
+        assert_eq!(
+            close_details("<details>x</details>\n</DETAILS>\n<p>after</p>", &mut depth).as_deref(),
+            Some("<details>x</details>\n\n<p>after</p>")
+        );
+
+        let mut depth = 1;
+        assert_eq!(
+            close_details("<details><summary>S</summary>", &mut depth),
+            None
+        );
+        assert_eq!(depth, 2);
+    }
+}
@@ -4,12 +4,20 @@
 
 #[doc(hidden)]
 pub mod alerts;
+pub mod document;
 #[doc(hidden)]
 pub mod elements;
+pub mod highlighting;
+#[doc(hidden)]
+pub mod html;
 #[doc(hidden)]
 pub mod misc;
 #[doc(hidden)]
 pub mod pulldown;
+pub mod theme;
+
+mod csv;
+mod uri;
 
 #[cfg(feature = "embedded_image")]
 mod data_url_loader;
@@ -20,7 +28,7 @@ pub use {
     alerts::{Alert, AlertBundle, alert_ui},
     // Pretty much every single element in this module is used by the proc macros
     elements::*,
-    misc::{CodeBlock, CommonMarkOptions, Image, Link, prepare_show},
+    misc::{CodeBlock, CommonMarkOptions, Image, Link, LinkAction, LinkContext, prepare_show},
 };
 
 // The only struct that is allowed to use directly. (If one does not need egui_commonmark)
@@ -29,7 +37,14 @@ pub use misc::CommonMarkCache;
 #[cfg(feature = "better_syntax_highlighting")]
 pub use syntect;
 
+#[cfg(feature = "tree_sitter")]
+pub use tree_sitter_highlight;
+
 /// Takes [`egui::Ui`], the math text to be rendered and whether it is inline
 pub type RenderMathFn = dyn Fn(&mut egui::Ui, &str, bool);
 /// Takes [`egui::Ui`] and the html text to be rendered/used
 pub type RenderHtmlFn = dyn Fn(&mut egui::Ui, &str);
+/// Takes [`egui::Ui`] and the contents of a fenced code block
+pub type RenderCodeBlockFn = dyn Fn(&mut egui::Ui, &str);
+/// Takes the [`LinkContext`] of a clicked link and decides what to do with it
+pub type LinkClickFn<'f> = dyn Fn(LinkContext<'_>) -> LinkAction + 'f;
@@ -1,17 +1,22 @@
 use crate::alerts::AlertBundle;
+use crate::document::{CodeBlockInfo, Document};
+use crate::highlighting::{Highlighter, SimpleHighlighter};
+use crate::theme::CommonMarkTheme;
 use egui::{RichText, TextBuffer, TextStyle, Ui, text::LayoutJob};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::pulldown::ScrollableCache;
 
 #[cfg(feature = "better_syntax_highlighting")]
 use syntect::{
-    easy::HighlightLines,
     highlighting::{Theme, ThemeSet},
     parsing::{SyntaxDefinition, SyntaxSet},
-    util::LinesWithEndings,
 };
 
+#[cfg(feature = "better_syntax_highlighting")]
+use crate::highlighting::{SyntectHighlighter, find_syntax, syntect_color_to_egui};
+
 #[cfg(feature = "better_syntax_highlighting")]
 const DEFAULT_THEME_LIGHT: &str = "base16-ocean.light";
 #[cfg(feature = "better_syntax_highlighting")]
@@ -28,14 +33,47 @@ pub struct CommonMarkOptions<'f> {
     pub theme_dark: String,
     pub use_explicit_uri_scheme: bool,
     pub default_implicit_uri_scheme: String,
+    /// Relative image and link destinations are resolved against this uri
+    pub base_uri: Option<String>,
     pub alerts: AlertBundle,
     /// Whether to present a mutable ui for things like checkboxes
     pub mutable: bool,
     pub math_fn: Option<&'f crate::RenderMathFn>,
     pub html_fn: Option<&'f crate::RenderHtmlFn>,
+    /// Whether a safe subset of html such as `<kbd>` and `<img width=..>` is rendered
+    /// instead of shown as text
+    pub render_html: bool,
+    /// Whether html elements that are not rendered are hidden instead of shown as text
+    pub strip_unknown_html: bool,
+    /// Highlights code blocks before the built in highlighting is tried
+    pub highlighter: Option<&'f dyn Highlighter>,
+    /// Renderers of fenced code blocks keyed by the language of the info string
+    pub code_block_fns: HashMap<String, &'f crate::RenderCodeBlockFn>,
+    /// Code blocks that are taller than this are shown in a scroll area that can be expanded
+    pub code_block_max_height: Option<f32>,
+    /// Whether long lines in code blocks are wrapped. Otherwise they can be scrolled
+    /// horizontally
+    pub code_block_wrap: bool,
+    /// Whether code blocks have a header with the name of the language
+    pub code_block_header: bool,
+    /// Whether to show ```` ```csv ```` and ```` ```tsv ```` code blocks as tables
+    pub render_csv: bool,
+    /// Decides what happens when a link is clicked
+    pub on_link_click: Option<&'f crate::LinkClickFn<'f>>,
     /// Whether to enable scrolling to headings by their ID.
     /// To give a heading an ID, use the syntax `# Heading {#myheadingid}`. Then links to `#myheadingid` e.g. `[click me!](#myheadingid)` will scroll to that heading.
     pub enable_scroll_to_heading: bool,
+    /// Whether to parse `^superscript^` and `~subscript~`
+    pub enable_superscript_and_subscript: bool,
+    /// Whether headings without an explicit id get a GitHub style slug as id, so that
+    /// links such as `[install](#installation-guide)` scroll to the heading.
+    pub enable_heading_slugs: bool,
+    /// Whether footnote definitions are shown at the end of the document, numbered by
+    /// their first reference
+    pub gather_footnotes: bool,
+    /// Whether `---` YAML and `+++` TOML front matter is parsed and hidden
+    pub enable_front_matter: bool,
+    pub theme: CommonMarkTheme,
 }
 
 impl std::fmt::Debug for CommonMarkOptions<'_> {
@@ -56,8 +94,28 @@ impl std::fmt::Debug for CommonMarkOptions<'_> {
                 "default_implicit_uri_scheme",
                 &self.default_implicit_uri_scheme,
             )
+            .field("base_uri", &self.base_uri)
             .field("alerts", &self.alerts)
             .field("mutable", &self.mutable)
+            .field("render_csv", &self.render_csv)
+            .field("render_html", &self.render_html)
+            .field("strip_unknown_html", &self.strip_unknown_html)
+            .field("code_block_max_height", &self.code_block_max_height)
+            .field("code_block_wrap", &self.code_block_wrap)
+            .field("code_block_header", &self.code_block_header)
+            .field("highlighter", &self.highlighter.is_some())
+            .field(
+                "code_block_fns",
+                &self.code_block_fns.keys().collect::<Vec<_>>(),
+            )
+            .field(
+                "enable_superscript_and_subscript",
+                &self.enable_superscript_and_subscript,
+            )
+            .field("enable_heading_slugs", &self.enable_heading_slugs)
+            .field("gather_footnotes", &self.gather_footnotes)
+            .field("enable_front_matter", &self.enable_front_matter)
+            .field("theme", &self.theme)
             .finish()
     }
 }
@@ -75,11 +133,26 @@ impl Default for CommonMarkOptions<'_> {
             theme_dark: DEFAULT_THEME_DARK.to_owned(),
             use_explicit_uri_scheme: false,
             default_implicit_uri_scheme: "file://".to_owned(),
+            base_uri: None,
             alerts: AlertBundle::gfm(),
             mutable: false,
             math_fn: None,
             html_fn: None,
+            render_html: false,
+            strip_unknown_html: false,
+            highlighter: None,
+            code_block_fns: HashMap::new(),
+            render_csv: false,
+            code_block_max_height: None,
+            code_block_wrap: true,
+            code_block_header: false,
+            on_link_click: None,
             enable_scroll_to_heading: false,
+            enable_superscript_and_subscript: false,
+            enable_heading_slugs: false,
+            gather_footnotes: false,
+            enable_front_matter: false,
+            theme: CommonMarkTheme::default(),
         }
     }
 }
@@ -94,6 +167,14 @@ impl CommonMarkOptions<'_> {
         }
     }
 
+    /// Resolve the uri against [`Self::base_uri`] if it is set
+    pub fn resolve_uri(&self, uri: &str) -> String {
+        match &self.base_uri {
+            Some(base) => crate::uri::resolve(base, uri),
+            None => uri.to_owned(),
+        }
+    }
+
     pub fn max_width(&self, ui: &Ui) -> f32 {
         let max_image_width = self.max_image_width.unwrap_or(0) as f32;
         let available_width = ui.available_width();
@@ -119,55 +200,40 @@ pub struct Style {
     pub strikethrough: bool,
     pub quote: bool,
     pub code: bool,
+    pub superscript: bool,
+    pub subscript: bool,
 }
 
 impl Style {
-    pub fn to_richtext(&self, ui: &Ui, text: &str) -> RichText {
+    pub fn to_richtext(&self, ui: &Ui, theme: &CommonMarkTheme, text: &str) -> RichText {
         let mut text = RichText::new(text);
 
         if let Some(level) = self.heading {
-            let max_height = ui
-                .style()
-                .text_styles
-                .get(&TextStyle::Heading)
-                .map_or(32.0, |d| d.size);
-            let min_height = ui
-                .style()
-                .text_styles
-                .get(&TextStyle::Body)
-                .map_or(14.0, |d| d.size);
-            let diff = max_height - min_height;
-
-            match level {
-                0 => {
-                    text = text.strong().heading();
-                }
-                1 => {
-                    let size = min_height + diff * 0.835;
-                    text = text.strong().size(size);
-                }
-                2 => {
-                    let size = min_height + diff * 0.668;
-                    text = text.strong().size(size);
-                }
-                3 => {
-                    let size = min_height + diff * 0.501;
-                    text = text.strong().size(size);
-                }
-                4 => {
-                    let size = min_height + diff * 0.334;
-                    text = text.size(size);
-                }
-                // We only support 6 levels
-                5.. => {
-                    let size = min_height + diff * 0.167;
-                    text = text.size(size);
-                }
+            let heading = theme.heading(level);
+            if !heading.is_text_style_heading(level) {
+                text = text.size(heading.size(ui));
+            }
+
+            if let Some(family) = &heading.family {
+                text = text.family(family.clone());
+            } else if level == 0 {
+                text = text.heading();
+            }
+
+            if let Some(color) = heading.color {
+                text = text.color(color);
+            }
+
+            if heading.strong {
+                text = text.strong();
             }
         }
 
         if self.quote {
-            text = text.weak();
+            text = match theme.quote_color {
+                Some(color) => text.color(color),
+                None => text.weak(),
+            };
         }
 
         if self.strong {
@@ -185,15 +251,62 @@ impl Style {
 
         if self.code {
             text = text.code();
+            if let Some(color) = theme.code_background {
+                text = text.background_color(color);
+            }
+        }
+
+        // The text is placed within a line of body height so that it can be aligned
+        // to the top or bottom of the surrounding text.
+        if self.superscript {
+            text = text
+                .small_raised()
+                .line_height(Some(ui.text_style_height(&TextStyle::Body)));
+        } else if self.subscript {
+            text = text
+                .small()
+                .line_height(Some(ui.text_style_height(&TextStyle::Body)));
         }
 
         text
     }
 }
 
+/// Information about a clicked link. See [`CommonMarkOptions::on_link_click`]
+#[derive(Debug, Clone, Copy)]
+pub struct LinkContext<'a> {
+    /// The destination as it is written in the markdown
+    pub destination: &'a str,
+    /// The destination resolved against the base uri. Same as `destination` if there
+    /// is no base uri.
+    pub url: &'a str,
+    pub title: &'a str,
+    /// The text of the link without any formatting
+    pub text: &'a str,
+    /// The modifiers that were held down when the link was clicked
+    pub modifiers: egui::Modifiers,
+    /// Whether the link should be opened in the background, e.g. it was middle clicked
+    pub open_in_background: bool,
+}
+
+/// What should happen when a link has been clicked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkAction {
+    /// Handle the link as if there was no callback. Links to headings are scrolled to
+    /// and everything else is opened as an url.
+    Default,
+    /// Open this url instead of the destination of the link
+    OpenUrl(String),
+    /// Scroll to the heading with this id
+    ScrollToHeading(String),
+    /// Do nothing, e.g. because the application has navigated by itself
+    Ignore,
+}
+
 #[derive(Default)]
 pub struct Link {
     pub destination: String,
+    pub title: String,
     pub text: Vec<RichText>,
 }
 
@@ -205,18 +318,20 @@ impl Link {
         options: &CommonMarkOptions,
         scroll_to_heading: &mut Option<String>,
     ) {
-        let Self { destination, text } = self;
-
         // When a link wraps an image (`[![alt](img)](url)`), all text events are captured
         // by the image widget and link.text is never populated. Rendering an empty Label in
         // a wrapping layout resets cursor.min.x to 0, superimposing subsequent elements on
         // the image that was just drawn. Nothing to render, so return early.
-        if text.is_empty() {
+        if self.text.is_empty() {
             return;
         }
 
         let mut layout_job = LayoutJob::default();
-        for t in text {
+        for t in &self.text {
+            let mut t = t.clone();
+            if let Some(color) = options.theme.link_color {
+                t = t.color(color);
+            }
             t.append_to(
                 &mut layout_job,
                 ui.style(),
@@ -224,19 +339,79 @@ impl Link {
                 egui::Align::LEFT,
             );
         }
-        if cache.link_hooks().contains_key(&destination) {
-            let ui_link = ui.link(layout_job);
-            if ui_link.clicked() || ui_link.middle_clicked() {
-                cache.link_hooks_mut().insert(destination, true);
+
+        let response = ui.link(layout_job);
+        self.respond(response, ui, cache, options, scroll_to_heading);
+    }
+
+    /// Makes the widget of the link, such as its text or an image within it, open the
+    /// link when clicked
+    pub fn respond(
+        &self,
+        response: egui::Response,
+        ui: &Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        scroll_to_heading: &mut Option<String>,
+    ) {
+        let heading_id = self
+            .destination
+            .strip_prefix("#")
+            .filter(|_| options.enable_scroll_to_heading || options.enable_heading_slugs);
+        let url = options.resolve_uri(&self.destination);
+        let is_hooked = cache.link_hooks().contains_key(&self.destination);
+
+        let mut response = response
+            .interact(egui::Sense::click())
+            .on_hover_cursor(egui::CursorIcon::PointingHand);
+        if heading_id.is_none() && !is_hooked && ui.style().url_in_tooltip {
+            response = response.on_hover_text(&url);
+        }
+
+        if !response.clicked() && !response.middle_clicked() {
+            return;
+        }
+        let open_in_background = response.clicked_with_open_in_background();
+
+        if is_hooked {
+            cache
+                .link_hooks_mut()
+                .insert(self.destination.clone(), true);
+            return;
+        }
+
+        let action = match options.on_link_click {
+            Some(on_link_click) => on_link_click(LinkContext {
+                destination: &self.destination,
+                url: &url,
+                title: &self.title,
+                text: &self.text.iter().map(|t| t.text()).collect::<String>(),
+                modifiers: ui.input(|i| i.modifiers),
+                open_in_background,
+            }),
+            None => LinkAction::Default,
+        };
+
+        let open_url = |url: String| {
+            ui.open_url(egui::OpenUrl {
+                url,
+                new_tab: open_in_background,
+            });
+        };
+
+        match action {
+            LinkAction::Default => {
+                if let Some(id) = heading_id {
+                    scroll_to_heading.replace(id.to_owned());
+                } else {
+                    open_url(url);
+                }
             }
-        } else if options.enable_scroll_to_heading
-            && let Some(stripped) = destination.strip_prefix("#")
-        {
-            if ui.link(layout_job).clicked() {
-                scroll_to_heading.replace(stripped.to_string());
-            };
-        } else {
-            ui.hyperlink_to(layout_job, destination);
+            LinkAction::OpenUrl(url) => open_url(url),
+            LinkAction::ScrollToHeading(id) => {
+                scroll_to_heading.replace(id);
+            }
+            LinkAction::Ignore => {}
         }
     }
 }
@@ -249,8 +424,8 @@ pub struct Image {
 impl Image {
     // FIXME: string conversion
     pub fn new(uri: &str, options: &CommonMarkOptions) -> Self {
-        let has_scheme = uri.contains("://") || uri.starts_with("data:");
-        let uri = if options.use_explicit_uri_scheme || has_scheme {
+        let uri = &options.resolve_uri(uri);
+        let uri = if options.use_explicit_uri_scheme || crate::uri::has_scheme(uri) {
             uri.to_string()
         } else {
             // Assume file scheme
@@ -280,40 +455,160 @@ impl Image {
     }
 }
 
-pub struct CodeBlock {
-    pub lang: Option<String>,
-    pub content: String,
+pub struct CodeBlock<'a> {
+    pub lang: Option<&'a str>,
+    pub content: &'a str,
 }
 
-impl CodeBlock {
+impl CodeBlock<'_> {
+    /// The attributes of the info string. Empty for indented code blocks
+    pub fn info(&self) -> CodeBlockInfo {
+        self.lang.map(CodeBlockInfo::parse).unwrap_or_default()
+    }
+
+    /// The delimiter of ```` ```csv ```` and ```` ```tsv ```` code blocks
+    pub fn csv_delimiter(&self) -> Option<char> {
+        match self.info().language.as_deref() {
+            Some("csv") => Some(','),
+            Some("tsv") => Some('\t'),
+            _ => None,
+        }
+    }
+
+    /// Show the code block. `table` is the number of tables shown before it in the
+    /// document and is used for the id of the table when the block is shown as one.
     pub fn end(
         &self,
         ui: &mut Ui,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         max_width: f32,
+        table: usize,
     ) {
+        let info = self.info();
+        if let Some(render) = info
+            .language
+            .as_deref()
+            .and_then(|lang| options.code_block_fns.get(lang))
+        {
+            render(ui, self.content);
+            return;
+        }
+
+        if options.render_csv
+            && let Some(delimiter) = self.csv_delimiter()
+        {
+            // The header is guessed unless it is given in the info string,
+            // e.g. ```csv header
+            let header = info.flags.iter().find_map(|flag| match flag.as_str() {
+                "header" => Some(true),
+                "noheader" => Some(false),
+                _ => None,
+            });
+
+            let id = ui.id().with("_table").with(table);
+            crate::elements::csv_table(ui, id, &options.theme, self.content, delimiter, header);
+            return;
+        }
+
         ui.scope(|ui| {
             Self::pre_syntax_highlighting(cache, options, ui);
+            let language = info.language.as_deref().map(|lang| {
+                options
+                    .highlighter
+                    .and_then(|h| h.language_name(lang))
+                    .unwrap_or_else(|| Self::language_name(cache, lang))
+            });
 
             let mut layout = |ui: &Ui, string: &dyn TextBuffer, wrap_width: f32| {
-                let mut job = if let Some(lang) = &self.lang {
-                    self.syntax_highlighting(cache, options, lang, ui, string.as_str())
-                } else {
-                    plain_highlighting(ui, string.as_str())
+                let text = string.as_str();
+                let key = Self::highlighting_key(options, ui, info.language.as_deref(), text);
+                if let Some(mut job) = cache.cached_highlighting(key) {
+                    job.wrap.max_width = wrap_width;
+                    return ui.fonts_mut(|f| f.layout_job(job));
+                }
+
+                let mut job = match info.language.as_deref() {
+                    Some(lang) => match diff_language(lang) {
+                        Some(Some(lang)) => diff_highlighting(ui, &options.theme, text, |code| {
+                            self.highlight(cache, options, lang, ui, code)
+                        }),
+                        Some(None) => diff_highlighting(ui, &options.theme, text, |code| {
+                            plain_highlighting(ui, code)
+                        }),
+                        None => self.highlight(cache, options, lang, ui, text),
+                    },
+                    None => plain_highlighting(ui, text),
                 };
+                cache.cache_highlighting(key, job.clone());
 
                 job.wrap.max_width = wrap_width;
                 ui.fonts_mut(|f| f.layout_job(job))
             };
 
-            crate::elements::code_block(ui, max_width, &self.content, &mut layout);
+            crate::elements::code_block(
+                ui,
+                options,
+                max_width,
+                self.content,
+                &info,
+                language.as_deref(),
+                &mut layout,
+            );
         });
     }
 }
 
+impl CodeBlock<'_> {
+    /// Highlight with the custom highlighter and fall back to the built in highlighting
+    fn highlight(
+        &self,
+        cache: &CommonMarkCache,
+        options: &CommonMarkOptions,
+        lang: &str,
+        ui: &Ui,
+        text: &str,
+    ) -> LayoutJob {
+        options
+            .highlighter
+            .and_then(|h| h.highlight(ui, lang, text))
+            .unwrap_or_else(|| self.syntax_highlighting(cache, options, lang, ui, text))
+    }
+
+    /// Identifies the highlighted text together with everything that affects its colors
+    fn highlighting_key(
+        options: &CommonMarkOptions,
+        ui: &Ui,
+        lang: Option<&str>,
+        text: &str,
+    ) -> u64 {
+        let visuals = ui.visuals();
+        #[cfg(feature = "better_syntax_highlighting")]
+        let syntax_theme = options.curr_theme(ui);
+        #[cfg(not(feature = "better_syntax_highlighting"))]
+        let syntax_theme = "";
+
+        egui::util::hash((
+            text,
+            lang,
+            syntax_theme,
+            options.highlighter.map(|h| h.style_hash(ui)),
+            SimpleHighlighter.style_hash(ui),
+            TextStyle::Monospace.resolve(ui.style()),
+            [
+                visuals.text_color(),
+                visuals.weak_text_color(),
+                visuals.strong_text_color(),
+                options.theme.diff_added_color(ui),
+                options.theme.diff_removed_color(ui),
+                options.theme.diff_hunk_color(ui),
+            ],
+        ))
+    }
+}
+
 #[cfg(not(feature = "better_syntax_highlighting"))]
-impl CodeBlock {
+impl CodeBlock<'_> {
     fn pre_syntax_highlighting(
         _cache: &mut CommonMarkCache,
         _options: &CommonMarkOptions,
@@ -322,20 +617,26 @@ impl CodeBlock {
         ui.style_mut().visuals.extreme_bg_color = ui.visuals().extreme_bg_color;
     }
 
+    fn language_name(_cache: &CommonMarkCache, lang: &str) -> String {
+        lang.to_owned()
+    }
+
     fn syntax_highlighting(
         &self,
-        _cache: &mut CommonMarkCache,
+        _cache: &CommonMarkCache,
         _options: &CommonMarkOptions,
         extension: &str,
         ui: &Ui,
         text: &str,
     ) -> egui::text::LayoutJob {
-        simple_highlighting(ui, text, extension)
+        SimpleHighlighter
+            .highlight(ui, extension, text)
+            .unwrap_or_else(|| plain_highlighting(ui, text))
     }
 }
 
 #[cfg(feature = "better_syntax_highlighting")]
-impl CodeBlock {
+impl CodeBlock<'_> {
     fn pre_syntax_highlighting(
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
@@ -355,6 +656,11 @@ impl CodeBlock {
         }
     }
 
+    /// The name of the syntax, e.g. `Rust` for `rs`
+    fn language_name(cache: &CommonMarkCache, lang: &str) -> String {
+        find_syntax(&cache.ps, lang).map_or_else(|| lang.to_owned(), |syntax| syntax.name.clone())
+    }
+
     fn syntax_highlighting(
         &self,
         cache: &CommonMarkCache,
@@ -363,42 +669,16 @@ impl CodeBlock {
         ui: &Ui,
         text: &str,
     ) -> egui::text::LayoutJob {
-        if let Some(syntax) = cache.ps.find_syntax_by_extension(extension) {
-            let mut job = egui::text::LayoutJob::default();
-            let mut h = HighlightLines::new(syntax, cache.curr_theme(ui, options));
-
-            for line in LinesWithEndings::from(text) {
-                let ranges = h.highlight_line(line, &cache.ps).unwrap();
-                for v in ranges {
-                    let front = v.0.foreground;
-                    job.append(
-                        v.1,
-                        0.0,
-                        egui::TextFormat::simple(
-                            TextStyle::Monospace.resolve(ui.style()),
-                            syntect_color_to_egui(front),
-                        ),
-                    );
-                }
-            }
-
-            job
-        } else {
-            simple_highlighting(ui, text, extension)
+        SyntectHighlighter {
+            syntaxes: &cache.ps,
+            theme: cache.curr_theme(ui, options),
         }
+        .highlight(ui, extension, text)
+        .or_else(|| SimpleHighlighter.highlight(ui, extension, text))
+        .unwrap_or_else(|| plain_highlighting(ui, text))
     }
 }
 
-fn simple_highlighting(ui: &Ui, text: &str, extension: &str) -> egui::text::LayoutJob {
-    egui_extras::syntax_highlighting::highlight(
-        ui.ctx(),
-        ui.style(),
-        &egui_extras::syntax_highlighting::CodeTheme::from_style(ui.style()),
-        text,
-        extension,
-    )
-}
-
 fn plain_highlighting(ui: &Ui, text: &str) -> egui::text::LayoutJob {
     let mut job = egui::text::LayoutJob::default();
     job.append(
@@ -412,9 +692,186 @@ fn plain_highlighting(ui: &Ui, text: &str) -> egui::text::LayoutJob {
     job
 }
 
-#[cfg(feature = "better_syntax_highlighting")]
-fn syntect_color_to_egui(color: syntect::highlighting::Color) -> egui::Color32 {
-    egui::Color32::from_rgb(color.r, color.g, color.b)
+/// The kind of a line in a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine {
+    Added,
+    Removed,
+    Context,
+    /// `@@ -1,3 +1,4 @@`
+    Hunk,
+    /// Lines such as `diff --git`, `+++ b/file` and `\ No newline at end of file`
+    Header,
+}
+
+impl DiffLine {
+    /// The kind of each line of a diff. Lines starting with `---` and `+++` are file
+    /// headers outside of hunks, but removed and added lines within them.
+    pub fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Self> {
+        // The number of old and new lines that are left of the current hunk
+        let mut remaining = None;
+        let mut is_after_hunk = false;
+
+        let mut kinds = Vec::new();
+        for line in lines {
+            let in_hunk = remaining.is_some_and(|(old, new)| old + new > 0);
+            let kind = match in_hunk.then(|| Self::parse_hunk_line(line)).flatten() {
+                Some(kind) => kind,
+                None => {
+                    remaining = None;
+                    Self::parse(line, is_after_hunk)
+                }
+            };
+
+            match (kind, &mut remaining) {
+                (Self::Hunk, _) => {
+                    remaining = hunk_lengths(line);
+                    is_after_hunk = true;
+                }
+                (Self::Added, Some((_, new))) => *new = new.saturating_sub(1),
+                (Self::Removed, Some((old, _))) => *old = old.saturating_sub(1),
+                (Self::Context, Some((old, new))) => {
+                    *old = old.saturating_sub(1);
+                    *new = new.saturating_sub(1);
+                }
+                _ => {}
+            }
+
+            kinds.push(kind);
+        }
+        kinds
+    }
+
+    /// A line outside of a hunk. Without the line counts of the hunk before it, only
+    /// `---` and `+++` followed by a path are taken as headers.
+    fn parse(line: &str, is_after_hunk: bool) -> Self {
+        let is_file_header = (line.starts_with("+++") || line.starts_with("---"))
+            && (!is_after_hunk
+                || line[3..]
+                    .strip_prefix(' ')
+                    .is_some_and(|path| !path.trim().is_empty()));
+
+        if is_file_header {
+            Self::Header
+        } else if line.starts_with("@@") {
+            Self::Hunk
+        } else {
+            Self::parse_hunk_line(line).unwrap_or(Self::Header)
+        }
+    }
+
+    fn parse_hunk_line(line: &str) -> Option<Self> {
+        if line.starts_with('+') {
+            Some(Self::Added)
+        } else if line.starts_with('-') {
+            Some(Self::Removed)
+        } else if line.starts_with(' ') || line.trim_end_matches(['\r', '\n']).is_empty() {
+            Some(Self::Context)
+        } else if line.starts_with('\\') {
+            // `\ No newline at end of file`
+            Some(Self::Header)
+        } else {
+            None
+        }
+    }
+
+    /// Length of the `+`, `-` or ` ` in front of the code
+    fn prefix_len(self, line: &str) -> Option<usize> {
+        match self {
+            Self::Added | Self::Removed => Some(1),
+            Self::Context => Some(usize::from(line.starts_with(' '))),
+            Self::Hunk | Self::Header => None,
+        }
+    }
+}
+
+/// The number of old and new lines of a hunk, `@@ -1,3 +1,4 @@` has 3 and 4
+fn hunk_lengths(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ ")?.split(' ');
+    let length = |range: &str| match range.split_once(',') {
+        Some((_, length)) => length.parse().ok(),
+        None => Some(1),
+    };
+    let old = length(ranges.next()?.strip_prefix('-')?)?;
+    let new = length(ranges.next()?.strip_prefix('+')?)?;
+    Some((old, new))
+}
+
+/// `Some(None)` for `diff` and `Some(Some("rust"))` for `diff-rust`
+pub fn diff_language(lang: &str) -> Option<Option<&str>> {
+    if lang == "diff" || lang == "patch" {
+        Some(None)
+    } else {
+        lang.strip_prefix("diff-").map(Some)
+    }
+}
+
+/// Highlight the code of a diff with `highlight` as if the `+`, `-` and ` ` in front of
+/// each line were not there, so that the syntax is highlighted across lines
+fn diff_highlighting(
+    ui: &Ui,
+    theme: &CommonMarkTheme,
+    text: &str,
+    highlight: impl FnOnce(&str) -> egui::text::LayoutJob,
+) -> egui::text::LayoutJob {
+    let kinds = DiffLine::parse_lines(text.split_inclusive('\n'));
+    let code: String = text
+        .split_inclusive('\n')
+        .zip(&kinds)
+        .filter_map(|(line, kind)| Some(&line[kind.prefix_len(line)?..]))
+        .collect();
+    let code_job = highlight(&code);
+
+    let font = TextStyle::Monospace.resolve(ui.style());
+    let mut job = egui::text::LayoutJob::default();
+    let mut offset = 0;
+    // The sections and lines are both in order, so the sections before the current line
+    // are never looked at again
+    let mut next_section = 0;
+    for (line, &kind) in text.split_inclusive('\n').zip(&kinds) {
+        let Some(prefix_len) = kind.prefix_len(line) else {
+            let color = match kind {
+                DiffLine::Hunk => theme.diff_hunk_color(ui),
+                _ => ui.visuals().strong_text_color(),
+            };
+            job.append(line, 0.0, egui::TextFormat::simple(font.clone(), color));
+            continue;
+        };
+
+        let color = match kind {
+            DiffLine::Added => theme.diff_added_color(ui),
+            DiffLine::Removed => theme.diff_removed_color(ui),
+            _ => ui.visuals().weak_text_color(),
+        };
+        job.append(
+            &line[..prefix_len],
+            0.0,
+            egui::TextFormat::simple(font.clone(), color),
+        );
+
+        let range = offset..offset + line.len() - prefix_len;
+        offset = range.end;
+        // A section that continues on the next line is kept for it
+        let sections = &code_job.sections;
+        while sections
+            .get(next_section)
+            .is_some_and(|section| section.byte_range.end.0 <= range.start)
+        {
+            next_section += 1;
+        }
+        for section in &sections[next_section..] {
+            if section.byte_range.start.0 >= range.end {
+                break;
+            }
+            let start = section.byte_range.start.0.max(range.start);
+            let end = section.byte_range.end.0.min(range.end);
+            if start < end {
+                job.append(&code_job.text[start..end], 0.0, section.format.clone());
+            }
+        }
+    }
+
+    job
 }
 
 #[cfg(feature = "better_syntax_highlighting")]
@@ -426,6 +883,24 @@ fn default_theme(ui: &Ui) -> &str {
     }
 }
 
+/// Cached documents and highlighting are evicted once they have not been used for this
+/// many passes, so content that is hidden for a moment is not parsed again
+const EVICT_AFTER_PASSES: u64 = 60;
+
+#[derive(Debug)]
+struct CachedDocument {
+    document: Arc<Document>,
+    /// The pass in which the document was last requested
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct CachedHighlighting {
+    job: LayoutJob,
+    /// The pass in which the highlighting was last requested
+    last_used: u64,
+}
+
 /// A cache used for storing content such as images.
 #[derive(Debug)]
 pub struct CommonMarkCache {
@@ -442,6 +917,16 @@ pub struct CommonMarkCache {
     link_hooks: HashMap<String, bool>,
 
     scroll: HashMap<egui::Id, ScrollableCache>,
+
+    /// Parsed documents keyed by the hash of their source text and parser options
+    documents: HashMap<u64, CachedDocument>,
+    /// The key of the document each viewer showed last. The previous document of a
+    /// viewer is removed when its text changes, so that editing the text does not keep
+    /// a document around for every frame.
+    viewer_documents: HashMap<egui::Id, u64>,
+    /// Syntax highlighted code blocks keyed by the hash of their text, language and style
+    highlighting: HashMap<u64, CachedHighlighting>,
+    pass_nr: u64,
     pub(self) has_installed_loaders: bool,
 }
 
@@ -456,17 +941,35 @@ impl Default for CommonMarkCache {
             link_hooks: HashMap::new(),
             scroll: Default::default(),
             scroll_to_id_target: None,
+            documents: HashMap::new(),
+            viewer_documents: HashMap::new(),
+            highlighting: HashMap::new(),
+            pass_nr: 0,
             has_installed_loaders: false,
         }
     }
 }
 
 impl CommonMarkCache {
+    /// Build a cache with additional syntaxes and themes loaded up front
+    #[cfg(feature = "better_syntax_highlighting")]
+    pub fn builder<'a>() -> CommonMarkCacheBuilder<'a> {
+        CommonMarkCacheBuilder::default()
+    }
+
     #[cfg(feature = "better_syntax_highlighting")]
-    pub fn add_syntax_from_folder(&mut self, path: &str) {
+    /// Add syntaxes for code blocks from the .sublime-syntax files in a folder. This requires
+    /// a filesystem, use [`add_syntax_from_bytes`](Self::add_syntax_from_bytes) or
+    /// [`add_syntaxes_from_dump`](Self::add_syntaxes_from_dump) on wasm.
+    pub fn add_syntax_from_folder(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), syntect::LoadingError> {
         let mut builder = self.ps.clone().into_builder();
-        let _ = builder.add_from_folder(path, true);
+        builder.add_from_folder(path, true)?;
         self.ps = builder.build();
+        self.highlighting.clear();
+        Ok(())
     }
 
     #[cfg(feature = "better_syntax_highlighting")]
@@ -478,6 +981,34 @@ impl CommonMarkCache {
         let mut builder = self.ps.clone().into_builder();
         SyntaxDefinition::load_from_str(s, true, fallback_name).map(|d| builder.add(d))?;
         self.ps = builder.build();
+        self.highlighting.clear();
+        Ok(())
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    /// Add a syntax for code blocks from the contents of a .sublime-syntax file, e.g. one
+    /// embedded with [`include_bytes!`]. `fallback_name` is used if the syntax has no name.
+    pub fn add_syntax_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        fallback_name: Option<&str>,
+    ) -> Result<(), syntect::LoadingError> {
+        let mut builder = self.ps.clone().into_builder();
+        builder.add(syntax_from_bytes(bytes, fallback_name)?);
+        self.ps = builder.build();
+        self.highlighting.clear();
+        Ok(())
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    /// Add the syntaxes of a `SyntaxSet` dumped with
+    /// [`syntect::dumps::dump_to_uncompressed_file`]. Loading a dump is much faster than
+    /// parsing the .sublime-syntax files it was created from.
+    pub fn add_syntaxes_from_dump(&mut self, bytes: &[u8]) -> Result<(), syntect::LoadingError> {
+        let mut builder = self.ps.clone().into_builder();
+        add_syntaxes_from_dump(&mut builder, bytes)?;
+        self.ps = builder.build();
+        self.highlighting.clear();
         Ok(())
     }
 
@@ -489,6 +1020,7 @@ impl CommonMarkCache {
         &mut self,
         path: impl AsRef<std::path::Path>,
     ) -> Result<(), syntect::LoadingError> {
+        self.highlighting.clear();
         self.ts.add_from_folder(path)
     }
 
@@ -505,6 +1037,7 @@ impl CommonMarkCache {
         self.ts
             .themes
             .insert(name.into(), ThemeSet::load_from_reader(&mut cursor)?);
+        self.highlighting.clear();
         Ok(())
     }
 
@@ -529,6 +1062,49 @@ impl CommonMarkCache {
         self.scroll.remove(&egui::Id::new(source_id)).is_some()
     }
 
+    /// Remove all parsed documents from the cache. Documents that have not been shown
+    /// for a while are removed automatically.
+    pub fn clear_documents(&mut self) {
+        self.documents.clear();
+        self.viewer_documents.clear();
+    }
+
+    /// Syntax highlighted code blocks are cached between frames. They are evicted
+    /// automatically when they have not been shown for a while or when syntaxes or
+    /// themes are added, but can be cleared manually as well.
+    pub fn clear_highlighting(&mut self) {
+        self.highlighting.clear();
+    }
+
+    /// Remove the documents and highlighting that have not been used recently
+    fn evict_unused(&mut self, pass_nr: u64) {
+        let is_used = |last_used: u64| last_used.saturating_add(EVICT_AFTER_PASSES) >= pass_nr;
+        self.documents.retain(|_, d| is_used(d.last_used));
+        let documents = &self.documents;
+        self.viewer_documents
+            .retain(|_, key| documents.contains_key(key));
+        self.highlighting.retain(|_, h| is_used(h.last_used));
+        self.pass_nr = pass_nr;
+    }
+
+    fn cached_highlighting(&mut self, key: u64) -> Option<LayoutJob> {
+        let pass_nr = self.pass_nr;
+        self.highlighting.get_mut(&key).map(|cached| {
+            cached.last_used = pass_nr;
+            cached.job.clone()
+        })
+    }
+
+    fn cache_highlighting(&mut self, key: u64, job: LayoutJob) {
+        self.highlighting.insert(
+            key,
+            CachedHighlighting {
+                job,
+                last_used: self.pass_nr,
+            },
+        );
+    }
+
     /// If the user clicks on a link in the markdown render that has `name` as a link. The hook
     /// specified with this method will be set to true. It's status can be acquired
     /// with [`get_link_hook`](Self::get_link_hook). Be aware that all hook state is reset once
@@ -599,6 +1175,172 @@ impl CommonMarkCache {
     }
 }
 
+#[cfg(feature = "better_syntax_highlighting")]
+/// The errors are returned as `InvalidData`, as `LoadingError::ParseSyntax` is for files
+/// and holds their path
+fn syntax_from_bytes(
+    bytes: &[u8],
+    fallback_name: Option<&str>,
+) -> Result<SyntaxDefinition, std::io::Error> {
+    let s = String::from_utf8(bytes.to_vec())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    SyntaxDefinition::load_from_str(&s, true, fallback_name)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+fn add_syntaxes_from_dump(
+    builder: &mut syntect::parsing::SyntaxSetBuilder,
+    bytes: &[u8],
+) -> Result<(), syntect::LoadingError> {
+    let set: SyntaxSet = syntect::dumps::from_uncompressed_data(bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    for syntax in set.into_builder().syntaxes() {
+        builder.add(syntax.clone());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+#[derive(Debug)]
+enum SyntaxSource<'a> {
+    Folder(std::path::PathBuf),
+    Bytes(&'a [u8], Option<&'a str>),
+    Dump(&'a [u8]),
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+#[derive(Debug)]
+enum ThemeSource<'a> {
+    Folder(std::path::PathBuf),
+    Bytes(String, &'a [u8]),
+}
+
+/// Builds a [`CommonMarkCache`] with additional syntaxes and themes. Everything is loaded
+/// at once in [`build`](Self::build), so the syntax set is only linked a single time.
+///
+/// Syntaxes and themes can be embedded in the binary, which works on wasm where there
+/// is no filesystem to load them from.
+///
+/// ```
+/// # use egui_commonmark_backend::misc::CommonMarkCache;
+/// const SYNTAX: &str = r#"
+/// name: Example
+/// file_extensions: [example]
+/// scope: source.example
+/// contexts:
+///   main:
+///     - match: '\b(if|else)\b'
+///       scope: keyword.control.example
+/// "#;
+///
+/// let cache = CommonMarkCache::builder()
+///     .syntax_from_bytes(SYNTAX.as_bytes(), None)
+///     .build()
+///     .unwrap();
+/// ```
+#[cfg(feature = "better_syntax_highlighting")]
+#[derive(Debug)]
+pub struct CommonMarkCacheBuilder<'a> {
+    default_syntaxes: bool,
+    syntaxes: Vec<SyntaxSource<'a>>,
+    themes: Vec<ThemeSource<'a>>,
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+impl Default for CommonMarkCacheBuilder<'_> {
+    fn default() -> Self {
+        Self {
+            default_syntaxes: true,
+            syntaxes: Vec::new(),
+            themes: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+impl<'a> CommonMarkCacheBuilder<'a> {
+    /// Whether to start out with the syntaxes that come with syntect. Disable this when
+    /// a dump added with [`syntaxes_from_dump`](Self::syntaxes_from_dump) already contains
+    /// them. By default this is enabled.
+    pub fn default_syntaxes(mut self, enable: bool) -> Self {
+        self.default_syntaxes = enable;
+        self
+    }
+
+    /// Add the .sublime-syntax files in a folder. This requires a filesystem.
+    pub fn syntaxes_from_folder(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.syntaxes.push(SyntaxSource::Folder(path.into()));
+        self
+    }
+
+    /// Add a syntax from the contents of a .sublime-syntax file. `fallback_name` is used
+    /// if the syntax has no name.
+    pub fn syntax_from_bytes(mut self, bytes: &'a [u8], fallback_name: Option<&'a str>) -> Self {
+        self.syntaxes
+            .push(SyntaxSource::Bytes(bytes, fallback_name));
+        self
+    }
+
+    /// Add the syntaxes of a `SyntaxSet` dumped with
+    /// [`syntect::dumps::dump_to_uncompressed_file`]
+    pub fn syntaxes_from_dump(mut self, bytes: &'a [u8]) -> Self {
+        self.syntaxes.push(SyntaxSource::Dump(bytes));
+        self
+    }
+
+    /// Add the .tmTheme files in a folder. This requires a filesystem.
+    pub fn themes_from_folder(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.themes.push(ThemeSource::Folder(path.into()));
+        self
+    }
+
+    /// Add a theme from the contents of a .tmTheme file
+    pub fn theme_from_bytes(mut self, name: impl Into<String>, bytes: &'a [u8]) -> Self {
+        self.themes.push(ThemeSource::Bytes(name.into(), bytes));
+        self
+    }
+
+    /// Load all syntaxes and themes. Fails on the first one that could not be loaded.
+    /// Syntaxes and dumps from bytes that can't be parsed fail with a
+    /// [`LoadingError::Io`](syntect::LoadingError::Io) of kind
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData).
+    pub fn build(self) -> Result<CommonMarkCache, syntect::LoadingError> {
+        let mut cache = CommonMarkCache::default();
+
+        if !self.default_syntaxes || !self.syntaxes.is_empty() {
+            let mut builder = if self.default_syntaxes {
+                std::mem::take(&mut cache.ps).into_builder()
+            } else {
+                syntect::parsing::SyntaxSetBuilder::new()
+            };
+
+            for source in self.syntaxes {
+                match source {
+                    SyntaxSource::Folder(path) => builder.add_from_folder(path, true)?,
+                    SyntaxSource::Bytes(bytes, fallback_name) => {
+                        builder.add(syntax_from_bytes(bytes, fallback_name)?);
+                    }
+                    SyntaxSource::Dump(bytes) => add_syntaxes_from_dump(&mut builder, bytes)?,
+                }
+            }
+
+            cache.ps = builder.build();
+        }
+
+        for source in self.themes {
+            match source {
+                ThemeSource::Folder(path) => cache.add_syntax_themes_from_folder(path)?,
+                ThemeSource::Bytes(name, bytes) => {
+                    cache.add_syntax_theme_from_bytes(name, bytes)?
+                }
+            }
+        }
+
+        Ok(cache)
+    }
+}
+
 pub fn scroll_cache<'a>(cache: &'a mut CommonMarkCache, id: &egui::Id) -> &'a mut ScrollableCache {
     if !cache.scroll.contains_key(id) {
         cache.scroll.insert(*id, Default::default());
@@ -606,6 +1348,36 @@ pub fn scroll_cache<'a>(cache: &'a mut CommonMarkCache, id: &egui::Id) -> &'a mu
     cache.scroll.get_mut(id).unwrap()
 }
 
+/// Get the cached document with the given key, parsing it only if it is not already cached.
+/// A document that the viewer with the given id showed before is replaced by this one
+/// unless another viewer is still showing it.
+pub fn cached_document(
+    cache: &mut CommonMarkCache,
+    key: u64,
+    viewer: Option<egui::Id>,
+    parse: impl FnOnce() -> Document,
+) -> Arc<Document> {
+    if let Some(viewer) = viewer
+        && let Some(previous) = cache.viewer_documents.insert(viewer, key)
+        && previous != key
+        && !cache.viewer_documents.values().any(|k| *k == previous)
+    {
+        cache.documents.remove(&previous);
+    }
+
+    let pass_nr = cache.pass_nr;
+    let entry = cache
+        .documents
+        .entry(key)
+        .or_insert_with(|| CachedDocument {
+            document: Arc::new(parse()),
+            last_used: pass_nr,
+        });
+
+    entry.last_used = pass_nr;
+    entry.document.clone()
+}
+
 /// Should be called before any rendering
 pub fn prepare_show(cache: &mut CommonMarkCache, ctx: &egui::Context) {
     if !cache.has_installed_loaders {
@@ -622,5 +1394,387 @@ pub fn prepare_show(cache: &mut CommonMarkCache, ctx: &egui::Context) {
         cache.has_installed_loaders = true;
     }
 
+    // The root viewport is used as the clock since the pass numbers of other viewports
+    // are independent.
+    let pass_nr = ctx.cumulative_pass_nr_for(egui::ViewportId::ROOT);
+    if pass_nr != cache.pass_nr {
+        cache.evict_unused(pass_nr);
+    }
+
     cache.deactivate_link_hooks();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lines() {
+        let diff = "\
+diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ context
+--- removed
++++ added
+
+\\ No newline at end of file
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-old
++new";
+
+        use DiffLine::*;
+        assert_eq!(
+            DiffLine::parse_lines(diff.lines()),
+            [
+                Header, Header, Header, Hunk, Context, Removed, Added, Context, Header, Header,
+                Header, Hunk, Removed, Added,
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_highlighting_slices_each_line() {
+        let diff = "\
+@@ -1,4 +1,4 @@
+ /* a comment
+-   over lines */
++   over more lines */
+ fn main() {
+-    let x = \"old\";
++    let x = \"new\";
+ }
+";
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let mut code_job = None;
+            let job = diff_highlighting(ui, &CommonMarkTheme::default(), diff, |code| {
+                let highlighted = SimpleHighlighter.highlight(ui, "rs", code).unwrap();
+                code_job = Some(highlighted.clone());
+                highlighted
+            });
+            let code_job = code_job.unwrap();
+            assert_eq!(job.text, diff);
+
+            // The code of every line is made of the sections of the underlying job that
+            // overlap it, cut at the line
+            // The hunk line is a single section
+            let mut sections = job.sections.iter().skip(1);
+            let mut offset = 0;
+            for line in diff.split_inclusive('\n').skip(1) {
+                let prefix = sections.next().unwrap();
+                assert_eq!(prefix.byte_range.end.0 - prefix.byte_range.start.0, 1);
+
+                let code = offset..offset + line.len() - 1;
+                offset = code.end;
+                for expected in &code_job.sections {
+                    let start = expected.byte_range.start.0.max(code.start);
+                    let end = expected.byte_range.end.0.min(code.end);
+                    if start < end {
+                        let section = sections.next().unwrap();
+                        assert_eq!(
+                            &job.text[section.byte_range.start.0..section.byte_range.end.0],
+                            &code_job.text[start..end]
+                        );
+                        assert_eq!(section.format, expected.format);
+                    }
+                }
+            }
+            assert!(sections.next().is_none());
+        });
+    }
+
+    #[test]
+    fn diff_lines_without_line_counts() {
+        let diff = "\
+--- a.txt
++++ a.txt
+@@
+---x
++++ b.txt
+-y";
+
+        use DiffLine::*;
+        assert_eq!(
+            DiffLine::parse_lines(diff.lines()),
+            [Header, Header, Hunk, Removed, Header, Removed]
+        );
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    const SYNTAX: &str = r#"
+name: Example
+file_extensions: [example]
+scope: source.example
+contexts:
+  main:
+    - match: '\b(if|else)\b'
+      scope: keyword.control.example
+"#;
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn syntaxes_from_bytes() {
+        let mut cache = CommonMarkCache::default();
+        cache
+            .add_syntax_from_bytes(SYNTAX.as_bytes(), None)
+            .unwrap();
+        assert!(cache.ps.find_syntax_by_extension("example").is_some());
+        assert!(cache.add_syntax_from_bytes(b"name: [", None).is_err());
+
+        // Invalid UTF-8 is an error instead of being replaced
+        let mut invalid = SYNTAX.as_bytes().to_vec();
+        invalid.extend_from_slice(b"# \xff\xfe\n");
+        let error = cache.add_syntax_from_bytes(&invalid, None).unwrap_err();
+        assert!(
+            matches!(error, syntect::LoadingError::Io(e) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+
+        let cache = CommonMarkCache::builder()
+            .syntax_from_bytes(SYNTAX.as_bytes(), None)
+            .build()
+            .unwrap();
+        assert!(cache.ps.find_syntax_by_extension("example").is_some());
+        assert!(cache.ps.find_syntax_by_extension("rs").is_some());
+
+        let error = CommonMarkCache::builder()
+            .syntax_from_bytes(b"name: [", None)
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(error, syntect::LoadingError::Io(e) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn syntaxes_from_dump() {
+        let mut builder = SyntaxSet::new().into_builder();
+        builder.add(SyntaxDefinition::load_from_str(SYNTAX, true, None).unwrap());
+        let path = std::env::temp_dir().join(format!(
+            "egui_commonmark_syntaxes_{}.packdump",
+            std::process::id()
+        ));
+        syntect::dumps::dump_to_uncompressed_file(&builder.build(), &path).unwrap();
+        let dump = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut cache = CommonMarkCache::default();
+        cache.add_syntaxes_from_dump(&dump).unwrap();
+        assert!(cache.ps.find_syntax_by_extension("example").is_some());
+        assert!(cache.add_syntaxes_from_dump(b"not a dump").is_err());
+
+        let cache = CommonMarkCache::builder()
+            .default_syntaxes(false)
+            .syntaxes_from_dump(&dump)
+            .build()
+            .unwrap();
+        assert!(cache.ps.find_syntax_by_extension("example").is_some());
+        assert!(cache.ps.find_syntax_by_extension("rs").is_none());
+    }
+
+    #[test]
+    fn superscript_and_subscript_text() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let format = |style: Style| {
+                let mut job = LayoutJob::default();
+                style
+                    .to_richtext(ui, &CommonMarkTheme::default(), "2")
+                    .append_to(
+                        &mut job,
+                        ui.style(),
+                        egui::FontSelection::Default,
+                        egui::Align::BOTTOM,
+                    );
+                job.sections.remove(0).format
+            };
+
+            let body = format(Style::default());
+            let superscript = format(Style {
+                superscript: true,
+                ..Default::default()
+            });
+            let subscript = format(Style {
+                subscript: true,
+                ..Default::default()
+            });
+
+            let small = TextStyle::Small.resolve(ui.style());
+            let line_height = Some(ui.text_style_height(&TextStyle::Body));
+            assert_eq!(superscript.font_id, small);
+            assert_eq!(superscript.valign, egui::Align::TOP);
+            assert_eq!(superscript.line_height, line_height);
+            assert_eq!(subscript.font_id, small);
+            assert_eq!(subscript.valign, egui::Align::BOTTOM);
+            assert_eq!(subscript.line_height, line_height);
+            assert_ne!(body.font_id, small);
+        });
+    }
+
+    fn show_document(cache: &mut CommonMarkCache, ctx: &egui::Context, parses: &mut usize) {
+        prepare_show(cache, ctx);
+        cached_document(cache, 0, None, || {
+            *parses += 1;
+            Document::parse("# Heading", pulldown_cmark::Options::empty())
+        });
+    }
+
+    #[test]
+    fn documents_are_parsed_once() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        let mut parses = 0;
+
+        for _ in 0..3 {
+            let _ = ctx.run_ui(Default::default(), |ui| {
+                show_document(&mut cache, ui.ctx(), &mut parses);
+            });
+        }
+        assert_eq!(parses, 1);
+    }
+
+    #[test]
+    fn hidden_documents_are_kept_for_a_while() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        let mut parses = 0;
+
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            show_document(&mut cache, ui.ctx(), &mut parses);
+        });
+        for _ in 0..EVICT_AFTER_PASSES {
+            let _ = ctx.run_ui(Default::default(), |ui| prepare_show(&mut cache, ui.ctx()));
+        }
+        assert_eq!(cache.documents.len(), 1);
+
+        let _ = ctx.run_ui(Default::default(), |ui| prepare_show(&mut cache, ui.ctx()));
+        assert!(cache.documents.is_empty());
+
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            show_document(&mut cache, ui.ctx(), &mut parses);
+        });
+        assert_eq!(parses, 2);
+    }
+
+    #[test]
+    fn edited_documents_replace_the_previous_one() {
+        let mut cache = CommonMarkCache::default();
+        let editor = egui::Id::new("editor");
+        let viewer = egui::Id::new("viewer");
+        let parse = || Document::parse("# Heading", pulldown_cmark::Options::empty());
+
+        cached_document(&mut cache, 0, Some(viewer), parse);
+        for key in 0..10 {
+            cached_document(&mut cache, key, Some(editor), parse);
+        }
+        // The document of the other viewer is kept even though the editor showed it too
+        assert_eq!(cache.documents.len(), 2);
+        assert!(cache.documents.contains_key(&0));
+        assert!(cache.documents.contains_key(&9));
+
+        cached_document(&mut cache, 10, Some(viewer), parse);
+        assert_eq!(cache.documents.len(), 2);
+        assert!(!cache.documents.contains_key(&0));
+    }
+
+    #[test]
+    fn highlighting_keys() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            let options = CommonMarkOptions::default();
+            let key = |ui: &Ui, lang, text| CodeBlock::highlighting_key(&options, ui, lang, text);
+
+            let rust = key(ui, Some("rust"), "fn main() {}");
+            assert_eq!(rust, key(ui, Some("rust"), "fn main() {}"));
+            assert_ne!(rust, key(ui, Some("rust"), "fn main() { }"));
+            assert_ne!(rust, key(ui, Some("toml"), "fn main() {}"));
+            assert_ne!(rust, key(ui, None, "fn main() {}"));
+
+            let dark_mode = ui.visuals().dark_mode;
+            *ui.visuals_mut() = if dark_mode {
+                egui::Visuals::light()
+            } else {
+                egui::Visuals::dark()
+            };
+            assert_ne!(rust, key(ui, Some("rust"), "fn main() {}"));
+        });
+    }
+
+    #[test]
+    fn code_blocks_with_a_renderer() {
+        // Renderers can't borrow from the test since they are `'static`
+        static RENDERED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+        let render = |_: &mut Ui, code: &str| RENDERED.lock().unwrap().push(code.to_owned());
+        let mut options = CommonMarkOptions {
+            render_csv: true,
+            ..Default::default()
+        };
+        options.code_block_fns.insert("csv".to_owned(), &render);
+        let mut cache = CommonMarkCache::default();
+
+        let ctx = egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            for (lang, content) in [
+                (Some("csv header {1}"), "a,b"),
+                (Some("rust"), "fn main() {}"),
+                (Some("csvx"), "c,d"),
+                (None, "csv"),
+            ] {
+                CodeBlock { lang, content }.end(ui, &mut cache, &options, 500.0, 0);
+            }
+        });
+
+        // Only the language is matched and the renderer replaces the csv table
+        assert_eq!(*RENDERED.lock().unwrap(), ["a,b"]);
+        assert_eq!(cache.highlighting.len(), 3);
+    }
+
+    fn show_code_block(cache: &mut CommonMarkCache, ui: &mut Ui, content: &str) {
+        prepare_show(cache, ui.ctx());
+        let options = CommonMarkOptions::default();
+        CodeBlock {
+            lang: Some("rust"),
+            content,
+        }
+        .end(ui, cache, &options, 500.0, 0);
+    }
+
+    #[test]
+    fn hidden_highlighting_is_kept_for_a_while() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            show_code_block(&mut cache, ui, "fn a() {}");
+            show_code_block(&mut cache, ui, "fn b() {}");
+        });
+        assert_eq!(cache.highlighting.len(), 2);
+
+        // Only `b` stays in use
+        for _ in 0..EVICT_AFTER_PASSES {
+            let _ = ctx.run_ui(Default::default(), |ui| {
+                show_code_block(&mut cache, ui, "fn b() {}");
+            });
+        }
+        assert_eq!(cache.highlighting.len(), 2);
+
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            show_code_block(&mut cache, ui, "fn b() {}");
+        });
+        assert_eq!(cache.highlighting.len(), 1);
+
+        cache.clear_highlighting();
+        assert!(cache.highlighting.is_empty());
+    }
+
+    #[test]
+    fn hunk_header_lengths() {
+        assert_eq!(hunk_lengths("@@ -1,3 +1,4 @@"), Some((3, 4)));
+        assert_eq!(hunk_lengths("@@ -1 +1,0 @@ fn main() {"), Some((1, 0)));
+        assert_eq!(hunk_lengths("@@"), None);
+    }
+}
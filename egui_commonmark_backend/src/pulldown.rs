@@ -1,182 +1,132 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
 use crate::alerts::*;
-use egui::{Pos2, Vec2};
+use crate::document::{Block, BlockKind, InlineKind};
 use pulldown_cmark::Options;
-use std::ops::Range;
+
+/// Top level blocks that are shown together
+#[derive(Debug, Clone)]
+pub struct BlockUnit {
+    pub blocks: Range<usize>,
+    /// How many details sections with each summary there are in the document before
+    /// the unit
+    pub details_count: Arc<HashMap<String, usize>>,
+}
 
 #[derive(Default, Debug)]
 pub struct ScrollableCache {
-    pub available_size: Vec2,
-    pub page_size: Option<Vec2>,
-    pub split_points: Vec<(usize, Pos2, Pos2)>,
+    /// Hash of the source text and the options that the units and heights were
+    /// computed for
+    pub source_hash: u64,
+    /// The top level blocks of the document grouped into the units that are shown
+    /// together
+    pub units: Arc<[BlockUnit]>,
+    /// The last measured height of each top level block and the width it was
+    /// measured at
+    pub block_heights: Vec<Option<(f32, f32)>>,
 }
 
-pub type EventIteratorItem<'e> = (usize, (pulldown_cmark::Event<'e>, Range<usize>));
-
-/// Parse events until a desired end tag is reached or no more events are found.
-/// This is needed for multiple events that must be rendered inside a single widget
-pub fn delayed_events<'e>(
-    events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
-    end_at: impl Fn(pulldown_cmark::TagEnd) -> bool,
-) -> Vec<(pulldown_cmark::Event<'e>, Range<usize>)> {
-    let mut curr_event = events.next();
-    let mut total_events = Vec::new();
-    loop {
-        if let Some(event) = curr_event.take() {
-            total_events.push(event.1.clone());
-            if let (_, (pulldown_cmark::Event::End(tag), _range)) = event
-                && end_at(tag)
-            {
-                return total_events;
-            }
-        } else {
-            return total_events;
-        }
-
-        curr_event = events.next();
-    }
+/// Newline logic shared by the viewer and the macros. All elements try to insert a
+/// newline before them (if they are allowed) and end their own line.
+pub struct Newline {
+    /// Whether a newline should not be inserted before a widget. This is only for
+    /// the first widget.
+    pub should_not_start_newline_forced: bool,
+    /// Whether an element should insert a newline before it
+    pub should_start_newline: bool,
+    /// Whether an element should end it's own line using a newline
+    /// This will have to be set to false in cases such as when blocks are within
+    /// a list.
+    pub should_end_newline: bool,
+    /// only false when the widget is the last one.
+    pub should_end_newline_forced: bool,
 }
 
-pub fn delayed_events_list_item<'e>(
-    events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
-) -> Vec<(pulldown_cmark::Event<'e>, Range<usize>)> {
-    let mut curr_event = events.next();
-    let mut total_events = Vec::new();
-    loop {
-        if let Some(event) = curr_event.take() {
-            total_events.push(event.1.clone());
-            if let (_, (pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Item), _range)) = event {
-                return total_events;
-            }
-
-            if let (_, (pulldown_cmark::Event::Start(pulldown_cmark::Tag::List(_)), _range)) = event
-            {
-                return total_events;
-            }
-        } else {
-            return total_events;
+impl Default for Newline {
+    fn default() -> Self {
+        Self {
+            should_not_start_newline_forced: true,
+            should_start_newline: true,
+            should_end_newline: true,
+            should_end_newline_forced: true,
         }
-
-        curr_event = events.next();
     }
 }
 
-type Column<'e> = Vec<(pulldown_cmark::Event<'e>, Range<usize>)>;
-type Row<'e> = Vec<Column<'e>>;
-
-pub struct Table<'e> {
-    pub header: Row<'e>,
-    pub rows: Vec<Row<'e>>,
-}
-
-fn parse_row<'e>(
-    events: &mut impl Iterator<Item = (pulldown_cmark::Event<'e>, Range<usize>)>,
-) -> Vec<Column<'e>> {
-    let mut row = Vec::new();
-    let mut column = Vec::new();
-
-    for (e, src_span) in events.by_ref() {
-        if let pulldown_cmark::Event::End(pulldown_cmark::TagEnd::TableCell) = e {
-            row.push(column);
-            column = Vec::new();
-        }
+impl Newline {
+    pub fn can_insert_end(&self) -> bool {
+        self.should_end_newline && self.should_end_newline_forced
+    }
 
-        if let pulldown_cmark::Event::End(pulldown_cmark::TagEnd::TableHead) = e {
-            break;
-        }
+    pub fn can_insert_start(&self) -> bool {
+        self.should_start_newline && !self.should_not_start_newline_forced
+    }
 
-        if let pulldown_cmark::Event::End(pulldown_cmark::TagEnd::TableRow) = e {
-            break;
+    pub fn try_insert_start(&self, ui: &mut egui::Ui) {
+        if self.can_insert_start() {
+            crate::newline(ui);
         }
-
-        column.push((e, src_span));
     }
 
-    row
-}
-
-pub fn parse_table<'e>(events: &mut impl Iterator<Item = EventIteratorItem<'e>>) -> Table<'e> {
-    let mut all_events = delayed_events(events, |end| matches!(end, pulldown_cmark::TagEnd::Table))
-        .into_iter()
-        .peekable();
-
-    let header = parse_row(&mut all_events);
-
-    let mut rows = Vec::new();
-    while all_events.peek().is_some() {
-        let row = parse_row(&mut all_events);
-        rows.push(row);
+    pub fn try_insert_end(&self, ui: &mut egui::Ui) {
+        if self.can_insert_end() {
+            crate::newline(ui);
+        }
     }
-
-    Table { header, rows }
 }
 
-/// try to parse events as an alert quote block. This ill modify the events
-/// to remove the parsed text that should not be rendered.
-/// Assumes that the first element is a Paragraph
+/// Try to parse the blocks of a block quote as an alert. The first block must be a
+/// paragraph that starts with the identifier of the alert, e.g. `[!TIP]`, which is not
+/// shown. Returns the alert and what is left of the first block.
 pub fn parse_alerts<'a>(
     alerts: &'a AlertBundle,
-    events: &mut Vec<(pulldown_cmark::Event<'_>, Range<usize>)>,
-) -> Option<&'a Alert> {
+    blocks: &[Block],
+) -> Option<(&'a Alert, Option<Block>)> {
     // no point in parsing if there are no alerts to render
-    if !alerts.is_empty() {
-        let mut alert_ident = "".to_owned();
-        let mut alert_ident_ends_at = 0;
-        let mut has_extra_line = false;
-
-        for (i, (e, _src_span)) in events.iter().enumerate() {
-            if let pulldown_cmark::Event::End(_) = e {
-                // > [!TIP]
-                // >
-                // > Detect the first paragraph
-                // In this case the next text will be within a paragraph so it is better to remove
-                // the entire paragraph
-                alert_ident_ends_at = i;
-                has_extra_line = true;
-                break;
-            }
-
-            if let pulldown_cmark::Event::SoftBreak = e {
-                // > [!NOTE]
-                // > this is valid and will produce a soft break
-                alert_ident_ends_at = i;
-                break;
-            }
+    if alerts.is_empty() {
+        return None;
+    }
 
-            if let pulldown_cmark::Event::HardBreak = e {
-                // > [!NOTE]<whitespace>
-                // > this is valid and will produce a hard break
+    let Some(Block {
+        kind: BlockKind::Paragraph(content),
+        span,
+    }) = blocks.first()
+    else {
+        return None;
+    };
+
+    let mut alert_ident = String::new();
+    let mut alert_ident_ends_at = content.len();
+    for (i, inline) in content.iter().enumerate() {
+        match &inline.kind {
+            InlineKind::Text(text) => alert_ident += text,
+            // > [!NOTE]
+            // > this is valid and will produce a soft break
+            //
+            // > [!NOTE]<whitespace>
+            // > this is valid and will produce a hard break
+            InlineKind::SoftBreak | InlineKind::HardBreak => {
                 alert_ident_ends_at = i;
                 break;
             }
-
-            if let pulldown_cmark::Event::Text(text) = e {
-                alert_ident += text;
-            }
-        }
-
-        let alert = try_get_alert(alerts, &alert_ident);
-
-        if alert.is_some() {
-            // remove the text that identifies it as an alert so that it won't end up in the
-            // render
-            //
-            // FIMXE: performance improvement potential
-            if has_extra_line {
-                for _ in 0..=alert_ident_ends_at {
-                    events.remove(0);
-                }
-            } else {
-                for _ in 0..alert_ident_ends_at {
-                    // the first element must be kept as it _should_ be Paragraph
-                    events.remove(1);
-                }
-            }
+            _ => return None,
         }
-
-        alert
-    } else {
-        None
     }
+
+    let alert = try_get_alert(alerts, &alert_ident)?;
+
+    // > [!TIP]
+    // >
+    // > Detect the first paragraph
+    // In this case the next text will be within a paragraph so it is better to remove
+    // the entire paragraph
+    let rest = content.get(alert_ident_ends_at + 1..).map(|rest| Block {
+        kind: BlockKind::Paragraph(rest.to_vec()),
+        span: span.clone(),
+    });
+    Some((alert, rest))
 }
 
 /// Supported pulldown_cmark options
@@ -0,0 +1,282 @@
+//! Styling of the rendered markdown
+//!
+//! Pass a [`CommonMarkTheme`] to `CommonMarkViewer::theme` to change how headings,
+//! links, block quotes, lists, tables and code blocks look. The macros can't be
+//! customized and always use the default theme.
+
+use crate::misc::DiffLine;
+use egui::emath::OrderedFloat;
+use egui::{Color32, FontFamily, Stroke, Ui};
+
+/// Visual styling of the rendered markdown.
+///
+/// Colors that are `None` follow the current [`egui::Visuals`], so the default theme
+/// works with both light and dark mode.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CommonMarkTheme {
+    /// Styling of heading level 1 through 6
+    pub headings: [HeadingTheme; 6],
+    /// Color of links. Defaults to [`egui::Visuals::hyperlink_color`]
+    pub link_color: Option<Color32>,
+    /// Background of inline code. Defaults to [`egui::Visuals::code_bg_color`]
+    pub code_background: Option<Color32>,
+    /// Color of the text and the bar in block quotes. Defaults to
+    /// [`egui::Visuals::weak_text_color`]
+    pub quote_color: Option<Color32>,
+    /// Width of the bar in front of block quotes
+    pub quote_bar_width: f32,
+    /// Space between the left edge and the content of block quotes
+    pub quote_indent: i8,
+    /// Stroke of horizontal rules. Defaults to the stroke of egui's separator
+    pub rule_stroke: Option<Stroke>,
+    /// Color of bullet points and numbers in lists. Defaults to
+    /// [`egui::Visuals::strong_text_color`]
+    pub list_marker_color: Option<Color32>,
+    /// Radius of bullet points as a fraction of the row height
+    pub bullet_radius: f32,
+    /// Space between the list marker and the content of the item
+    pub list_marker_spacing: f32,
+    /// Whether every other table row should have a different background
+    pub table_striped: bool,
+    /// Background of the striped table rows. Defaults to [`egui::Visuals::faint_bg_color`]
+    pub table_stripe_color: Option<Color32>,
+    /// Background of highlighted lines in code blocks, e.g. ```` ```rust {2} ````.
+    /// Defaults to a faded [`egui::style::Selection::bg_fill`]
+    pub code_highlight_color: Option<Color32>,
+    /// Color of added lines in ```` ```diff ```` code blocks. The background of the line
+    /// is a faded version of it
+    pub diff_added_color: Option<Color32>,
+    /// Color of removed lines in ```` ```diff ```` code blocks. The background of the line
+    /// is a faded version of it
+    pub diff_removed_color: Option<Color32>,
+    /// Color of `@@ -1,3 +1,4 @@` lines in ```` ```diff ```` code blocks. Defaults to
+    /// [`egui::Visuals::hyperlink_color`]
+    pub diff_hunk_color: Option<Color32>,
+}
+
+impl Default for CommonMarkTheme {
+    fn default() -> Self {
+        Self {
+            headings: [
+                HeadingTheme::new(1.0, true),
+                HeadingTheme::new(0.835, true),
+                HeadingTheme::new(0.668, true),
+                HeadingTheme::new(0.501, true),
+                HeadingTheme::new(0.334, false),
+                HeadingTheme::new(0.167, false),
+            ],
+            link_color: None,
+            code_background: None,
+            quote_color: None,
+            quote_bar_width: 3.0,
+            quote_indent: 10,
+            rule_stroke: None,
+            list_marker_color: None,
+            bullet_radius: 1.0 / 6.0,
+            list_marker_spacing: 4.0,
+            table_striped: true,
+            table_stripe_color: None,
+            code_highlight_color: None,
+            diff_added_color: None,
+            diff_removed_color: None,
+            diff_hunk_color: None,
+        }
+    }
+}
+
+/// The theme is hashed to tell when the heights of rendered blocks have to be measured
+/// again
+impl std::hash::Hash for CommonMarkTheme {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let Self {
+            headings,
+            link_color,
+            code_background,
+            quote_color,
+            quote_bar_width,
+            quote_indent,
+            rule_stroke,
+            list_marker_color,
+            bullet_radius,
+            list_marker_spacing,
+            table_striped,
+            table_stripe_color,
+            code_highlight_color,
+            diff_added_color,
+            diff_removed_color,
+            diff_hunk_color,
+        } = self;
+
+        headings.hash(state);
+        link_color.hash(state);
+        code_background.hash(state);
+        quote_color.hash(state);
+        OrderedFloat(*quote_bar_width).hash(state);
+        quote_indent.hash(state);
+        rule_stroke.hash(state);
+        list_marker_color.hash(state);
+        OrderedFloat(*bullet_radius).hash(state);
+        OrderedFloat(*list_marker_spacing).hash(state);
+        table_striped.hash(state);
+        table_stripe_color.hash(state);
+        code_highlight_color.hash(state);
+        diff_added_color.hash(state);
+        diff_removed_color.hash(state);
+        diff_hunk_color.hash(state);
+    }
+}
+
+impl CommonMarkTheme {
+    /// The styling of a heading where level 0 is `#` and 5 is `######`
+    pub fn heading(&self, level: u8) -> &HeadingTheme {
+        &self.headings[(level as usize).min(self.headings.len() - 1)]
+    }
+
+    /// The color of block quotes, falling back to the current visuals
+    pub fn quote_color(&self, ui: &Ui) -> Color32 {
+        self.quote_color
+            .unwrap_or_else(|| ui.visuals().weak_text_color())
+    }
+
+    /// The color of list markers, falling back to the current visuals
+    pub fn list_marker_color(&self, ui: &Ui) -> Color32 {
+        self.list_marker_color
+            .unwrap_or_else(|| ui.visuals().strong_text_color())
+    }
+
+    /// The background of highlighted code lines, falling back to the current visuals
+    pub fn code_highlight_color(&self, ui: &Ui) -> Color32 {
+        self.code_highlight_color
+            .unwrap_or_else(|| ui.visuals().selection.bg_fill.gamma_multiply(0.4))
+    }
+
+    /// The color of added diff lines, falling back to a green that suits the visuals
+    pub fn diff_added_color(&self, ui: &Ui) -> Color32 {
+        self.diff_added_color.unwrap_or(if ui.visuals().dark_mode {
+            Color32::from_rgb(0x3f, 0xb9, 0x50)
+        } else {
+            Color32::from_rgb(0x1a, 0x7f, 0x37)
+        })
+    }
+
+    /// The color of removed diff lines, falling back to a red that suits the visuals
+    pub fn diff_removed_color(&self, ui: &Ui) -> Color32 {
+        self.diff_removed_color
+            .unwrap_or(if ui.visuals().dark_mode {
+                Color32::from_rgb(0xf8, 0x51, 0x49)
+            } else {
+                Color32::from_rgb(0xcf, 0x22, 0x2e)
+            })
+    }
+
+    /// The color of diff hunk headers, falling back to the current visuals
+    pub fn diff_hunk_color(&self, ui: &Ui) -> Color32 {
+        self.diff_hunk_color
+            .unwrap_or_else(|| ui.visuals().hyperlink_color)
+    }
+
+    /// The background of a line in a ```` ```diff ```` code block
+    #[doc(hidden)]
+    pub fn diff_background(&self, ui: &Ui, line: DiffLine) -> Option<Color32> {
+        let color = match line {
+            DiffLine::Added => self.diff_added_color(ui),
+            DiffLine::Removed => self.diff_removed_color(ui),
+            DiffLine::Hunk => self.diff_hunk_color(ui),
+            DiffLine::Context | DiffLine::Header => return None,
+        };
+        Some(color.gamma_multiply(0.15))
+    }
+
+    /// The grid that tables are laid out in
+    #[doc(hidden)]
+    pub fn table_grid(&self, id: egui::Id) -> egui::Grid {
+        let grid = egui::Grid::new(id).striped(self.table_striped);
+        match self.table_stripe_color {
+            Some(color) if self.table_striped => {
+                grid.with_row_color(move |row, _| (row % 2 == 1).then_some(color))
+            }
+            _ => grid,
+        }
+    }
+}
+
+/// Styling of a single heading level
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct HeadingTheme {
+    /// Size of the font. When `None` the size is interpolated between the size of
+    /// [`egui::TextStyle::Body`] (0.0) and [`egui::TextStyle::Heading`] (1.0) using `scale`
+    pub size: Option<f32>,
+    /// Where the size is between the body (0.0) and heading (1.0) text size
+    pub scale: f32,
+    /// Font family. Defaults to the family of [`egui::TextStyle::Heading`] for the
+    /// first level and [`egui::TextStyle::Body`] for the others
+    pub family: Option<FontFamily>,
+    /// Defaults to the text color of the surrounding text
+    pub color: Option<Color32>,
+    /// Whether the heading is shown with [`egui::RichText::strong`]
+    pub strong: bool,
+}
+
+impl Default for HeadingTheme {
+    fn default() -> Self {
+        Self::new(1.0, true)
+    }
+}
+
+impl std::hash::Hash for HeadingTheme {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let Self {
+            size,
+            scale,
+            family,
+            color,
+            strong,
+        } = self;
+
+        size.map(OrderedFloat).hash(state);
+        OrderedFloat(*scale).hash(state);
+        family.hash(state);
+        color.hash(state);
+        strong.hash(state);
+    }
+}
+
+impl HeadingTheme {
+    /// A heading of the given `scale` with the default family and color
+    pub fn new(scale: f32, strong: bool) -> Self {
+        Self {
+            size: None,
+            scale,
+            family: None,
+            color: None,
+            strong,
+        }
+    }
+
+    /// Whether the heading at `level` is shown with [`egui::TextStyle::Heading`] as is,
+    /// which already has the size of a `scale` of 1.0
+    pub fn is_text_style_heading(&self, level: u8) -> bool {
+        level == 0 && self.family.is_none() && self.size.is_none() && self.scale == 1.0
+    }
+
+    /// The font size of the heading in the given ui
+    pub fn size(&self, ui: &Ui) -> f32 {
+        self.size.unwrap_or_else(|| {
+            let max_height = ui
+                .style()
+                .text_styles
+                .get(&egui::TextStyle::Heading)
+                .map_or(32.0, |d| d.size);
+            let min_height = ui
+                .style()
+                .text_styles
+                .get(&egui::TextStyle::Body)
+                .map_or(14.0, |d| d.size);
+            min_height + (max_height - min_height) * self.scale
+        })
+    }
+}
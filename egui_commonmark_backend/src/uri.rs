@@ -0,0 +1,275 @@
+//! Resolution of relative references as described in RFC 3986 section 5.2
+
+/// The components of a uri reference. Components that are `None` are undefined, which is
+/// different from being empty.
+struct Reference<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Reference<'a> {
+    /// Split a reference into its components. Equivalent to the regular expression in
+    /// appendix B of the RFC.
+    fn parse(s: &'a str) -> Self {
+        let (s, fragment) = match s.split_once('#') {
+            Some((s, fragment)) => (s, Some(fragment)),
+            None => (s, None),
+        };
+
+        let (s, query) = match s.split_once('?') {
+            Some((s, query)) => (s, Some(query)),
+            None => (s, None),
+        };
+
+        let (scheme, s) = match s.split_once(':') {
+            Some((scheme, rest)) if is_scheme(scheme) => (Some(scheme), rest),
+            _ => (None, s),
+        };
+
+        let (authority, path) = match s.strip_prefix("//") {
+            Some(s) => {
+                let end = s.find('/').unwrap_or(s.len());
+                (Some(&s[..end]), &s[end..])
+            }
+            None => (None, s),
+        };
+
+        Self {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Whether `uri` starts with a scheme such as `https:` or `data:`. Windows drive letters
+/// such as the `C` in `C:\docs\a.png` are not counted as schemes.
+pub(crate) fn has_scheme(uri: &str) -> bool {
+    Reference::parse(uri)
+        .scheme
+        .is_some_and(|scheme| scheme.len() > 1)
+}
+
+/// Resolve `reference` against `base`, e.g. `../img/a.png` against
+/// `file:///docs/guide/intro.md` becomes `file:///docs/img/a.png`.
+///
+/// The base must be a uri. Windows paths such as `C:\docs\intro.md` would be read as
+/// the scheme `C`, so references are returned unchanged for them. Use
+/// `file:///C:/docs/intro.md` instead.
+pub fn resolve(base: &str, reference: &str) -> String {
+    let r = Reference::parse(reference);
+    // Already absolute. Returned as is so that data urls are never touched
+    if r.scheme.is_some() {
+        return reference.to_owned();
+    }
+
+    let base = Reference::parse(base);
+    if base.scheme.is_some_and(|scheme| scheme.len() == 1) {
+        return reference.to_owned();
+    }
+    let (scheme, authority, path, query) = if r.authority.is_some() {
+        (
+            base.scheme,
+            r.authority,
+            remove_dot_segments(r.path),
+            r.query,
+        )
+    } else if r.path.is_empty() {
+        (
+            base.scheme,
+            base.authority,
+            base.path.to_owned(),
+            r.query.or(base.query),
+        )
+    } else if r.path.starts_with('/') {
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(r.path),
+            r.query,
+        )
+    } else {
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(&merge(&base, r.path)),
+            r.query,
+        )
+    };
+
+    let mut result = String::new();
+    if let Some(scheme) = scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+
+    result.push_str(&path);
+
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+
+    if let Some(fragment) = r.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+
+    result
+}
+
+fn merge(base: &Reference, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        format!("/{path}")
+    } else {
+        let dir = base.path.rfind('/').map_or("", |i| &base.path[..=i]);
+        format!("{dir}{path}")
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::with_capacity(path.len());
+
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            let last = output.rfind('/').unwrap_or(0);
+            output.truncate(last);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // Move the first path segment, including its initial slash, to the output
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    fn assert_resolves(examples: &[(&str, &str)]) {
+        for (reference, expected) in examples {
+            assert_eq!(resolve(BASE, reference), *expected, "{reference}");
+        }
+    }
+
+    /// RFC 3986 section 5.4.1
+    #[test]
+    fn normal_examples() {
+        assert_resolves(&[
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../", "http://a/"),
+            ("../../g", "http://a/g"),
+        ]);
+    }
+
+    /// RFC 3986 section 5.4.2
+    #[test]
+    fn abnormal_examples() {
+        assert_resolves(&[
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            (".g", "http://a/b/c/.g"),
+            ("g..", "http://a/b/c/g.."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g?y/../x", "http://a/b/c/g?y/../x"),
+            ("g#s/./x", "http://a/b/c/g#s/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ]);
+    }
+
+    #[test]
+    fn file_uris() {
+        assert_eq!(
+            resolve("file:///docs/guide/intro.md", "../img/a.png"),
+            "file:///docs/img/a.png"
+        );
+        assert_eq!(
+            resolve("file:///C:/docs/intro.md", "a.png"),
+            "file:///C:/docs/a.png"
+        );
+    }
+
+    #[test]
+    fn schemes() {
+        assert!(has_scheme("https://example.org/a.png"));
+        assert!(has_scheme("data:image/png;base64,iVBORw0KGgo="));
+        assert!(has_scheme("bytes://a.png"));
+        assert!(!has_scheme("img/a.png"));
+        assert!(!has_scheme("img/a:b.png"));
+        assert!(!has_scheme("a.png?v=http://example.org"));
+        assert!(!has_scheme("C:\\docs\\a.png"));
+        assert!(!has_scheme("C:/docs/a.png"));
+    }
+
+    #[test]
+    fn windows_paths_are_not_resolved_against() {
+        assert_eq!(resolve("C:\\docs\\intro.md", "a.png"), "a.png");
+        assert_eq!(resolve("C:/docs/intro.md", "../a.png"), "../a.png");
+    }
+}
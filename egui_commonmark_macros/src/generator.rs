@@ -1,50 +1,29 @@
-use std::iter::Peekable;
-
 use egui_commonmark_backend::{
-    CodeBlock, CommonMarkOptions, Image, alerts::Alert, misc::Style, pulldown::*,
+    CodeBlock, CommonMarkOptions, Image,
+    alerts::Alert,
+    document::{
+        Block, BlockKind, DefinitionListItem, Document, HeadingLevel, Inline, InlineKind, ListItem,
+        TableRow,
+    },
+    misc::Style,
+    pulldown::*,
+    theme::CommonMarkTheme,
 };
 
 use proc_macro2::TokenStream;
-use pulldown_cmark::{CowStr, HeadingLevel};
 use quote::quote;
 use syn::Expr;
 
-struct Newline {
-    /// Whether a newline should not be inserted before a widget. This is only for
-    /// the first widget.
-    should_not_start_newline_forced: bool,
-    /// Whether an element should insert a newline before it
-    should_start_newline: bool,
-    /// Whether an element should end it's own line using a newline
-    /// This will have to be set to false in cases such as when blocks are within
-    /// a list.
-    should_end_newline: bool,
-    /// only false when the widget is the last one.
-    should_end_newline_forced: bool,
-}
-
-impl Default for Newline {
-    fn default() -> Self {
-        Self {
-            should_not_start_newline_forced: true,
-            should_start_newline: true,
-            should_end_newline: true,
-            should_end_newline_forced: true,
-        }
-    }
+/// Generates the newlines of [`Newline`] instead of adding them to a ui
+trait NewlineTokens {
+    #[must_use]
+    fn start_tokens(&self) -> TokenStream;
+    #[must_use]
+    fn end_tokens(&self) -> TokenStream;
 }
 
-impl Newline {
-    pub fn can_insert_end(&self) -> bool {
-        self.should_end_newline && self.should_end_newline_forced
-    }
-
-    pub fn can_insert_start(&self) -> bool {
-        self.should_start_newline && !self.should_not_start_newline_forced
-    }
-
-    #[must_use]
-    pub fn try_insert_start(&self) -> TokenStream {
+impl NewlineTokens for Newline {
+    fn start_tokens(&self) -> TokenStream {
         if self.can_insert_start() {
             quote!(egui_commonmark_backend::newline(ui);)
         } else {
@@ -52,8 +31,7 @@ impl Newline {
         }
     }
 
-    #[must_use]
-    pub fn try_insert_end(&self) -> TokenStream {
+    fn end_tokens(&self) -> TokenStream {
         if self.can_insert_end() {
             quote!(egui_commonmark_backend::newline(ui);)
         } else {
@@ -110,18 +88,22 @@ impl List {
 
             if let Some(number) = &mut item.current_number {
                 let num = number.to_string();
-                stream.extend(quote!( egui_commonmark_backend::number_point(ui, #num);));
+                stream.extend(
+                    quote!( egui_commonmark_backend::number_point(ui, &options.theme, #num);),
+                );
                 *number += 1;
             } else if len > 1 {
-                stream.extend(quote!( egui_commonmark_backend::bullet_point_hollow(ui);));
+                stream.extend(
+                    quote!( egui_commonmark_backend::bullet_point_hollow(ui, &options.theme);),
+                );
             } else {
-                stream.extend(quote!( egui_commonmark_backend::bullet_point(ui);));
+                stream.extend(quote!( egui_commonmark_backend::bullet_point(ui, &options.theme);));
             }
         } else {
             unreachable!();
         }
 
-        stream.extend(quote!( ui.add_space(4.0); ));
+        stream.extend(quote!( ui.add_space(options.theme.list_marker_spacing); ));
         stream
     }
 
@@ -155,6 +137,7 @@ impl StyledText {
 
 pub struct StyledLink {
     pub destination: String,
+    pub title: String,
     pub text: Vec<StyledText>,
 }
 
@@ -163,12 +146,6 @@ pub struct StyledImage {
     pub alt_text: Vec<StyledText>,
 }
 
-#[derive(Default)]
-struct DefinitionList {
-    is_first_item: bool,
-    is_def_list_def: bool,
-}
-
 pub(crate) struct CommonMarkViewerInternal {
     curr_table: usize,
     text_style: Style,
@@ -176,11 +153,14 @@ pub(crate) struct CommonMarkViewerInternal {
     link: Option<StyledLink>,
     image: Option<StyledImage>,
     line: Newline,
-    code_block: Option<CodeBlock>,
-    is_list_item: bool,
-    def_list: DefinitionList,
-    is_table: bool,
-    is_blockquote: bool,
+    /// The theme used when generating text. The macros can't be customized so this is
+    /// always the default
+    theme: CommonMarkTheme,
+
+    /// Whether `^text^` is parsed as superscript
+    superscript: bool,
+    /// Whether `~text~` is parsed as subscript instead of strikethrough
+    subscript: bool,
 
     /// Informs that a calculation of heading sizes is required.
     /// This will dump min and max text size at the top of the macro output
@@ -189,7 +169,7 @@ pub(crate) struct CommonMarkViewerInternal {
 }
 
 impl CommonMarkViewerInternal {
-    pub fn new() -> Self {
+    pub fn new(superscript: bool, subscript: bool) -> Self {
         Self {
             curr_table: 0,
             text_style: Style::default(),
@@ -197,11 +177,9 @@ impl CommonMarkViewerInternal {
             link: None,
             image: None,
             line: Newline::default(),
-            is_list_item: false,
-            def_list: Default::default(),
-            code_block: None,
-            is_table: false,
-            is_blockquote: false,
+            theme: CommonMarkTheme::default(),
+            superscript,
+            subscript,
             dumps_heading: false,
         }
     }
@@ -209,28 +187,22 @@ impl CommonMarkViewerInternal {
 
 impl CommonMarkViewerInternal {
     pub fn show(&mut self, ui: Expr, cache: Expr, text: &str) -> TokenStream {
-        let mut events = pulldown_cmark::Parser::new_ext(text, parser_options())
-            .into_offset_iter()
-            .enumerate()
-            .peekable();
+        // The extensions that only add syntax are always enabled. Superscript and
+        // subscript are opt-in like in the viewer as they change how existing markdown
+        // is shown.
+        let mut parser_options = parser_options();
+        if self.superscript {
+            parser_options |= pulldown_cmark::Options::ENABLE_SUPERSCRIPT;
+        }
+        if self.subscript {
+            parser_options |= pulldown_cmark::Options::ENABLE_SUBSCRIPT;
+        }
+        let document = Document::parse(text, parser_options);
 
         let options = CommonMarkOptions::default();
         let mut stream = TokenStream::new();
 
-        let mut event_stream = TokenStream::new();
-        while let Some((i, (e, _))) = events.next() {
-            if events.peek().is_none() {
-                self.line.should_end_newline_forced = false;
-            }
-
-            let e = self.process_event(&mut events, e, &cache, &options);
-
-            if i == 0 {
-                self.line.should_not_start_newline_forced = false;
-            }
-
-            event_stream.extend(e);
-        }
+        let block_stream = self.blocks(&document.blocks, true, &cache, &options);
 
         stream.extend(quote!(
             egui_commonmark_backend::prepare_show(#cache, ui.ctx());
@@ -242,7 +214,7 @@ impl CommonMarkViewerInternal {
                 ui.spacing_mut().item_spacing.x = 0.0;
                 let height = ui.text_style_height(&egui::TextStyle::Body);
                 ui.set_row_height(height);
-                #event_stream
+                #block_stream
             })
         ));
 
@@ -265,289 +237,410 @@ impl CommonMarkViewerInternal {
         })
     }
 
-    fn process_event<'e>(
+    /// The blocks of a container. When the blocks end the document the last block does
+    /// not end its line.
+    fn blocks(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        event: pulldown_cmark::Event,
+        blocks: &[Block],
+        ends_document: bool,
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
-        let mut stream = self.event(event, cache, options);
+        let mut stream = TokenStream::new();
+        for (i, block) in blocks.iter().enumerate() {
+            let is_last = ends_document && i + 1 == blocks.len();
+            stream.extend(self.block(block, is_last, cache, options));
+        }
+        stream
+    }
 
-        stream.extend(self.item_list_wrapping(events, cache, options));
-        stream.extend(self.def_list_def_wrapping(events, cache, options));
-        stream.extend(self.table(events, cache, options));
-        stream.extend(self.blockquote(events, cache, options));
+    /// Starts a new line for a block unless it is the first block
+    fn start_line(&mut self) -> TokenStream {
+        let stream = self.line.start_tokens();
+        self.line.should_not_start_newline_forced = false;
         stream
     }
 
-    fn def_list_def_wrapping<'e>(
+    /// Ends the line of a block unless it is the last block
+    fn end_line(&mut self, is_last: bool) -> TokenStream {
+        if is_last {
+            self.line.should_end_newline_forced = false;
+        }
+        self.line.end_tokens()
+    }
+
+    fn block(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        block: &Block,
+        is_last: bool,
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
-        let mut stream = TokenStream::new();
-        if self.def_list.is_def_list_def {
-            self.def_list.is_def_list_def = false;
+        match &block.kind {
+            BlockKind::Paragraph(content) => {
+                let mut stream = self.start_line();
+                stream.extend(self.inlines(content, cache, options));
+                stream.extend(self.end_line(is_last));
+                stream
+            }
+            BlockKind::Plain(content) => {
+                self.line.should_not_start_newline_forced = false;
+                self.inlines(content, cache, options)
+            }
+            BlockKind::Heading { level, content, .. } => {
+                let mut stream = quote!(egui_commonmark_backend::newline(ui););
+                self.line.should_not_start_newline_forced = false;
+                self.text_style.heading = Some(match level {
+                    HeadingLevel::H1 => 0,
+                    HeadingLevel::H2 => 1,
+                    HeadingLevel::H3 => 2,
+                    HeadingLevel::H4 => 3,
+                    HeadingLevel::H5 => 4,
+                    HeadingLevel::H6 => 5,
+                });
+                stream.extend(self.inlines(content, cache, options));
+                self.text_style.heading = None;
+                stream.extend(self.end_line(is_last));
+                stream
+            }
+            BlockKind::BlockQuote { blocks, .. } => {
+                self.blockquote(blocks, is_last, cache, options)
+            }
+            BlockKind::CodeBlock { info, content } => {
+                let mut stream = self.start_line();
 
-            let item_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::DefinitionListDefinition)
-            });
+                let curr_table = self.curr_table;
+                let block = CodeBlock {
+                    lang: info.as_deref(),
+                    content,
+                };
+                if block.csv_delimiter().is_some() {
+                    self.curr_table += 1;
+                }
 
-            let mut events_iter = item_events.into_iter().enumerate().peekable();
+                stream.extend(if let Some(lang) = info {
+                    quote!(egui_commonmark_backend::CodeBlock {
+                        lang: Some(#lang), content: #content}
+                        .end(ui, #cache, &options, max_width, #curr_table);)
+                } else {
+                    quote!(egui_commonmark_backend::CodeBlock {
+                        lang: None, content: #content}
+                        .end(ui, #cache, &options, max_width, #curr_table);)
+                });
 
-            let mut inner = TokenStream::new();
+                stream.extend(self.end_line(is_last));
+                stream
+            }
+            BlockKind::HtmlBlock(lines) => {
+                let mut stream = self.start_line();
+                stream.extend(self.inlines(lines, cache, options));
+                stream
+            }
+            BlockKind::List { start, items } => self.list(*start, items, is_last, cache, options),
+            BlockKind::FootnoteDefinition { label, blocks } => {
+                let mut stream = self.start_line();
 
-            stream.extend(self.line.try_insert_start());
+                self.line.should_start_newline = false;
+                self.line.should_end_newline = false;
+                stream.extend(scroll_to_footnote(
+                    cache,
+                    quote!(egui_commonmark_backend::footnote(ui, #cache, #label, &mut scroll_to);),
+                ));
+                stream.extend(self.blocks(blocks, false, cache, options));
 
-            // Proccess a single event separately so that we do not insert spaces where we do not
-            // want them
-            self.line.should_start_newline = false;
-            if let Some((_, (e, _))) = events_iter.next() {
-                inner.extend(self.process_event(&mut events_iter, e, cache, options));
+                self.line.should_start_newline = true;
+                self.line.should_end_newline = true;
+                stream.extend(self.end_line(is_last));
+                stream
             }
+            BlockKind::Table {
+                alignments,
+                header,
+                rows,
+            } => self.table(alignments, header, rows, is_last, cache, options),
+            BlockKind::DefinitionList(items) => {
+                let mut stream = self.start_line();
+
+                for (i, item) in items.iter().enumerate() {
+                    match item {
+                        DefinitionListItem::Title { content, .. } => {
+                            // The list has already started the line of the first title
+                            if i > 0 {
+                                stream.extend(self.line.start_tokens());
+                            }
+                            stream.extend(self.inlines(content, cache, options));
+                        }
+                        DefinitionListItem::Definition { blocks, .. } => {
+                            stream.extend(self.definition(blocks, cache, options));
+
+                            // Only end the definition items line if it is not the last
+                            // element in the list
+                            if i + 1 < items.len() {
+                                stream.extend(self.line.end_tokens());
+                            }
+                        }
+                    }
+                }
 
-            self.line.should_start_newline = true;
-            self.line.should_end_newline = false;
-            while let Some((_, (e, _))) = events_iter.next() {
-                inner.extend(self.process_event(&mut events_iter, e, cache, options));
+                stream.extend(self.end_line(is_last));
+                stream
             }
-            self.line.should_end_newline = true;
-
-            let spaces = " ".repeat(options.indentation_spaces);
-            stream.extend(quote!(ui.label(#spaces);));
-
-            // Required to ensure that the content is aligned with the identation
-            stream.extend(quote!(ui.horizontal_wrapped(|ui| {
-                    #inner
-            });));
+            BlockKind::MetadataBlock { .. } => TokenStream::new(),
+            BlockKind::Rule => {
+                if is_last {
+                    self.line.should_end_newline_forced = false;
+                }
+                let mut stream = self.start_line();
 
-            // Only end the definition items line if it is not the last element in the list
-            if !matches!(
-                events.peek(),
-                Some((
-                    _,
-                    (
-                        pulldown_cmark::Event::End(pulldown_cmark::TagEnd::DefinitionList),
-                        _
-                    )
-                ))
-            ) {
-                stream.extend(self.line.try_insert_end());
+                let end = self.line.can_insert_end();
+                stream.extend(quote!(egui_commonmark_backend::rule(ui, &options.theme, #end);));
+                stream
             }
         }
-
-        stream
     }
 
-    fn item_list_wrapping<'e>(
+    fn list(
         &mut self,
-        events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+        start: Option<u64>,
+        items: &[ListItem],
+        is_last: bool,
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
         let mut stream = TokenStream::new();
-        if self.is_list_item {
-            self.is_list_item = false;
 
-            let item_events = delayed_events_list_item(events);
-            let mut events_iter = item_events.into_iter().enumerate().peekable();
+        if !self.list.is_inside_a_list() && self.line.can_insert_start() {
+            stream.extend(quote!( egui_commonmark_backend::newline(ui);));
+        }
+        self.line.should_not_start_newline_forced = false;
 
-            let mut inner = TokenStream::new();
+        if let Some(number) = start {
+            self.list.start_level_with_number(number);
+        } else {
+            self.list.start_level_without_number();
+        }
+        self.line.should_start_newline = false;
+        self.line.should_end_newline = false;
 
-            while let Some((_, (e, _))) = events_iter.next() {
-                inner.extend(self.process_event(&mut events_iter, e, cache, options));
-            }
+        for item in items {
+            stream.extend(self.list.start_item(options));
 
             // Required to ensure that the content of the list item is aligned with
-            // the * or - when wrapping
+            // the * or - when wrapping. Nested lists start their items on lines of
+            // their own so they are left out.
+            let nested = item
+                .blocks
+                .iter()
+                .position(|block| matches!(block.kind, BlockKind::List { .. }))
+                .unwrap_or(item.blocks.len());
+            let inner = self.blocks(&item.blocks[..nested], false, cache, options);
             stream.extend(quote!(ui.horizontal_wrapped(|ui| {
                     #inner
             });));
+            stream.extend(self.blocks(&item.blocks[nested..], false, cache, options));
+        }
+
+        if self.list.is_last_level() {
+            self.line.should_start_newline = true;
+            self.line.should_end_newline = true;
+        }
+
+        if is_last {
+            self.line.should_end_newline_forced = false;
+        }
+        stream.extend(self.list.end_level(self.line.can_insert_end()));
+
+        if !self.list.is_inside_a_list() {
+            // Reset all the state and make it ready for the next list that occurs
+            self.list = List::default();
         }
 
         stream
     }
 
-    fn blockquote<'e>(
+    fn definition(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        blocks: &[Block],
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
-        let mut stream = TokenStream::new();
-        if self.is_blockquote {
-            let mut collected_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::BlockQuote(_))
-            });
-            stream.extend(self.line.try_insert_start());
-
-            // See non proc macro version for reaseon
-            self.line.should_not_start_newline_forced = false;
-            if let Some(alert) = parse_alerts(&options.alerts, &mut collected_events) {
-                let Alert {
-                    accent_color,
-                    icon,
-                    identifier,
-                    identifier_rendered,
-                } = alert;
-
-                let mut inner = TokenStream::new();
-                for (event, _) in collected_events.into_iter() {
-                    inner.extend(self.event(event, cache, options));
-                }
+        let mut stream = self.line.start_tokens();
+
+        // The first block continues the line of the indentation so that we do not insert
+        // spaces where we do not want them
+        self.line.should_start_newline = false;
+        self.line.should_end_newline = false;
+        let (first, rest) = blocks.split_at(blocks.len().min(1));
+        let mut inner = self.blocks(first, false, cache, options);
+        self.line.should_start_newline = true;
+        inner.extend(self.blocks(rest, false, cache, options));
+        self.line.should_end_newline = true;
+
+        let spaces = " ".repeat(options.indentation_spaces);
+        stream.extend(quote!(ui.label(#spaces);));
+
+        // Required to ensure that the content is aligned with the identation
+        stream.extend(quote!(ui.horizontal_wrapped(|ui| {
+                #inner
+        });));
 
-                let r = accent_color.r();
-                let g = accent_color.g();
-                let b = accent_color.b();
-                let a = accent_color.a();
-                stream.extend(quote!(
-                egui_commonmark_backend::alert_ui(&egui_commonmark_backend::Alert {
-                    accent_color: egui::Color32::from_rgba_premultiplied(#r, #g, #b, #a),
-                    icon: #icon,
-                    identifier: #identifier.to_owned(),
-                    identifier_rendered: #identifier_rendered.to_owned()
-                }, ui, |ui| {
-                    #inner
-                });));
-            } else {
-                let mut inner = TokenStream::new();
+        stream
+    }
 
-                self.text_style.quote = true;
-                for (event, _) in collected_events {
-                    inner.extend(self.event(event, cache, options));
-                }
-                self.text_style.quote = false;
+    fn blockquote(
+        &mut self,
+        blocks: &[Block],
+        is_last: bool,
+        cache: &Expr,
+        options: &CommonMarkOptions,
+    ) -> TokenStream {
+        // See non proc macro version for reaseon
+        let mut stream = self.start_line();
 
-                stream.extend(quote!(egui_commonmark_backend::blockquote(ui, ui.visuals().weak_text_color(), |ui| {#inner});));
-            }
+        if let Some((alert, first)) = parse_alerts(&options.alerts, blocks) {
+            let Alert {
+                accent_color,
+                icon,
+                identifier,
+                identifier_rendered,
+            } = alert;
 
-            if events.peek().is_none() {
-                self.line.should_end_newline_forced = false;
+            let mut inner = TokenStream::new();
+            if let Some(first) = &first {
+                inner.extend(self.block(first, false, cache, options));
             }
+            inner.extend(self.blocks(&blocks[1..], false, cache, options));
 
-            stream.extend(self.line.try_insert_end());
+            let accent_color = color_tokenstream(*accent_color);
+            stream.extend(quote!(
+            egui_commonmark_backend::alert_ui(&egui_commonmark_backend::Alert {
+                accent_color: #accent_color,
+                icon: #icon,
+                identifier: #identifier.to_owned(),
+                identifier_rendered: #identifier_rendered.to_owned()
+            }, ui, &options.theme, |ui| {
+                #inner
+            });));
+        } else {
+            let quote = std::mem::replace(&mut self.text_style.quote, true);
+            let inner = self.blocks(blocks, false, cache, options);
+            self.text_style.quote = quote;
 
-            self.is_blockquote = false;
+            stream.extend(quote!(egui_commonmark_backend::blockquote(ui, &options.theme, options.theme.quote_color(ui), |ui| {#inner});));
         }
+
+        stream.extend(self.end_line(is_last));
         stream
     }
 
-    fn table<'e>(
+    fn table(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        alignments: &[pulldown_cmark::Alignment],
+        header: &TableRow,
+        rows: &[TableRow],
+        is_last: bool,
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
-        let mut stream = TokenStream::new();
-        if self.is_table {
-            stream.extend(self.line.try_insert_start());
-
-            let Table { header, rows } = parse_table(events);
+        let mut stream = self.start_line();
 
-            let mut header_stream = TokenStream::new();
-            for col in header {
+        let mut content_stream = TokenStream::new();
+        for (r, row) in std::iter::once(header).chain(rows).enumerate() {
+            let mut row_stream = TokenStream::new();
+            for (c, cell) in row.cells.iter().enumerate() {
                 let mut inner = TokenStream::new();
-                for (e, _) in col {
-                    self.line.should_start_newline = false;
-                    self.line.should_end_newline = false;
-                    inner.extend(self.event(e, cache, options));
-                    self.line.should_start_newline = true;
-                    self.line.should_end_newline = true;
+                // Ensure space between cells
+                if c > 0 {
+                    inner.extend(quote!(ui.label("  ");));
                 }
 
-                header_stream.extend(quote!(ui.horizontal(|ui| {#inner});));
-            }
-
-            let mut content_stream = TokenStream::new();
-            for row in rows {
-                let mut row_stream = TokenStream::new();
-                for col in row {
-                    let mut inner = TokenStream::new();
-                    for (e, _) in col {
-                        self.line.should_start_newline = false;
-                        self.line.should_end_newline = false;
-                        inner.extend(self.event(e, cache, options));
-                        self.line.should_start_newline = true;
-                        self.line.should_end_newline = true;
-                    }
-
-                    row_stream.extend(quote!(ui.horizontal(|ui| {#inner});));
-                }
+                self.line.should_start_newline = false;
+                self.line.should_end_newline = false;
+                inner.extend(self.inlines(&cell.content, cache, options));
+                self.line.should_start_newline = true;
+                self.line.should_end_newline = true;
 
-                if !row_stream.is_empty() {
-                    content_stream.extend(quote!(#row_stream ui.end_row();))
-                }
+                row_stream.extend(quote!(alignment.cell(ui, #r, #c, |ui| {#inner});));
             }
 
-            let curr_table = self.curr_table;
-            stream.extend(quote!(
-                egui::Frame::group(ui.style()).show(ui, |ui| {
-                    let id = ui.id().with("_table").with(#curr_table);
-                    egui::Grid::new(id).striped(true).show(ui, |ui| {
-
-                    #header_stream
+            content_stream.extend(quote!(#row_stream ui.end_row();));
+        }
 
-                    ui.end_row();
+        let alignments = alignments.iter().map(|a| match a {
+            pulldown_cmark::Alignment::None => {
+                quote!(egui_commonmark_backend::document::Alignment::None)
+            }
+            pulldown_cmark::Alignment::Left => {
+                quote!(egui_commonmark_backend::document::Alignment::Left)
+            }
+            pulldown_cmark::Alignment::Center => {
+                quote!(egui_commonmark_backend::document::Alignment::Center)
+            }
+            pulldown_cmark::Alignment::Right => {
+                quote!(egui_commonmark_backend::document::Alignment::Right)
+            }
+        });
 
-                    #content_stream
-                    });
+        let curr_table = self.curr_table;
+        stream.extend(quote!(
+            egui::Frame::group(ui.style()).show(ui, |ui| {
+                let id = ui.id().with("_table").with(#curr_table);
+                options.theme.table_grid(id).show(ui, |ui| {
+                let mut alignment = egui_commonmark_backend::TableAlignment::new(
+                    ui,
+                    id.with("alignment"),
+                    vec![#(#alignments),*],
+                );
+
+                #content_stream
+
+                alignment.finish(ui);
                 });
-            ));
-
-            self.curr_table += 1;
+            });
+        ));
 
-            self.is_table = false;
+        self.curr_table += 1;
 
-            if events.peek().is_none() {
-                self.line.should_end_newline_forced = false;
-            }
+        stream.extend(self.end_line(is_last));
+        stream
+    }
 
-            stream.extend(self.line.try_insert_end());
+    fn inlines(
+        &mut self,
+        inlines: &[Inline],
+        cache: &Expr,
+        options: &CommonMarkOptions,
+    ) -> TokenStream {
+        let mut stream = TokenStream::new();
+        for inline in inlines {
+            stream.extend(self.inline(inline, cache, options));
         }
-
         stream
     }
 
-    fn event(
+    fn inline(
         &mut self,
-        event: pulldown_cmark::Event,
+        inline: &Inline,
         cache: &Expr,
         options: &CommonMarkOptions,
     ) -> TokenStream {
-        match event {
-            pulldown_cmark::Event::Start(tag) => self.start_tag(tag, options),
-            pulldown_cmark::Event::End(tag) => self.end_tag(tag, cache, options),
-            pulldown_cmark::Event::Text(text) => self.event_text(text),
-            pulldown_cmark::Event::Code(text) => {
-                self.text_style.code = true;
-                let s = self.event_text(text);
-                self.text_style.code = false;
-                s
-            }
-            pulldown_cmark::Event::InlineHtml(text) | pulldown_cmark::Event::Html(text) => {
-                self.event_text(text)
-            }
-            pulldown_cmark::Event::FootnoteReference(footnote) => {
-                let footnote = footnote.to_string();
-                quote!(egui_commonmark_backend::footnote_start(ui, #footnote);)
-            }
-            pulldown_cmark::Event::SoftBreak => {
-                quote!(egui_commonmark_backend::soft_break(ui);)
-            }
-            pulldown_cmark::Event::HardBreak => {
-                quote!(egui_commonmark_backend::newline(ui);)
-            }
-            pulldown_cmark::Event::Rule => {
-                let mut stream = TokenStream::new();
-                stream.extend(self.line.try_insert_start());
-
-                let end = self.line.can_insert_end();
-                stream.extend(quote!(egui_commonmark_backend::rule(ui, #end);));
+        match &inline.kind {
+            InlineKind::Text(text) | InlineKind::Html(text) | InlineKind::InlineHtml(text) => {
+                self.text(text)
+            }
+            InlineKind::Code(text) => {
+                let code = std::mem::replace(&mut self.text_style.code, true);
+                let stream = self.text(text);
+                self.text_style.code = code;
                 stream
             }
-            pulldown_cmark::Event::TaskListMarker(checkbox) => {
+            InlineKind::FootnoteReference(footnote) => scroll_to_footnote(
+                cache,
+                quote!(egui_commonmark_backend::footnote_start(ui, #cache, #footnote, &mut scroll_to);),
+            ),
+            InlineKind::SoftBreak => quote!(egui_commonmark_backend::soft_break(ui);),
+            InlineKind::HardBreak => quote!(egui_commonmark_backend::newline(ui);),
+            InlineKind::TaskListMarker(checkbox) => {
                 if options.mutable {
                     // FIXME: Unsupported for now
                     TokenStream::new()
@@ -555,300 +648,141 @@ impl CommonMarkViewerInternal {
                     quote!(ui.add(egui_commonmark_backend::ImmutableCheckbox::without_text(&mut #checkbox));)
                 }
             }
-
-            pulldown_cmark::Event::InlineMath(_) | pulldown_cmark::Event::DisplayMath(_) => {
-                TokenStream::new()
-            }
-        }
-    }
-
-    fn event_text(&mut self, text: CowStr) -> TokenStream {
-        if let Some(image) = &mut self.image {
-            image
-                .alt_text
-                .push(StyledText::new(self.text_style.clone(), text.to_string()));
-        } else if let Some(block) = &mut self.code_block {
-            block.content.push_str(&text);
-        } else if let Some(link) = &mut self.link {
-            link.text
-                .push(StyledText::new(self.text_style.clone(), text.to_string()));
-        } else {
-            let rich_text = self.richtext_tokenstream(&self.text_style.clone(), &text);
-            return quote!(
-                ui.label(#rich_text);
-            );
-        }
-
-        TokenStream::new()
-    }
-
-    fn start_tag(&mut self, tag: pulldown_cmark::Tag, options: &CommonMarkOptions) -> TokenStream {
-        match tag {
-            pulldown_cmark::Tag::Paragraph => self.line.try_insert_start(),
-
-            pulldown_cmark::Tag::Heading { level, .. } => {
-                self.text_style.heading = Some(match level {
-                    HeadingLevel::H1 => 0,
-                    HeadingLevel::H2 => 1,
-                    HeadingLevel::H3 => 2,
-                    HeadingLevel::H4 => 3,
-                    HeadingLevel::H5 => 4,
-                    HeadingLevel::H6 => 5,
-                });
-
-                quote!(egui_commonmark_backend::newline(ui);)
-            }
-            pulldown_cmark::Tag::BlockQuote(_) => {
-                self.is_blockquote = true;
-                TokenStream::new()
-            }
-            pulldown_cmark::Tag::CodeBlock(c) => {
-                match c {
-                    pulldown_cmark::CodeBlockKind::Fenced(lang) => {
-                        self.code_block = Some(CodeBlock {
-                            lang: Some(lang.to_string()),
-                            content: "".to_string(),
-                        });
-                    }
-                    pulldown_cmark::CodeBlockKind::Indented => {
-                        self.code_block = Some(CodeBlock {
-                            lang: None,
-                            content: "".to_string(),
-                        });
-                    }
-                }
-
-                self.line.try_insert_start()
-            }
-
-            pulldown_cmark::Tag::List(point) => {
-                let mut stream = TokenStream::new();
-
-                if !self.list.is_inside_a_list() && self.line.can_insert_start() {
-                    stream.extend(quote!( egui_commonmark_backend::newline(ui);));
-                }
-
-                if let Some(number) = point {
-                    self.list.start_level_with_number(number);
-                } else {
-                    self.list.start_level_without_number();
-                }
-
-                self.line.should_start_newline = false;
-                self.line.should_end_newline = false;
+            InlineKind::InlineMath(_) | InlineKind::DisplayMath(_) => TokenStream::new(),
+            InlineKind::Emphasis(content) => {
+                let emphasis = std::mem::replace(&mut self.text_style.emphasis, true);
+                let stream = self.inlines(content, cache, options);
+                self.text_style.emphasis = emphasis;
                 stream
             }
-            pulldown_cmark::Tag::Item => {
-                self.is_list_item = true;
-                self.list.start_item(options)
-            }
-            pulldown_cmark::Tag::FootnoteDefinition(note) => {
-                let mut stream = self.line.try_insert_start();
-
-                self.line.should_start_newline = false;
-                self.line.should_end_newline = false;
-                let note = note.to_string();
-                stream.extend(quote!(egui_commonmark_backend::footnote(ui, #note);));
+            InlineKind::Strong(content) => {
+                let strong = std::mem::replace(&mut self.text_style.strong, true);
+                let stream = self.inlines(content, cache, options);
+                self.text_style.strong = strong;
                 stream
             }
-            pulldown_cmark::Tag::Table(_) => {
-                self.is_table = true;
-                TokenStream::new()
-            }
-            pulldown_cmark::Tag::TableHead
-            | pulldown_cmark::Tag::TableRow
-            | pulldown_cmark::Tag::TableCell => TokenStream::new(),
-            pulldown_cmark::Tag::Emphasis => {
-                self.text_style.emphasis = true;
-                TokenStream::new()
+            InlineKind::Strikethrough(content) => {
+                let strikethrough = std::mem::replace(&mut self.text_style.strikethrough, true);
+                let stream = self.inlines(content, cache, options);
+                self.text_style.strikethrough = strikethrough;
+                stream
             }
-            pulldown_cmark::Tag::Strong => {
-                self.text_style.strong = true;
-                TokenStream::new()
+            InlineKind::Superscript(content) => {
+                let superscript = std::mem::replace(&mut self.text_style.superscript, true);
+                let stream = self.inlines(content, cache, options);
+                self.text_style.superscript = superscript;
+                stream
             }
-            pulldown_cmark::Tag::Strikethrough => {
-                self.text_style.strikethrough = true;
-                TokenStream::new()
+            InlineKind::Subscript(content) => {
+                let subscript = std::mem::replace(&mut self.text_style.subscript, true);
+                let stream = self.inlines(content, cache, options);
+                self.text_style.subscript = subscript;
+                stream
             }
-            pulldown_cmark::Tag::Link { dest_url, .. } => {
+            InlineKind::Link {
+                destination,
+                title,
+                content,
+                ..
+            } => {
                 self.link = Some(StyledLink {
-                    destination: dest_url.to_string(),
+                    destination: destination.clone(),
+                    title: title.clone(),
                     text: Vec::new(),
                 });
-                TokenStream::new()
+                let mut stream = self.inlines(content, cache, options);
+                if let Some(link) = self.link.take() {
+                    stream.extend(self.link_end(link, cache));
+                }
+                stream
             }
-            pulldown_cmark::Tag::Image { dest_url, .. } => {
-                let tmp = Image::new(&dest_url, options);
+            InlineKind::Image {
+                destination,
+                content,
+                ..
+            } => {
+                let tmp = Image::new(destination, options);
                 self.image = Some(StyledImage {
                     uri: tmp.uri,
                     alt_text: Vec::new(),
                 });
-
-                TokenStream::new()
-            }
-            pulldown_cmark::Tag::HtmlBlock | pulldown_cmark::Tag::MetadataBlock(_) => {
-                self.line.try_insert_start()
-            }
-            pulldown_cmark::Tag::DefinitionList => {
-                let s = self.line.try_insert_start();
-                self.def_list.is_first_item = true;
-                s
-            }
-            pulldown_cmark::Tag::DefinitionListTitle => {
-                if !self.def_list.is_first_item {
-                    self.line.try_insert_start()
-                } else {
-                    self.def_list.is_first_item = false;
-                    TokenStream::new()
+                let mut stream = self.inlines(content, cache, options);
+                if let Some(image) = self.image.take() {
+                    stream.extend(self.image_end(image, options));
                 }
+                stream
             }
-            pulldown_cmark::Tag::DefinitionListDefinition => {
-                self.def_list.is_def_list_def = true;
-                TokenStream::new()
-            }
-            pulldown_cmark::Tag::Superscript | pulldown_cmark::Tag::Subscript => TokenStream::new(),
         }
     }
 
-    fn end_tag(
-        &mut self,
-        tag: pulldown_cmark::TagEnd,
-        cache: &Expr,
-        options: &CommonMarkOptions,
-    ) -> TokenStream {
-        match tag {
-            pulldown_cmark::TagEnd::Paragraph => self.line.try_insert_end(),
-            pulldown_cmark::TagEnd::Heading { .. } => {
-                self.text_style.heading = None;
-                self.line.try_insert_end()
-            }
-            pulldown_cmark::TagEnd::BlockQuote(_) => TokenStream::new(),
-            pulldown_cmark::TagEnd::CodeBlock => self.end_code_block(cache),
-            pulldown_cmark::TagEnd::List(_) => {
-                if self.list.is_last_level() {
-                    self.line.should_start_newline = true;
-                    self.line.should_end_newline = true;
-                }
-
-                let s = self.list.end_level(self.line.can_insert_end());
+    fn text(&mut self, text: &str) -> TokenStream {
+        if let Some(image) = &mut self.image {
+            image
+                .alt_text
+                .push(StyledText::new(self.text_style.clone(), text));
+        } else if let Some(link) = &mut self.link {
+            link.text
+                .push(StyledText::new(self.text_style.clone(), text));
+        } else {
+            let rich_text = self.richtext_tokenstream(&self.text_style.clone(), text);
+            return quote!(
+                ui.label(#rich_text);
+            );
+        }
 
-                if !self.list.is_inside_a_list() {
-                    // Reset all the state and make it ready for the next list that occurs
-                    self.list = List::default();
-                }
+        TokenStream::new()
+    }
 
-                s
-            }
-            pulldown_cmark::TagEnd::FootnoteDefinition => {
-                self.line.should_start_newline = true;
-                self.line.should_end_newline = true;
-                self.line.try_insert_end()
-            }
-            pulldown_cmark::TagEnd::Item
-            | pulldown_cmark::TagEnd::Table
-            | pulldown_cmark::TagEnd::TableHead
-            | pulldown_cmark::TagEnd::TableRow => TokenStream::new(),
-            pulldown_cmark::TagEnd::TableCell => {
-                // Ensure space between cells
-                quote!(ui.label("  ");)
-            }
-            pulldown_cmark::TagEnd::Emphasis => {
-                self.text_style.emphasis = false;
-                TokenStream::new()
-            }
-            pulldown_cmark::TagEnd::Strong => {
-                self.text_style.strong = false;
-                TokenStream::new()
-            }
-            pulldown_cmark::TagEnd::Strikethrough => {
-                self.text_style.strikethrough = false;
-                TokenStream::new()
-            }
-            pulldown_cmark::TagEnd::Link => {
-                if let Some(link) = self.link.take() {
-                    let StyledLink { destination, text } = link;
-                    // When a link wraps an image (`[![alt](img)](url)`), text is empty.
-                    // Emitting Link::end with empty text resets the layout cursor to x=0,
-                    // superimposing subsequent elements on the image just drawn.
-                    if text.is_empty() {
-                        TokenStream::new()
-                    } else {
-                        let mut text_stream = TokenStream::new();
-                        for text_style in text {
-                            text_stream.extend(
-                                self.richtext_tokenstream(&text_style.style, &text_style.text),
-                            );
-                            text_stream.extend(quote!(,));
-                        }
-                        quote!(
-                        egui_commonmark_backend::Link {
-                            destination: #destination.to_owned(),
-                            text: vec![#text_stream]
-                        }.end(ui, #cache, &options, &mut None);)
-                    }
-                } else {
-                    TokenStream::new()
-                }
-            }
-            pulldown_cmark::TagEnd::Image => {
-                let mut stream = TokenStream::new();
-                if let Some(image) = self.image.take() {
-                    // FIXME: Try to reduce code duplication here
-                    let StyledImage { uri, alt_text } = image;
-
-                    stream.extend(quote!(
-                    let response = ui.add(
-                        egui::Image::from_uri(#uri)
-                            .fit_to_original_size(1.0)
-                            .max_width(max_width)
-                    );
-                    ));
-
-                    if !alt_text.is_empty() && options.show_alt_text_on_hover {
-                        let mut alt_text_stream = TokenStream::new();
-                        for alt in alt_text {
-                            let text = self.richtext_tokenstream(&alt.style, &alt.text);
-                            alt_text_stream.extend(quote!(ui.label(#text);));
-                        }
+    fn link_end(&mut self, link: StyledLink, cache: &Expr) -> TokenStream {
+        let StyledLink {
+            destination,
+            title,
+            text,
+        } = link;
+        // When a link wraps an image (`[![alt](img)](url)`), text is empty.
+        // Emitting Link::end with empty text resets the layout cursor to x=0,
+        // superimposing subsequent elements on the image just drawn.
+        if text.is_empty() {
+            return TokenStream::new();
+        }
 
-                        stream.extend(quote!(
-                        response.on_hover_ui_at_pointer(|ui| {
-                            #alt_text_stream
-                        });));
-                    }
-                }
-                stream
-            }
-            pulldown_cmark::TagEnd::HtmlBlock | pulldown_cmark::TagEnd::MetadataBlock(_) => {
-                TokenStream::new()
-            }
-            pulldown_cmark::TagEnd::DefinitionList => self.line.try_insert_end(),
-            pulldown_cmark::TagEnd::DefinitionListTitle => TokenStream::new(),
-            pulldown_cmark::TagEnd::DefinitionListDefinition => TokenStream::new(),
-            pulldown_cmark::TagEnd::Superscript | pulldown_cmark::TagEnd::Subscript => {
-                TokenStream::new()
-            }
+        let mut text_stream = TokenStream::new();
+        for text_style in text {
+            text_stream.extend(self.richtext_tokenstream(&text_style.style, &text_style.text));
+            text_stream.extend(quote!(,));
         }
+        quote!(
+        egui_commonmark_backend::Link {
+            destination: #destination.to_owned(),
+            title: #title.to_owned(),
+            text: vec![#text_stream]
+        }.end(ui, #cache, &options, &mut None);)
     }
 
-    fn end_code_block(&mut self, cache: &Expr) -> TokenStream {
-        let mut stream = TokenStream::new();
-        if let Some(block) = self.code_block.take() {
-            let content = block.content;
+    fn image_end(&mut self, image: StyledImage, options: &CommonMarkOptions) -> TokenStream {
+        // FIXME: Try to reduce code duplication here
+        let StyledImage { uri, alt_text } = image;
 
-            stream.extend(if let Some(lang) = block.lang {
-                quote!(egui_commonmark_backend::CodeBlock {
-                    lang: Some(#lang.to_owned()), content: #content.to_owned()}
-                    .end(ui, #cache, &options, max_width);)
-            } else {
-                quote!(egui_commonmark_backend::CodeBlock {
-                    lang: None, content: #content.to_owned()}
-                    .end(ui, #cache, &options, max_width);)
-            });
+        let mut stream = quote!(
+        let response = ui.add(
+            egui::Image::from_uri(#uri)
+                .fit_to_original_size(1.0)
+                .max_width(max_width)
+        );
+        );
 
-            stream.extend(self.line.try_insert_end());
-        }
+        if !alt_text.is_empty() && options.show_alt_text_on_hover {
+            let mut alt_text_stream = TokenStream::new();
+            for alt in alt_text {
+                let text = self.richtext_tokenstream(&alt.style, &alt.text);
+                alt_text_stream.extend(quote!(ui.label(#text);));
+            }
 
+            stream.extend(quote!(
+            response.on_hover_ui_at_pointer(|ui| {
+                #alt_text_stream
+            });));
+        }
         stream
     }
 
@@ -856,42 +790,51 @@ impl CommonMarkViewerInternal {
         // Try to write a compact stream
 
         let mut stream = TokenStream::new();
+        stream.extend(quote!(egui::RichText::new(#text)));
+
         if let Some(level) = s.heading {
-            stream.extend(quote!(egui::RichText::new(#text)));
+            let heading = self.theme.heading(level).clone();
+            if heading.is_text_style_heading(level) {
+                // The size is set by `.heading()` below
+            } else if let Some(size) = heading.size {
+                stream.extend(quote!(.size(#size)));
+            } else {
+                self.dumps_heading = true;
+                let scale = heading.scale;
+                stream.extend(quote!(.size(min_height + diff * #scale)));
+            }
+
+            if let Some(family) = heading.family {
+                let family = match family {
+                    egui::FontFamily::Proportional => quote!(egui::FontFamily::Proportional),
+                    egui::FontFamily::Monospace => quote!(egui::FontFamily::Monospace),
+                    egui::FontFamily::Name(name) => {
+                        let name = name.to_string();
+                        quote!(egui::FontFamily::Name(#name.into()))
+                    }
+                };
+                stream.extend(quote!(.family(#family)));
+            } else if level == 0 {
+                stream.extend(quote!(.heading()));
+            }
 
-            match level {
-                0 => {
-                    // no dumps_heading here as it does not depend on min_height and diff
-                    stream.extend(quote!(.strong().heading()));
-                }
-                1 => {
-                    self.dumps_heading = true;
-                    stream.extend(quote!(.strong().size(min_height + diff * 0.835)));
-                }
-                2 => {
-                    self.dumps_heading = true;
-                    stream.extend(quote!(.strong().size(min_height + diff * 0.668)));
-                }
-                3 => {
-                    self.dumps_heading = true;
-                    stream.extend(quote!(.strong().size(min_height + diff * 0.501)));
-                }
-                4 => {
-                    self.dumps_heading = true;
-                    stream.extend(quote!(.size(min_height + diff * 0.334)));
-                }
-                // We only support 6 levels
-                5.. => {
-                    self.dumps_heading = true;
-                    stream.extend(quote!(.size(min_height + diff * 0.167)));
-                }
+            if let Some(color) = heading.color {
+                let color = color_tokenstream(color);
+                stream.extend(quote!(.color(#color)));
+            }
+
+            if heading.strong {
+                stream.extend(quote!(.strong()));
             }
-        } else {
-            stream.extend(quote!(egui::RichText::new(#text)));
         }
 
         if s.quote {
-            stream.extend(quote!(.weak()));
+            if let Some(color) = self.theme.quote_color {
+                let color = color_tokenstream(color);
+                stream.extend(quote!(.color(#color)));
+            } else {
+                stream.extend(quote!(.weak()));
+            }
         }
 
         if s.strong {
@@ -908,12 +851,43 @@ impl CommonMarkViewerInternal {
 
         if s.code {
             stream.extend(quote!(.code()));
+            if let Some(color) = self.theme.code_background {
+                let color = color_tokenstream(color);
+                stream.extend(quote!(.background_color(#color)));
+            }
+        }
+
+        if s.superscript {
+            stream.extend(quote!(.small_raised().line_height(Some(
+                ui.text_style_height(&egui::TextStyle::Body)
+            ))));
+        } else if s.subscript {
+            stream.extend(quote!(
+                .small().line_height(Some(ui.text_style_height(&egui::TextStyle::Body)))
+            ));
         }
 
         stream
     }
 }
 
+fn color_tokenstream(color: egui::Color32) -> TokenStream {
+    let [r, g, b, a] = color.to_array();
+    quote!(egui::Color32::from_rgba_premultiplied(#r, #g, #b, #a))
+}
+
+/// Wraps a footnote element that may request a scroll target. There is no deferral like in
+/// the viewer, so the target is set on the cache right away.
+fn scroll_to_footnote(cache: &Expr, element: TokenStream) -> TokenStream {
+    quote!({
+        let mut scroll_to = None;
+        #element
+        if scroll_to.is_some() {
+            *(#cache).scroll_to_id_target_mut() = scroll_to;
+        }
+    })
+}
+
 fn dump_heading_heights() -> TokenStream {
     quote!(
     let max_height = ui
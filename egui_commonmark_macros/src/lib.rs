@@ -58,6 +58,23 @@
 //! offer customization. This is something that will be addressed eventually once
 //! a good API has been chosen.
 //!
+//! The markdown is always shown with the default `CommonMarkTheme`, as the colors and
+//! sizes of the theme are written into the generated code.
+//!
+//! Like in the viewer superscript (`^text^`) and subscript (`~text~`) change how
+//! existing markdown is shown, as subscript turns `~text~` strikethrough into
+//! subscript. They must be enabled explicitly with trailing `superscript = true` and
+//! `subscript = true` arguments:
+//!
+//! ```
+//! # use egui_commonmark_backend::CommonMarkCache;
+//! # use egui_commonmark_macros::commonmark;
+//! # egui::__run_test_ui(|ui| {
+//! let mut cache = CommonMarkCache::default();
+//! commonmark!(ui, &mut cache, "x^2^ + H~2~O", superscript = true, subscript = true);
+//! # });
+//! ```
+//!
 //! ## What this crate is not
 //!
 //! This crate does not have as a goal to make widgets that can be interacted with
@@ -78,12 +95,14 @@ use generator::*;
 
 use quote::quote_spanned;
 use syn::parse::{Parse, ParseStream, Result};
-use syn::{Expr, LitStr, Token, parse_macro_input};
+use syn::{Expr, Ident, LitBool, LitStr, Token, parse_macro_input};
 
 struct Parameters {
     ui: Expr,
     cache: Expr,
     markdown: LitStr,
+    superscript: bool,
+    subscript: bool,
 }
 
 impl Parse for Parameters {
@@ -94,16 +113,39 @@ impl Parse for Parameters {
         input.parse::<Token![,]>()?;
         let markdown: LitStr = input.parse()?;
 
+        let mut superscript = false;
+        let mut subscript = false;
+        while input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let option: Ident = input.parse()?;
+            let value = if option == "superscript" {
+                &mut superscript
+            } else if option == "subscript" {
+                &mut subscript
+            } else {
+                return Err(syn::Error::new(option.span(), "Unknown option"));
+            };
+            input.parse::<Token![=]>()?;
+            *value = input.parse::<LitBool>()?.value;
+        }
+
         Ok(Parameters {
             ui,
             cache,
             markdown,
+            superscript,
+            subscript,
         })
     }
 }
 
-fn commonmark_impl(ui: Expr, cache: Expr, text: String) -> proc_macro2::TokenStream {
-    let stream = CommonMarkViewerInternal::new().show(ui, cache, &text);
+fn commonmark_impl(
+    ui: Expr,
+    cache: Expr,
+    text: String,
+    superscript: bool,
+    subscript: bool,
+) -> proc_macro2::TokenStream {
+    let stream = CommonMarkViewerInternal::new(superscript, subscript).show(ui, cache, &text);
 
     #[cfg(feature = "dump-macro")]
     {
@@ -124,9 +166,11 @@ pub fn commonmark(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         ui,
         cache,
         markdown,
+        superscript,
+        subscript,
     } = parse_macro_input!(input as Parameters);
 
-    commonmark_impl(ui, cache, markdown.value()).into()
+    commonmark_impl(ui, cache, markdown.value(), superscript, subscript).into()
 }
 
 #[proc_macro]
@@ -135,6 +179,8 @@ pub fn commonmark_str(input: proc_macro::TokenStream) -> proc_macro::TokenStream
         ui,
         cache,
         markdown,
+        superscript,
+        subscript,
     } = parse_macro_input!(input as Parameters);
 
     let path = markdown.value();
@@ -152,7 +198,7 @@ pub fn commonmark_str(input: proc_macro::TokenStream) -> proc_macro::TokenStream
         .into();
     };
 
-    commonmark_impl(ui, cache, md).into()
+    commonmark_impl(ui, cache, md, superscript, subscript).into()
 }
 
 fn resolve_backend_crate_import() -> proc_macro2::TokenStream {
@@ -0,0 +1,9 @@
+use egui::__run_test_ui;
+use egui_commonmark_macros::commonmark;
+
+fn main() {
+    __run_test_ui(|ui| {
+        let mut cache = egui_commonmark_backend::CommonMarkCache::default();
+        commonmark!(ui, &mut cache, "H~2~O", math = true);
+    });
+}
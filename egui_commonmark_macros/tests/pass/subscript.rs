@@ -0,0 +1,17 @@
+use egui::__run_test_ui;
+use egui_commonmark_macros::{commonmark, commonmark_str};
+
+// Subscript is opt-in and the trailing option may be followed by a comma
+fn main() {
+    __run_test_ui(|ui| {
+        let mut cache = egui_commonmark_backend::CommonMarkCache::default();
+        commonmark!(ui, &mut cache, "H~2~O", subscript = true);
+        commonmark!(ui, &mut cache, "~strikethrough~", subscript = false,);
+        commonmark_str!(
+            ui,
+            &mut cache,
+            "../../../../egui_commonmark_macros/tests/file.md",
+            subscript = true
+        );
+    });
+}
@@ -0,0 +1,24 @@
+use egui::__run_test_ui;
+use egui_commonmark_macros::{commonmark, commonmark_str};
+
+// Superscript is opt-in and can be combined with subscript in any order
+fn main() {
+    __run_test_ui(|ui| {
+        let mut cache = egui_commonmark_backend::CommonMarkCache::default();
+        commonmark!(ui, &mut cache, "x^2^", superscript = true);
+        commonmark!(ui, &mut cache, "x^2^", superscript = false,);
+        commonmark!(
+            ui,
+            &mut cache,
+            "x^2^ + H~2~O",
+            subscript = true,
+            superscript = true
+        );
+        commonmark_str!(
+            ui,
+            &mut cache,
+            "../../../../egui_commonmark_macros/tests/file.md",
+            superscript = true
+        );
+    });
+}